@@ -0,0 +1,132 @@
+// Hands out string buffers for the strings made while evaluating expressions, so
+// string-heavy loops don't need a new allocation for every concatenation.
+//
+// Values own their strings, so buffers can't be borrowed from one big block. Instead
+// buffers that are no longer needed (like the operands of '++' and the values of
+// statements that are thrown away) are given back and reused for later strings.
+// The Interpreter empties the arena between top-level statements.
+
+// How many free buffers are kept. Any more are just dropped
+const MAX_FREE: usize = 64;
+
+
+#[derive(Debug, Default)]
+pub struct StringArena {
+    free: Vec<String>,
+}
+
+impl StringArena {
+    pub fn new() -> StringArena {
+        StringArena::default()
+    }
+
+    // An empty string that can hold at least len bytes without growing
+    pub fn alloc(&mut self, len: usize) -> String {
+        match self.free.iter().position(|buf| buf.capacity() >= len) {
+            Some(i) => self.free.swap_remove(i),
+            // Rounding up leaves room for the string to grow, like 's = s ++ "x"' in a loop
+            None => String::with_capacity(len.next_power_of_two()),
+        }
+    }
+
+    // Gives a string back so its buffer can be reused
+    pub fn recycle(&mut self, mut buf: String) {
+        if buf.capacity() > 0 && self.free.len() < MAX_FREE {
+            buf.clear();
+            self.free.push(buf);
+        }
+    }
+
+    // Frees every buffer the arena is holding on to
+    pub fn reset(&mut self) {
+        self.free.clear();
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+    use crate::interpreter::{Interpreter, Value, Visit};
+    use crate::lexer::{lex_source, Token, TokenType};
+    use crate::parser::Parser;
+
+    // Counts the allocations made on each thread, so tests running in parallel don't
+    // throw off each other's counts
+    struct CountingAllocator;
+
+    thread_local! {
+        static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    fn count_allocation() {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            count_allocation();
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            count_allocation();
+            System.realloc(ptr, layout, new_size)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    // Runs the source and returns the value of s along with how many allocations it took
+    fn run_counting(mut interpreter: Interpreter, source: &str) -> (Value, usize) {
+        let statements = Parser::new(lex_source(source).unwrap()).parse().unwrap();
+        let mut env = interpreter.globals.clone();
+        let before = ALLOCATIONS.with(|count| count.get());
+        for statement in statements {
+            statement.evaluate(&mut interpreter, &mut env).unwrap();
+        }
+        let allocations = ALLOCATIONS.with(|count| count.get()) - before;
+        let s = env.get(&Token::new(TokenType::Identifier, "s".to_string(), 1)).unwrap();
+        (s, allocations)
+    }
+
+    #[test]
+    fn buffers_are_reused() {
+        let mut arena = StringArena::new();
+        let buf = arena.alloc(5);
+        assert_eq!(8, buf.capacity());
+        let ptr = buf.as_ptr();
+        arena.recycle(buf);
+        let buf = arena.alloc(3);
+        assert_eq!(ptr, buf.as_ptr());
+        assert!(buf.is_empty());
+        // Too small buffers aren't handed out
+        arena.recycle(buf);
+        assert!(arena.alloc(20).capacity() >= 20);
+        arena.reset();
+        assert!(arena.free.is_empty());
+    }
+
+    #[test]
+    fn concatenation_loop_allocates_less() {
+        let source = "let s = \"\";\nlet i = 0;\nwhile i < 300 {\n    s = s ++ \"ab\";\n    i = i + 1;\n}";
+        let (owned, owned_allocations) = run_counting(Interpreter::new(), source);
+
+        let mut interpreter = Interpreter::new();
+        interpreter.arena = Some(StringArena::new());
+        let (arena, arena_allocations) = run_counting(interpreter, source);
+
+        assert_eq!(Value::STRING("ab".repeat(300)), owned);
+        assert_eq!(owned, arena);
+        assert!(arena_allocations < owned_allocations, "{} allocations with the arena, {} without", arena_allocations, owned_allocations);
+    }
+}
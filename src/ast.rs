@@ -1,4 +1,6 @@
-use crate::lexer::{Token};
+use crate::lexer::{Token, TokenType};
+use crate::interpreter::Value;
+use std::cell::{Cell, OnceCell};
 use std::fmt;
 use std::fmt::{ Display };
 
@@ -11,16 +13,28 @@ use std::fmt::{ Display };
 /// 1) PrintStmt: evaluates an expression and prints it to the console (TEMPORARY)
 /// 2) ExprStmt: evaluates an expression
 /// 3) VarDecl: Variable declaration
-/// 4) Block: Block statement 
+/// 4) ConstDecl: Constant declaration, which can not be reassigned
+/// 5) Block: Block statement 
+/// 6) Import: Runs another Flax file in the current environment
+/// 7) TryStmt: Runs a block, handing any runtime error to a catch block
+/// 8) Throw: Raises a value as a runtime error
 #[derive(Debug, PartialEq, Clone)]
 pub enum Stmt {
     ExprStmt(Expr),
     IfStmt(Box<IfStatement>),
     VarDecl(Token, Option<Expr>),
+    ConstDecl(Token, Expr),
+    // 'let x, y = f();' binds the elements of an array. The token is the '='
+    Destructure(Vec<Token>, Token, Expr),
     Block(Box<Vec<Stmt>>),
-    WhileStmt(Expr, Box<Stmt>),
+    WhileStmt(Token, Expr, Box<Stmt>),
     FuncStmt(Box<Function>),
     ReturnStmt(Box<Return>),
+    Import(Token),
+    TryStmt(Box<TryCatch>),
+    Throw(Token, Expr),
+    // 'defer stmt;' runs the statement when the enclosing block exits. The token is the 'defer'
+    Defer(Token, Box<Stmt>),
     Break,
 }
 
@@ -33,8 +47,8 @@ impl Stmt {
         Stmt::IfStmt(Box::new(IfStatement { conditional, then_block, else_block }))  
     }
     
-    pub fn new_while(condition: Expr, block: Stmt) -> Stmt {
-        Stmt::WhileStmt(condition, Box::new(block))
+    pub fn new_while(tok: Token, condition: Expr, block: Stmt) -> Stmt {
+        Stmt::WhileStmt(tok, condition, Box::new(block))
     }
 
     pub fn new_function(name: Token, params: Vec<Token>, body: Stmt) -> Stmt {
@@ -44,6 +58,10 @@ impl Stmt {
     pub fn new_return(tok: Token, expr: Option<Expr>) -> Stmt {
         Stmt::ReturnStmt(Box::new(Return { tok, expr }))
     }
+
+    pub fn new_try(try_block: Stmt, name: Token, catch_block: Stmt) -> Stmt {
+        Stmt::TryStmt(Box::new(TryCatch { try_block, name, catch_block }))
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -60,6 +78,13 @@ pub struct Function {
     pub body: Stmt,
 }
 
+#[derive(PartialEq, Debug, Clone)]
+pub struct TryCatch {
+    pub try_block: Stmt,
+    pub name: Token,
+    pub catch_block: Stmt,
+}
+
 #[derive(PartialEq, Debug, Clone)]
 pub struct Return {
     pub tok: Token,
@@ -79,15 +104,23 @@ pub enum Expr {
     B(Box<Binary>),
     G(Box<Grouping>),
     C(Box<Conditional>),
-    V(Token),
-    A(Token, Box<Expr>),
+    V(Token, Depth),
+    A(Token, Box<Expr>, Depth),
     Log(Box<Logical>),
     Cal(Box<Call>),
+    Arr(Box<ArrayLiteral>),
+    Map(Box<MapLiteral>),
+    Lambda(Box<Function>),
+    Block(Vec<Stmt>),
+    Method(Box<MethodCall>),
+    // '...xs' in an array literal or argument list, the token is the '...'
+    Spread(Token, Box<Expr>),
+    Match(Box<Match>),
 }
 
 impl Expr {
-    pub fn new_literal(val: String) -> Expr {
-        Expr::L(Literal::new(val))
+    pub fn new_literal(val: String, token_type: TokenType, line: u64) -> Expr {
+        Expr::L(Literal::new(val, token_type, line))
     }
 
     pub fn new_unary(op: Token, expr: Expr) -> Expr {
@@ -98,16 +131,16 @@ impl Expr {
         Expr::B(Box::new(Binary::new(op, left, right)))
     }
 
-    pub fn new_grouping(expr: Expr) -> Expr {
-        Expr::G(Box::new(Grouping::new(expr)))
+    pub fn new_grouping(expr: Expr, line: u64) -> Expr {
+        Expr::G(Box::new(Grouping::new(expr, line)))
     }
 
     pub fn new_variable(token: Token) -> Expr {
-        Expr::V(token)
+        Expr::V(token, Depth::default())
     }
 
     pub fn new_assignment(token: Token, expr: Expr) -> Expr {
-        Expr::A(token, Box::new(expr))
+        Expr::A(token, Box::new(expr), Depth::default())
     }
 
     pub fn new_conditional(conditional: Expr, then_expr: Expr, else_expr: Expr, line: u64) -> Expr {
@@ -121,17 +154,69 @@ impl Expr {
     pub fn new_call(callee: Expr, tok: Token, args: Vec<Expr>) -> Expr {
         Expr::Cal(Box::new(Call { callee, tok, args }))
     }
+
+    pub fn new_array(tok: Token, elements: Vec<Expr>) -> Expr {
+        Expr::Arr(Box::new(ArrayLiteral { tok, elements }))
+    }
+
+    pub fn new_map(tok: Token, entries: Vec<(Expr, Expr)>) -> Expr {
+        Expr::Map(Box::new(MapLiteral { tok, entries }))
+    }
+
+    // Lambdas are functions without a name. The name token is the 'func' keyword
+    // with an '<anonymous>' lexeme so errors and introspection have something to show
+    pub fn new_lambda(func_tok: Token, params: Vec<Token>, body: Stmt) -> Expr {
+        let name = Token::new(func_tok.token_type, String::from("<anonymous>"), func_tok.line);
+        Expr::Lambda(Box::new(Function { name, params, body }))
+    }
+
+    // The line the expression is on, taken from its operator or first token. Blocks
+    // have no token of their own
+    pub fn line(&self) -> Option<u64> {
+        match self {
+            Expr::L(lit) => Some(lit.line),
+            Expr::U(unary) => Some(unary.operator.line),
+            Expr::B(binary) => Some(binary.operator.line),
+            Expr::G(grouping) => Some(grouping.line),
+            Expr::C(cond) => cond.cond.line().or(Some(cond.line_num)),
+            Expr::V(tok, _) | Expr::A(tok, _, _) | Expr::Spread(tok, _) => Some(tok.line),
+            Expr::Log(logical) => Some(logical.tok.line),
+            Expr::Cal(call) => call.callee.line().or(Some(call.tok.line)),
+            Expr::Method(method) => Some(method.name.line),
+            Expr::Arr(arr) => Some(arr.tok.line),
+            Expr::Map(map) => Some(map.tok.line),
+            Expr::Lambda(func) => Some(func.name.line),
+            Expr::Match(m) => Some(m.tok.line),
+            Expr::Block(_) => None,
+        }
+    }
+
+    pub fn new_method(receiver: Expr, name: Token, args: Vec<Expr>, paren: Token) -> Expr {
+        Expr::Method(Box::new(MethodCall { receiver, name, args, paren }))
+    }
+
+    pub fn new_match(tok: Token, value: Expr, arms: Vec<(Token, Expr)>) -> Expr {
+        Expr::Match(Box::new(Match { tok, value, arms }))
+    }
+
+    // A block used as an expression. It evaluates to the value of its last statement
+    // when that is an expression statement, and to nil otherwise
+    pub fn new_block(statements: Vec<Stmt>) -> Expr {
+        Expr::Block(statements)
+    }
 }
 
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Grouping {
-    pub expr: Expr
+    pub expr: Expr,
+    // The line of the '('
+    pub line: u64,
 }
 
 impl Grouping {
-    pub fn new(expr: Expr) -> Grouping {
-        Grouping { expr }
+    pub fn new(expr: Expr, line: u64) -> Grouping {
+        Grouping { expr, line }
     }
 }
 
@@ -154,14 +239,54 @@ impl Binary {
 
 
 
-#[derive(Debug, PartialEq, Clone)]
+/// A Literal keeps the raw text of its token along with the token's type, so
+/// `"1"` and `1` stay distinct and numbers can be parsed into the right kind.
+/// A number is parsed the first time it is evaluated and kept in `cache`, so a
+/// literal in a loop body isn't parsed again on every iteration
+#[derive(Debug, Clone)]
 pub struct Literal {
-    pub val: String
+    pub val: String,
+    pub token_type: TokenType,
+    pub line: u64,
+    pub cache: OnceCell<Box<Value>>,
 }
 
 impl Literal {
-    pub fn new(val: String) -> Literal {
-        Literal { val }
+    pub fn new(val: String, token_type: TokenType, line: u64) -> Literal {
+        Literal { val, token_type, line, cache: OnceCell::new() }
+    }
+}
+
+// Where the resolver found a variable, filled in after parsing (see resolver.rs)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resolution {
+    // Looked up by name at runtime, like code that was never resolved
+    Unresolved,
+    // Declared this many scopes out from where it's used
+    Local(usize),
+    Global,
+}
+
+#[derive(Debug, Clone)]
+pub struct Depth(pub Cell<Resolution>);
+
+impl Default for Depth {
+    fn default() -> Depth {
+        Depth(Cell::new(Resolution::Unresolved))
+    }
+}
+
+// Resolving a variable doesn't change what it is
+impl PartialEq for Depth {
+    fn eq(&self, _other: &Depth) -> bool {
+        true
+    }
+}
+
+// Whether a literal has been evaluated yet doesn't change what it is
+impl PartialEq for Literal {
+    fn eq(&self, other: &Literal) -> bool {
+        self.val == other.val && self.token_type == other.token_type && self.line == other.line
     }
 }
 
@@ -214,6 +339,42 @@ pub struct Call {
 
 
 
+// A call like '"abc".upper()'. The method is looked up from the type of the receiver
+#[derive(Debug, PartialEq, Clone)]
+pub struct MethodCall {
+    pub receiver: Expr,
+    pub name: Token,
+    pub args: Vec<Expr>,
+    pub paren: Token,
+}
+
+
+// The types a match arm can name. 'number' matches both kinds of number and '_' matches anything
+pub const MATCH_PATTERNS: [&str; 10] = ["bool", "string", "number", "int", "nil", "function", "array", "map", "bytes", "_"];
+
+// 'match value { number => ..., _ => ... }'. Each arm is the type it matches and its
+// value, only the first arm matching the value's type is evaluated
+#[derive(Debug, PartialEq, Clone)]
+pub struct Match {
+    pub tok: Token,
+    pub value: Expr,
+    pub arms: Vec<(Token, Expr)>,
+}
+
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ArrayLiteral {
+    pub tok: Token,
+    pub elements: Vec<Expr>,
+}
+
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct MapLiteral {
+    pub tok: Token,
+    pub entries: Vec<(Expr, Expr)>,
+}
+
 
 
 // Implement Display for each struct in the Abstract Syntax Tree so we can debug the tree if needed
@@ -224,11 +385,30 @@ impl Display for Expr {
             Expr::U(ur) => write!(f, "{}", ur),
             Expr::B(bi) => write!(f, "{}", bi),
             Expr::G(grp) => write!(f, "{}", grp),
-            Expr::V(tok) => write!(f, "{}", tok.lexeme),
-            Expr::A(_, expr) => write!(f, "{}", expr),
+            Expr::V(tok, _) => write!(f, "{}", tok.lexeme),
+            Expr::A(_, expr, _) => write!(f, "{}", expr),
             Expr::C(cond) => write!(f, "{}", cond),
             Expr::Log(logical) => write!(f, "{}", logical),
-            Expr::Cal(_) => write!(f, "{}", self)
+            Expr::Cal(_) => write!(f, "{}", self),
+            Expr::Arr(arr) => write!(f, "{}", arr),
+            Expr::Map(map) => write!(f, "{}", map),
+            Expr::Lambda(func) => {
+                let params: Vec<&str> = func.params.iter().map(|param| &param.lexeme[..]).collect();
+                write!(f, "(lambda ({}))", params.join(" "))
+            },
+            Expr::Method(method) => {
+                let args: Vec<String> = method.args.iter().map(|arg| arg.to_string()).collect();
+                write!(f, "(.{} {} ({}))", method.name.lexeme, method.receiver, args.join(" "))
+            },
+            Expr::Block(statements) => {
+                let statements: Vec<String> = statements.iter().map(|stmt| stmt.to_string()).collect();
+                write!(f, "(block {})", statements.join(" "))
+            },
+            Expr::Spread(_, expr) => write!(f, "(... {})", expr),
+            Expr::Match(m) => {
+                let arms: Vec<String> = m.arms.iter().map(|(pattern, value)| format!("({} {})", pattern.lexeme, value)).collect();
+                write!(f, "(match {} {})", m.value, arms.join(" "))
+            },
         }
     }
 }
@@ -241,7 +421,7 @@ impl Display for Grouping {
 
 impl Display for Binary {
     fn fmt(&self, f: &mut fmt::Formatter<>) -> fmt::Result {
-        write!(f, "({} {} {})", self.operator, self.left, self.right)
+        write!(f, "('{}' {} {})", self.operator.lexeme, self.left, self.right)
     }
 }
 
@@ -253,7 +433,7 @@ impl Display for Literal {
 
 impl Display for Unary {
     fn fmt(&self, f: &mut fmt::Formatter<>) -> fmt::Result {
-        write!(f, "({} {})", self.operator, self.expr)
+        write!(f, "('{}' {})", self.operator.lexeme, self.expr)
     }
 }
 
@@ -275,6 +455,20 @@ impl Display for Call {
     }
 }
 
+impl Display for ArrayLiteral {
+    fn fmt(&self, f: &mut fmt::Formatter<>) -> fmt::Result {
+        let elements: Vec<String> = self.elements.iter().map(|e| e.to_string()).collect();
+        write!(f, "[{}]", elements.join(" "))
+    }
+}
+
+impl Display for MapLiteral {
+    fn fmt(&self, f: &mut fmt::Formatter<>) -> fmt::Result {
+        let entries: Vec<String> = self.entries.iter().map(|(k, v)| format!("({} {})", k, v)).collect();
+        write!(f, "{{{}}}", entries.join(" "))
+    }
+}
+
 impl Display for Stmt {
     fn fmt(&self, f: &mut fmt::Formatter<>) -> fmt::Result {
         match self {
@@ -285,12 +479,21 @@ impl Display for Stmt {
                     None => write!(f, "({})", name.lexeme),
                 }
             },
+            Stmt::ConstDecl(name, expr) => write!(f, "(const {} = {})", name.lexeme, expr),
+            Stmt::Destructure(names, _, expr) => {
+                let names: Vec<&str> = names.iter().map(|name| &name.lexeme[..]).collect();
+                write!(f, "({} = {})", names.join(", "), expr)
+            },
             Stmt::Block(_) => write!(f, "Placeholder for block"),
             Stmt::IfStmt(_) => write!(f, "Placeholder for block"),
-            Stmt::WhileStmt(_,_) => write!(f, "Placeholder for while"),
+            Stmt::WhileStmt(_, _, _) => write!(f, "Placeholder for while"),
             Stmt::Break => write!(f, "Placeholder for while"),
             Stmt::FuncStmt(_) => write!(f, "Placeholder for func stmt"),
             Stmt::ReturnStmt(_) => write!(f, "Placeholder for return stmt"),
+            Stmt::Import(path) => write!(f, "(import {})", path.lexeme),
+            Stmt::TryStmt(_) => write!(f, "Placeholder for try stmt"),
+            Stmt::Throw(_, expr) => write!(f, "(throw {})", expr),
+            Stmt::Defer(_, stmt) => write!(f, "(defer {})", stmt),
         }
     }
 }
@@ -0,0 +1,246 @@
+use std::fs;
+
+use crate::ast::{Expr, Stmt, Function};
+use crate::lexer::{self, Token, TokenType};
+use crate::parser::Parser;
+use crate::json::Json;
+
+// Writes the Abstract Syntax Tree as JSON for tools outside of Flax. Every node is an
+// object with a "type", like {"type": "Binary", "operator": "+", "left": ..., "right": ...}.
+// Literals keep the text they were written with, so 1 and 1.0 stay distinct.
+
+
+// Parses the file and returns its statements as pretty JSON. When the file can't be
+// read, lexed or parsed the error is an {"errors": [...]} object, so either way the
+// text is valid JSON
+pub fn dump_ast_json(path: &str) -> Result<String, String> {
+    let source = fs::read_to_string(path)
+        .map_err(|e| errors_json(vec![error_json(format!("Unable to read '{}': {}", path, e), None, None)]))?;
+    let tokens = lexer::lex_source(&source).map_err(|errors| {
+        errors_json(errors.iter().map(|e| error_json(e.message().to_string(), Some(e.line()), Some(e.column()))).collect())
+    })?;
+    let statements = Parser::new(tokens).parse_all().map_err(|errors| {
+        errors_json(errors.errors.iter().map(|e| error_json(e.message().to_string(), Some(e.line()), None)).collect())
+    })?;
+    Ok(Json::Array(statements.iter().map(stmt_json).collect()).pretty())
+}
+
+fn errors_json(errors: Vec<Json>) -> String {
+    Json::object(vec![("errors", Json::Array(errors))]).pretty()
+}
+
+fn error_json(message: String, line: Option<u64>, column: Option<usize>) -> Json {
+    let mut entries = vec![("message", Json::String(message))];
+    if let Some(line) = line {
+        entries.push(("line", Json::Int(line as i64)));
+    }
+    if let Some(column) = column {
+        entries.push(("column", Json::Int(column as i64)));
+    }
+    Json::object(entries)
+}
+
+
+pub fn stmt_json(stmt: &Stmt) -> Json {
+    match stmt {
+        Stmt::ExprStmt(expr) => Json::object(vec![("type", Json::string("Expression")), ("expression", expr_json(expr))]),
+        Stmt::VarDecl(name, initializer) => Json::object(vec![
+            ("type", Json::string("Let")),
+            ("name", Json::string(&name.lexeme)),
+            ("initializer", initializer.as_ref().map_or(Json::Null, expr_json)),
+        ]),
+        Stmt::Destructure(names, _, initializer) => Json::object(vec![
+            ("type", Json::string("Destructure")),
+            ("names", Json::Array(names.iter().map(|name| Json::string(&name.lexeme)).collect())),
+            ("initializer", expr_json(initializer)),
+        ]),
+        Stmt::ConstDecl(name, initializer) => Json::object(vec![
+            ("type", Json::string("Const")),
+            ("name", Json::string(&name.lexeme)),
+            ("initializer", expr_json(initializer)),
+        ]),
+        Stmt::Block(statements) => statements_json("Block", statements),
+        Stmt::IfStmt(if_stmt) => Json::object(vec![
+            ("type", Json::string("If")),
+            ("condition", expr_json(&if_stmt.conditional)),
+            ("then", stmt_json(&if_stmt.then_block)),
+            ("else", if_stmt.else_block.as_ref().map_or(Json::Null, stmt_json)),
+        ]),
+        Stmt::WhileStmt(_, cond, body) => Json::object(vec![
+            ("type", Json::string("While")),
+            ("condition", expr_json(cond)),
+            ("body", stmt_json(body)),
+        ]),
+        Stmt::FuncStmt(func) => function_json("Function", func),
+        Stmt::ReturnStmt(ret) => Json::object(vec![
+            ("type", Json::string("Return")),
+            ("value", ret.expr.as_ref().map_or(Json::Null, expr_json)),
+        ]),
+        Stmt::Import(path) => Json::object(vec![("type", Json::string("Import")), ("path", Json::string(&path.lexeme))]),
+        Stmt::TryStmt(stmt) => Json::object(vec![
+            ("type", Json::string("Try")),
+            ("body", stmt_json(&stmt.try_block)),
+            ("name", Json::string(&stmt.name.lexeme)),
+            ("catch", stmt_json(&stmt.catch_block)),
+        ]),
+        Stmt::Throw(_, expr) => Json::object(vec![("type", Json::string("Throw")), ("value", expr_json(expr))]),
+        Stmt::Defer(_, stmt) => Json::object(vec![("type", Json::string("Defer")), ("statement", stmt_json(stmt))]),
+        Stmt::Break => Json::object(vec![("type", Json::string("Break"))]),
+    }
+}
+
+pub fn expr_json(expr: &Expr) -> Json {
+    match expr {
+        Expr::L(lit) => Json::object(vec![
+            ("type", Json::string("Literal")),
+            ("kind", Json::string(literal_kind(&lit.token_type))),
+            ("value", Json::string(&lit.val)),
+        ]),
+        Expr::V(tok, _) => Json::object(vec![("type", Json::string("Variable")), ("name", Json::string(&tok.lexeme))]),
+        Expr::A(tok, value, _) => Json::object(vec![
+            ("type", Json::string("Assign")),
+            ("name", Json::string(&tok.lexeme)),
+            ("value", expr_json(value)),
+        ]),
+        Expr::U(ur) => Json::object(vec![
+            ("type", Json::string("Unary")),
+            ("operator", Json::string(&ur.operator.lexeme)),
+            ("operand", expr_json(&ur.expr)),
+        ]),
+        Expr::B(bi) => operator_json("Binary", &bi.operator, &bi.left, &bi.right),
+        Expr::Log(log) => operator_json("Logical", &log.tok, &log.left, &log.right),
+        Expr::G(grp) => Json::object(vec![("type", Json::string("Grouping")), ("expression", expr_json(&grp.expr))]),
+        Expr::C(cond) => Json::object(vec![
+            ("type", Json::string("Conditional")),
+            ("condition", expr_json(&cond.cond)),
+            ("then", expr_json(&cond.then_expr)),
+            ("else", expr_json(&cond.else_expr)),
+        ]),
+        Expr::Cal(call) => Json::object(vec![
+            ("type", Json::string("Call")),
+            ("callee", expr_json(&call.callee)),
+            ("arguments", Json::Array(call.args.iter().map(expr_json).collect())),
+        ]),
+        Expr::Method(method) => Json::object(vec![
+            ("type", Json::string("Method")),
+            ("receiver", expr_json(&method.receiver)),
+            ("name", Json::string(&method.name.lexeme)),
+            ("arguments", Json::Array(method.args.iter().map(expr_json).collect())),
+        ]),
+        Expr::Arr(arr) => Json::object(vec![
+            ("type", Json::string("Array")),
+            ("elements", Json::Array(arr.elements.iter().map(expr_json).collect())),
+        ]),
+        Expr::Map(map) => {
+            let entries = map.entries.iter()
+                .map(|(k, v)| Json::object(vec![("key", expr_json(k)), ("value", expr_json(v))]))
+                .collect();
+            Json::object(vec![("type", Json::string("Map")), ("entries", Json::Array(entries))])
+        },
+        Expr::Lambda(func) => function_json("Lambda", func),
+        Expr::Block(statements) => statements_json("BlockExpression", statements),
+        Expr::Spread(_, expr) => Json::object(vec![("type", Json::string("Spread")), ("expression", expr_json(expr))]),
+        Expr::Match(m) => {
+            let arms = m.arms.iter()
+                .map(|(pattern, value)| Json::object(vec![("pattern", Json::string(&pattern.lexeme)), ("value", expr_json(value))]))
+                .collect();
+            Json::object(vec![("type", Json::string("Match")), ("value", expr_json(&m.value)), ("arms", Json::Array(arms))])
+        },
+    }
+}
+
+fn literal_kind(token_type: &TokenType) -> &'static str {
+    match token_type {
+        TokenType::NUMBER => "number",
+        TokenType::STRING => "string",
+        TokenType::TRUE | TokenType::FALSE => "boolean",
+        _ => "nil",
+    }
+}
+
+fn operator_json(kind: &str, operator: &Token, left: &Expr, right: &Expr) -> Json {
+    Json::object(vec![
+        ("type", Json::string(kind)),
+        ("operator", Json::string(&operator.lexeme)),
+        ("left", expr_json(left)),
+        ("right", expr_json(right)),
+    ])
+}
+
+fn statements_json(kind: &str, statements: &[Stmt]) -> Json {
+    Json::object(vec![("type", Json::string(kind)), ("statements", Json::Array(statements.iter().map(stmt_json).collect()))])
+}
+
+fn function_json(kind: &str, func: &Function) -> Json {
+    Json::object(vec![
+        ("type", Json::string(kind)),
+        ("name", Json::string(&func.name.lexeme)),
+        ("params", Json::Array(func.params.iter().map(|param| Json::string(&param.lexeme)).collect())),
+        ("body", stmt_json(&func.body)),
+    ])
+}
+
+
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::json;
+
+    // Writes the source to a temporary file and dumps it
+    fn dump(name: &str, source: &str) -> Result<String, String> {
+        let path = std::env::temp_dir().join(format!("flax_ast_{}_{}.flax", name, std::process::id()));
+        fs::write(&path, source).unwrap();
+        let result = dump_ast_json(path.to_str().unwrap());
+        fs::remove_file(&path).unwrap();
+        result
+    }
+
+    #[test]
+    fn dumps_the_tree() {
+        let output = dump("program", "let x = 1 + 2.0;\nif x > 2 {\n    println(\"big\");\n}").unwrap();
+        let tree = json::parse(&output).unwrap();
+        let statements = match &tree {
+            Json::Array(statements) => statements,
+            _ => panic!("expected an array, got {}", tree),
+        };
+        assert_eq!(2, statements.len());
+
+        let decl = &statements[0];
+        assert_eq!(Some(&Json::string("Let")), decl.get("type"));
+        assert_eq!(Some(&Json::string("x")), decl.get("name"));
+        let sum = decl.get("initializer").unwrap();
+        assert_eq!(Some(&Json::string("+")), sum.get("operator"));
+        assert_eq!(Some(&Json::string("2.0")), sum.get("right").and_then(|right| right.get("value")));
+
+        let if_stmt = &statements[1];
+        assert_eq!(Some(&Json::string("If")), if_stmt.get("type"));
+        assert_eq!(Some(&Json::Null), if_stmt.get("else"));
+        let call = match if_stmt.get("then").and_then(|block| block.get("statements")) {
+            Some(Json::Array(statements)) => statements[0].get("expression").unwrap(),
+            other => panic!("expected a block, got {:?}", other),
+        };
+        assert_eq!(Some(&Json::string("Call")), call.get("type"));
+    }
+
+    #[test]
+    fn errors_are_json_too() {
+        let output = dump("broken", "let x = ;\nlet y = (1;").unwrap_err();
+        let errors = json::parse(&output).unwrap();
+        match errors.get("errors") {
+            Some(Json::Array(errors)) => {
+                assert_eq!(2, errors.len());
+                assert_eq!(Some(&Json::Int(1)), errors[0].get("line"));
+            },
+            other => panic!("expected a list of errors, got {:?}", other),
+        }
+
+        let output = dump("lex", "let s = \"open").unwrap_err();
+        let errors = json::parse(&output).unwrap();
+        assert!(errors.to_string().contains("\"column\": 9"));
+
+        let output = dump_ast_json("/no/such/file.flax").unwrap_err();
+        assert!(json::parse(&output).is_ok());
+    }
+}
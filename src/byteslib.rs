@@ -0,0 +1,134 @@
+use std::fmt;
+use crate::errors::RuntimeError;
+use crate::callable::Callable;
+use crate::interpreter::{Value, Interpreter};
+use crate::environment::Environment;
+
+
+const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+
+#[derive(PartialEq, Clone)]
+pub enum BytesLib {
+    ToHex,
+    FromHex,
+    ToBase64,
+    FromBase64,
+}
+
+
+impl Callable for BytesLib {
+    fn call(&self, _interpreter: &mut Interpreter, args: Vec<Value>, _env: &mut Environment) -> Result<Value, RuntimeError> {
+        match self {
+            BytesLib::ToHex => to_hex(&args[0]),
+            BytesLib::FromHex => from_hex(&args[0]),
+            BytesLib::ToBase64 => to_base64(&args[0]),
+            BytesLib::FromBase64 => from_base64(&args[0]),
+        }
+    }
+
+    fn arity(&self) -> u8 {
+        1
+    }
+}
+
+
+fn to_hex(val: &Value) -> Result<Value, RuntimeError> {
+    if let Value::BYTES(bytes) = val {
+        let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        return Ok(Value::STRING(hex))
+    }
+    Err(RuntimeError::no_token_error("to_hex", format!("to_hex expects Bytes, given: {}", val), 1000))
+}
+
+fn from_hex(val: &Value) -> Result<Value, RuntimeError> {
+    let text = match val {
+        Value::STRING(text) => text,
+        _ => return Err(RuntimeError::no_token_error("from_hex", format!("from_hex expects String, given: {}", val), 1000)),
+    };
+    if text.len() % 2 != 0 {
+        return Err(RuntimeError::no_token_error("from_hex", "Hex strings must have an even number of digits".to_string(), 1000))
+    }
+    let mut bytes = Vec::with_capacity(text.len() / 2);
+    for pair in text.as_bytes().chunks(2) {
+        match ((pair[0] as char).to_digit(16), (pair[1] as char).to_digit(16)) {
+            (Some(high), Some(low)) => bytes.push((high * 16 + low) as u8),
+            _ => return Err(RuntimeError::no_token_error("from_hex", format!("Invalid hex string '{}'", text), 1000)),
+        }
+    }
+    Ok(Value::BYTES(bytes))
+}
+
+// Standard base64 with '=' padding
+fn to_base64(val: &Value) -> Result<Value, RuntimeError> {
+    let bytes = match val {
+        Value::BYTES(bytes) => bytes,
+        _ => return Err(RuntimeError::no_token_error("to_base64", format!("to_base64 expects Bytes, given: {}", val), 1000)),
+    };
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = (chunk[0] as u32) << 16 | (*chunk.get(1).unwrap_or(&0) as u32) << 8 | *chunk.get(2).unwrap_or(&0) as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(BASE64_ALPHABET[(group >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    Ok(Value::STRING(encoded))
+}
+
+fn from_base64(val: &Value) -> Result<Value, RuntimeError> {
+    let text = match val {
+        Value::STRING(text) => text,
+        _ => return Err(RuntimeError::no_token_error("from_base64", format!("from_base64 expects String, given: {}", val), 1000)),
+    };
+    let invalid = || RuntimeError::no_token_error("from_base64", format!("Invalid base64 string '{}'", text), 1000);
+    if text.len() % 4 != 0 {
+        return Err(invalid())
+    }
+    let data = text.as_bytes();
+    let padding = data.iter().rev().take_while(|c| **c == b'=').count();
+    if padding > 2 {
+        return Err(invalid())
+    }
+
+    let mut bytes = Vec::with_capacity(data.len() / 4 * 3);
+    for (n, chunk) in data.chunks(4).enumerate() {
+        let last = n == data.len() / 4 - 1;
+        let mut group = 0u32;
+        for (i, c) in chunk.iter().enumerate() {
+            let sextet = match BASE64_ALPHABET.iter().position(|a| a == c) {
+                Some(sextet) => sextet as u32,
+                // Padding may only appear at the end of the last chunk
+                None if *c == b'=' && last && i >= 4 - padding => 0,
+                None => return Err(invalid()),
+            };
+            group = group << 6 | sextet;
+        }
+        let count = if last { 3 - padding } else { 3 };
+        bytes.extend_from_slice(&group.to_be_bytes()[1..=count]);
+    }
+    Ok(Value::BYTES(bytes))
+}
+
+
+impl BytesLib {
+    // The name the builtin is bound to in the globals
+    pub fn name(&self) -> &'static str {
+        match self {
+            BytesLib::ToHex => "to_hex",
+            BytesLib::FromHex => "from_hex",
+            BytesLib::ToBase64 => "to_base64",
+            BytesLib::FromBase64 => "from_base64",
+        }
+    }
+}
+
+impl fmt::Debug for BytesLib {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<fn {}>", self.name())
+    }
+}
+
@@ -3,9 +3,10 @@ use std::fmt;
 use crate::ast;
 use crate::interpreter;
 use crate::environment;
-use crate::errors::RuntimeError;
+use crate::errors::{RuntimeError, TailCall};
 use crate::native_functions::NativeFunctions;
 use crate::strlib::StrLib;
+use crate::byteslib::BytesLib;
 
 use environment::Environment;
 use interpreter::{Interpreter, Value};
@@ -16,6 +17,10 @@ use ast::Function as AstFunc;
 pub trait Callable {
     fn call(&self, interpreter: &mut Interpreter, args: Vec<Value>, env: &mut Environment) -> Result<Value, RuntimeError>;
     fn arity(&self) -> u8;
+    // Variadic functions take their arity as the minimum number of arguments
+    fn variadic(&self) -> bool {
+        false
+    }
 }
 
 #[derive(PartialEq, Clone)]
@@ -23,11 +28,12 @@ pub enum FunctionTypes {
     Function(FlaxFunction),
     NativeFunction(NativeFunctions),
     StringLibrary(Box<StrLib>),
+    BytesLibrary(Box<BytesLib>),
 }
 
 impl FunctionTypes {
-    pub fn new_function(declaration: AstFunc) -> FunctionTypes {
-        FunctionTypes::Function(FlaxFunction::new(declaration))
+    pub fn new_function(declaration: AstFunc, closure: Environment) -> FunctionTypes {
+        FunctionTypes::Function(FlaxFunction::new(declaration, closure))
     }
 
     pub fn new_native_func(func: NativeFunctions) -> FunctionTypes {
@@ -37,6 +43,10 @@ impl FunctionTypes {
     pub fn str_lib_func(func: StrLib) -> FunctionTypes {
         FunctionTypes::StringLibrary(Box::new(func))
     }
+
+    pub fn bytes_lib_func(func: BytesLib) -> FunctionTypes {
+        FunctionTypes::BytesLibrary(Box::new(func))
+    }
 }
 
 impl FunctionTypes {
@@ -45,6 +55,7 @@ impl FunctionTypes {
             FunctionTypes::Function(func) => func.call(interpreter, args, env),
             FunctionTypes::NativeFunction(func) => func.call(interpreter, args, env),
             FunctionTypes::StringLibrary(func) => func.call(interpreter, args, env),
+            FunctionTypes::BytesLibrary(func) => func.call(interpreter, args, env),
         }
     }
 
@@ -53,6 +64,26 @@ impl FunctionTypes {
             FunctionTypes::Function(func) => func.arity(),
             FunctionTypes::NativeFunction(func) => func.arity(),
             FunctionTypes::StringLibrary(func) => func.arity(),
+            FunctionTypes::BytesLibrary(func) => func.arity(),
+        }
+    }
+
+    pub fn variadic(&self) -> bool {
+        match self {
+            FunctionTypes::Function(func) => func.variadic(),
+            FunctionTypes::NativeFunction(func) => func.variadic(),
+            FunctionTypes::StringLibrary(func) => func.variadic(),
+            FunctionTypes::BytesLibrary(func) => func.variadic(),
+        }
+    }
+
+    // The declared name, '<anonymous>' for lambdas. Builtins have the name they're bound to
+    pub fn name(&self) -> String {
+        match self {
+            FunctionTypes::Function(func) => func.declaration.name.lexeme.clone(),
+            FunctionTypes::NativeFunction(func) => func.name().to_string(),
+            FunctionTypes::StringLibrary(func) => func.name().to_string(),
+            FunctionTypes::BytesLibrary(func) => func.name().to_string(),
         }
     }
 }
@@ -63,30 +94,47 @@ impl FunctionTypes {
 #[derive(PartialEq, Debug, Clone)]
 pub struct FlaxFunction {
     declaration: AstFunc,
+    // The scope the function was declared in
+    closure: Environment,
 }
 
 impl FlaxFunction {
-    pub fn new(declaration: AstFunc) -> FlaxFunction {
-        FlaxFunction { declaration }
+    pub fn new(declaration: AstFunc, closure: Environment) -> FlaxFunction {
+        FlaxFunction { declaration, closure }
+    }
+
+    // Whether the function was declared in this scope
+    pub fn closes_over(&self, env: &Environment) -> bool {
+        self.closure == *env
     }
 }
 
 
 impl Callable for FlaxFunction {
-    fn call(&self, interpreter: &mut Interpreter, args: Vec<Value>, env: &mut Environment) -> Result<Value, RuntimeError> {
-        let mut env = env.clone().new_lexical();
-        for (i, token) in self.declaration.params.iter().enumerate() {
-            env.define(token.lexeme.clone(), Some(args[i].clone()))
-        }
-        let value = interpreter.interpret_function(&self.declaration.body, &mut env);
-        match value {
-            Ok(_value) => Ok(Value::Nil),
-            Err(e) => {
-                return match e {
-                    RuntimeError::Return(Some(val)) => Ok(val),
-                    RuntimeError::Return(None) => Ok(Value::Nil),
-                    _ => Err(e),
-                }
+    // 'return f(x)' inside f comes back as a TailCall. The body is run again with the new
+    // arguments instead of calling f recursively, so tail recursion doesn't use up the stack
+    // The body runs in a new scope inside the function's closure, not the caller's scope
+    fn call(&self, interpreter: &mut Interpreter, args: Vec<Value>, _env: &mut Environment) -> Result<Value, RuntimeError> {
+        let mut args = args;
+        loop {
+            let mut env = self.closure.clone().new_lexical();
+            for (i, token) in self.declaration.params.iter().enumerate() {
+                env.define(token.lexeme.clone(), Some(args[i].clone()))
+            }
+            let value = interpreter.interpret_function(&self.declaration.body, &mut env);
+            match value {
+                Ok(_value) => return Ok(Value::Nil),
+                Err(RuntimeError::Return(Some(val))) => return Ok(val),
+                Err(RuntimeError::Return(None)) => return Ok(Value::Nil),
+                Err(RuntimeError::TailCall(call)) => {
+                    let TailCall { func, paren, args: next } = *call;
+                    match &func {
+                        FunctionTypes::Function(callee) if callee == self => args = next,
+                        // Another function that happens to have the same name
+                        _ => return interpreter.call_function(&func, &paren, next, &mut env),
+                    }
+                },
+                Err(e) => return Err(e),
             }
         }
     }
@@ -102,9 +150,10 @@ impl Callable for FlaxFunction {
 impl fmt::Debug for FunctionTypes {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            FunctionTypes::Function(func) => write!(f, "<fn {}>", func.declaration.name),
+            FunctionTypes::Function(func) => write!(f, "<fn '{}'>", func.declaration.name.lexeme),
             FunctionTypes::NativeFunction(func) => write!(f, "{:?}", func),
             FunctionTypes::StringLibrary(func) => write!(f, "{:?}", func),
+            FunctionTypes::BytesLibrary(func) => write!(f, "{:?}", func),
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,98 @@
+use std::cmp::Ordering;
+
+// Compares strings the way a dictionary orders words, a simplified take on the Unicode
+// Collation Algorithm. Strings are compared in three passes, each only breaking ties
+// left by the one before:
+//
+// 1) by their letters, ignoring accents and case: "é" sorts between "e" and "f"
+// 2) by their accents: "e" < "é"
+// 3) by case: "a" < "A"
+//
+// Accented Latin letters are folded to their base letter. Characters without a base
+// letter are compared by code point.
+
+
+pub fn collate(a: &str, b: &str) -> Ordering {
+    let a: Vec<Key> = a.chars().map(key).collect();
+    let b: Vec<Key> = b.chars().map(key).collect();
+    compare_by(&a, &b, |k| k.base)
+        .then_with(|| compare_by(&a, &b, |k| k.accent))
+        .then_with(|| compare_by(&a, &b, |k| k.upper))
+}
+
+fn compare_by<T: Ord>(a: &[Key], b: &[Key], level: impl Fn(&Key) -> T) -> Ordering {
+    a.iter().map(&level).cmp(b.iter().map(&level))
+}
+
+
+// How a character sorts at each level
+struct Key {
+    base: char,
+    accent: u8,
+    upper: bool,
+}
+
+fn key(c: char) -> Key {
+    let upper = c.is_uppercase();
+    let lower = c.to_lowercase().next().unwrap_or(c);
+    let (base, accent) = fold(lower);
+    Key { base, accent, upper }
+}
+
+// The base letter and accent of a lowercase character. Accents are numbered in the
+// order they sort, with 0 for no accent
+fn fold(c: char) -> (char, u8) {
+    const GRAVE: u8 = 1;
+    const ACUTE: u8 = 2;
+    const CIRCUMFLEX: u8 = 3;
+    const TILDE: u8 = 4;
+    const DIAERESIS: u8 = 5;
+    const RING: u8 = 6;
+    const CEDILLA: u8 = 7;
+    const CARON: u8 = 8;
+    const STROKE: u8 = 9;
+    match c {
+        'à' => ('a', GRAVE), 'á' => ('a', ACUTE), 'â' => ('a', CIRCUMFLEX), 'ã' => ('a', TILDE),
+        'ä' => ('a', DIAERESIS), 'å' => ('a', RING),
+        'ç' => ('c', CEDILLA), 'č' => ('c', CARON),
+        'ď' => ('d', CARON),
+        'è' => ('e', GRAVE), 'é' => ('e', ACUTE), 'ê' => ('e', CIRCUMFLEX), 'ë' => ('e', DIAERESIS), 'ě' => ('e', CARON),
+        'ì' => ('i', GRAVE), 'í' => ('i', ACUTE), 'î' => ('i', CIRCUMFLEX), 'ï' => ('i', DIAERESIS),
+        'ł' => ('l', STROKE),
+        'ñ' => ('n', TILDE), 'ň' => ('n', CARON),
+        'ò' => ('o', GRAVE), 'ó' => ('o', ACUTE), 'ô' => ('o', CIRCUMFLEX), 'õ' => ('o', TILDE),
+        'ö' => ('o', DIAERESIS), 'ø' => ('o', STROKE),
+        'ř' => ('r', CARON),
+        'š' => ('s', CARON),
+        'ť' => ('t', CARON),
+        'ù' => ('u', GRAVE), 'ú' => ('u', ACUTE), 'û' => ('u', CIRCUMFLEX), 'ü' => ('u', DIAERESIS), 'ů' => ('u', RING),
+        'ý' => ('y', ACUTE), 'ÿ' => ('y', DIAERESIS),
+        'ž' => ('z', CARON),
+        c => (c, 0),
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn accents_sort_with_their_letter() {
+        assert_eq!(Ordering::Less, collate("é", "f"));
+        assert_eq!(Ordering::Greater, collate("é", "e"));
+        assert_eq!(Ordering::Less, collate("résumé", "resumes"));
+        assert_eq!(Ordering::Less, collate("cote", "côte"));
+        assert_eq!(Ordering::Less, collate("Ärger", "Zebra"));
+    }
+
+    #[test]
+    fn case_only_breaks_ties() {
+        assert_eq!(Ordering::Less, collate("a", "B"));
+        assert_eq!(Ordering::Less, collate("ab", "Z"));
+        assert_eq!(Ordering::Less, collate("a", "A"));
+        assert_eq!(Ordering::Equal, collate("Flax", "Flax"));
+    }
+}
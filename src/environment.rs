@@ -1,67 +1,264 @@
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::rc::Rc;
 use crate::interpreter::Value;
+use crate::callable::FunctionTypes;
 use crate::errors::RuntimeError;
 use crate::lexer::Token;
 
-#[derive(Debug, Clone, PartialEq)]
-pub enum EnvType {
-    Global,
-    Scoped(Box<Environment>)
-}
+// An Environment is a handle to a scope. Cloning it gives another handle to the same
+// scope, so a function keeps the scope it was declared in (its closure) and sees the
+// changes made to it later
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Clone)]
 pub struct Environment {
-    enclosing: EnvType,
-    values: HashMap<String, Value>
+    scope: Rc<Scope>,
+}
+
+struct Scope {
+    enclosing: Option<Environment>,
+    values: RefCell<HashMap<String, Value>>,
+    constants: RefCell<HashSet<String>>,
 }
 
 impl Environment {
     pub fn new() -> Environment {
-        Environment { values: HashMap::new(), enclosing: EnvType::Global }
+        Environment::with_enclosing(None)
+    }
+
+    fn with_enclosing(enclosing: Option<Environment>) -> Environment {
+        let scope = Scope { enclosing, values: RefCell::new(HashMap::new()), constants: RefCell::new(HashSet::new()) };
+        Environment { scope: Rc::new(scope) }
     }
 
     pub fn new_lexical(&mut self) -> Environment {
-        let outer = self.clone();
-        Environment { values: HashMap::new(), enclosing:  EnvType::Scoped(Box::new(outer)) }
+        Environment::with_enclosing(Some(self.clone()))
     }
 
     pub fn define(&mut self, name: String, value: Option<Value>) {
-        match value {
-            Some(val) => {self.values.insert(name, val.clone());},
-            None => {self.values.insert(name, Value::Nil);}
-        }
+        self.scope.constants.borrow_mut().remove(&name);
+        self.scope.values.borrow_mut().insert(name, value.unwrap_or(Value::Nil));
+    }
+
+    pub fn define_constant(&mut self, name: String, value: Value) {
+        self.scope.constants.borrow_mut().insert(name.clone());
+        self.scope.values.borrow_mut().insert(name, value);
     }
 
     pub fn get(&mut self, token: &Token) -> Result<Value, RuntimeError> {
-        match self.values.get(&token.lexeme) {
-            Some(val) => Ok(val.clone()),
-            None => {
-                match self.enclosing {
-                    EnvType::Scoped(ref mut env) => env.get(token), 
-                    EnvType::Global => Err(RuntimeError::string_error(&token, format!("Undefined Identifier: {}", token.lexeme)))
+        match self.find(&token.lexeme) {
+            Some(val) => Ok(val),
+            None => Err(self.undefined(token)),
+        }
+    }
 
-                }
+    fn find(&self, name: &str) -> Option<Value> {
+        let mut scope = Some(self);
+        while let Some(env) = scope {
+            if let Some(val) = env.scope.values.borrow().get(name) {
+                return Some(val.clone());
             }
+            scope = env.scope.enclosing.as_ref();
         }
+        None
     }
 
-    pub fn assign(&mut self, token: &Token, value: Value) -> Result<(), RuntimeError> {
-        let name = token.lexeme.clone();
-        if self.values.contains_key(&name) {
-            self.values.insert(name, value);
-            return Ok(())
+    // The scope 'depth' scopes out from this one, as worked out by the resolver.
+    // A depth of None is the outermost (global) scope
+    fn ancestor(&self, depth: Option<usize>) -> &Environment {
+        let mut env = self;
+        let mut hops = 0;
+        while let Some(outer) = &env.scope.enclosing {
+            if Some(hops) == depth {
+                break;
+            }
+            env = outer;
+            hops += 1;
+        }
+        env
+    }
+
+    // Looks the name up in the one scope the resolver found it in
+    pub fn get_at(&self, depth: Option<usize>, token: &Token) -> Result<Value, RuntimeError> {
+        match self.ancestor(depth).scope.values.borrow().get(&token.lexeme) {
+            Some(val) => Ok(val.clone()),
+            None => Err(self.undefined(token)),
         }
-        if let EnvType::Scoped(ref mut x) = self.enclosing {
-            return x.assign(token, value);
+    }
+
+    pub fn assign_at(&self, depth: Option<usize>, token: &Token, value: Value) -> Result<(), RuntimeError> {
+        self.ancestor(depth).assign_here(token, value)
+            .unwrap_or_else(|| Err(RuntimeError::string_error(token, format!("Undefined identifier: {}", token.lexeme))))
+    }
+
+    fn undefined(&self, token: &Token) -> RuntimeError {
+        RuntimeError::string_error(token, format!("Undefined Identifier: {}{}", token.lexeme, suggestion(&token.lexeme, self.names())))
+    }
+
+    // Assigns the name when it's declared in this scope
+    fn assign_here(&self, token: &Token, value: Value) -> Option<Result<(), RuntimeError>> {
+        let name = &token.lexeme;
+        let mut values = self.scope.values.borrow_mut();
+        if !values.contains_key(name) {
+            return None;
+        }
+        if self.scope.constants.borrow().contains(name) {
+            return Some(Err(RuntimeError::string_error(token, format!("cannot assign to constant '{}'", name))));
+        }
+        values.insert(name.clone(), value);
+        Some(Ok(()))
+    }
+
+    pub fn assign(&mut self, token: &Token, value: Value) -> Result<(), RuntimeError> {
+        let mut scope = Some(&*self);
+        while let Some(env) = scope {
+            if let Some(result) = env.assign_here(token, value.clone()) {
+                return result;
+            }
+            scope = env.scope.enclosing.as_ref();
         }
         Err(RuntimeError::string_error(&token, format!("Undefined identifier: {}", token.lexeme)))
     }
 
-    // TODO:: Better memory management
+    // Returns every name visible from this scope, innermost scope first. Shadowed
+    // names from outer scopes are only listed once
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = Vec::new();
+        let mut scope = Some(self);
+        while let Some(env) = scope {
+            let mut local: Vec<String> = env.scope.values.borrow().keys().filter(|name| !names.contains(name)).cloned().collect();
+            local.sort();
+            names.append(&mut local);
+            scope = env.scope.enclosing.as_ref();
+        }
+        names
+    }
+
+    pub fn is_global(&self) -> bool {
+        self.scope.enclosing.is_none()
+    }
+
+    // Leaving a block scope also releases it (see release)
     pub fn return_outer_scope(&mut self) -> Environment {
-        match self.enclosing {
-            EnvType::Global => self.clone(),
-            EnvType::Scoped(ref env) => *env.clone(),
+        match self.scope.enclosing {
+            None => self.clone(),
+            Some(ref env) => {
+                let outer = env.clone();
+                self.release();
+                outer
+            },
         }
     }
-}
\ No newline at end of file
+
+    // A function declared in a scope is stored in that scope and holds it as its closure,
+    // so the two keep each other alive. When this handle and those functions are all
+    // that is left of the scope, nothing can reach it again and its values are dropped.
+    // A function that escaped the scope is another reference, so the scope is kept, and
+    // stays alive even after that function is dropped
+    fn release(&self) {
+        let own = self.scope.values.borrow().values()
+            .filter(|value| matches!(value, Value::Callable(FunctionTypes::Function(func)) if func.closes_over(self)))
+            .count();
+        if own > 0 && Rc::strong_count(&self.scope) == 1 + own {
+            let values = std::mem::take(&mut *self.scope.values.borrow_mut());
+            drop(values);
+        }
+    }
+}
+
+impl Default for Environment {
+    fn default() -> Environment {
+        Environment::new()
+    }
+}
+
+// Two handles are equal when they are the same scope
+impl PartialEq for Environment {
+    fn eq(&self, other: &Environment) -> bool {
+        Rc::ptr_eq(&self.scope, &other.scope)
+    }
+}
+
+// Scopes can hold functions that point back at them, so only the names are shown
+impl fmt::Debug for Environment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Environment {:?}", self.names())
+    }
+}
+
+
+// Points out the closest of the names to a misspelled one, like "did you mean 'length'?".
+// Names more than 2 edits away (or that would need every character changed) aren't suggested
+pub fn suggestion(name: &str, names: Vec<String>) -> String {
+    let closest = names.into_iter()
+        .map(|candidate| (edit_distance(name, &candidate), candidate))
+        .filter(|(distance, candidate)| *distance <= 2 && *distance < candidate.chars().count().min(name.chars().count()))
+        .min_by_key(|(distance, _)| *distance);
+    match closest {
+        Some((_, candidate)) => format!(", did you mean '{}'?", candidate),
+        None => String::new(),
+    }
+}
+
+// The Levenshtein distance: how many characters have to be inserted, removed or
+// replaced to turn one string into the other
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let replace = previous[j] + if ca == *cb { 0 } else { 1 };
+            current.push(replace.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::interpreter::{Interpreter, Visit};
+    use crate::lexer::lex_source;
+    use crate::parser::Parser;
+
+    // Runs the source in a new block scope inside env and gives back the block's scope
+    fn run_block(interpreter: &mut Interpreter, env: &mut Environment, source: &str) -> Environment {
+        let mut block = env.new_lexical();
+        for statement in Parser::new(lex_source(source).unwrap()).parse().unwrap() {
+            statement.evaluate(interpreter, &mut block).unwrap();
+        }
+        block
+    }
+
+    #[test]
+    fn leaving_a_block_frees_the_functions_declared_in_it() {
+        let mut interpreter = Interpreter::new();
+        let mut env = interpreter.globals.clone();
+        let scopes: Vec<_> = (0..3).map(|_| {
+            let mut block = run_block(&mut interpreter, &mut env, "let x = 1;\nfunc f() { return x; }\nfunc g() { return f(); }");
+            let scope = Rc::downgrade(&block.scope);
+            block.return_outer_scope();
+            scope
+        }).collect();
+        assert!(scopes.iter().all(|scope| scope.upgrade().is_none()));
+    }
+
+    #[test]
+    fn functions_that_escape_a_block_keep_it() {
+        let mut interpreter = Interpreter::new();
+        let mut env = interpreter.globals.clone();
+        let mut block = run_block(&mut interpreter, &mut env, "let x = 1;\nfunc f() { return x; }");
+        let f = block.find("f").unwrap();
+        let scope = Rc::downgrade(&block.scope);
+        block.return_outer_scope();
+        drop(block);
+        assert!(matches!(&f, Value::Callable(FunctionTypes::Function(func)) if func.closes_over(&Environment { scope: scope.upgrade().unwrap() })));
+        assert_eq!(scope.upgrade().unwrap().values.borrow().get("x"), Some(&Value::INT(1)));
+    }
+}
@@ -2,28 +2,65 @@ use std::fmt;
 use std::error::Error;
 use crate::lexer::Token;
 use crate::interpreter::Value;
+use crate::callable::FunctionTypes;
 
-// A Lex Error is an error that the Lexer can throw 
-#[derive(Debug)]
+// A Lex Error is an error that the Lexer can throw. The column is the character
+// the bad token starts at, counting from 1
+#[derive(Debug, PartialEq)]
 pub struct LexError {
     line: u64,
+    column: usize,
     msg: String,
 }
 
-// A Parse Error is an error that the Parser can throw 
+// A Parse Error is an error that the Parser can throw. at_end is set when the
+// Parser ran out of tokens, meaning more input could still make the source valid
 #[derive(Debug)]
 pub struct ParseError {
     line: u64,
-    msg: String
+    msg: String,
+    at_end: bool,
+}
+
+// Every error the Parser found in a file. Only the first few are kept when the
+// number of errors is capped, the rest are just counted
+#[derive(Debug)]
+pub struct ParseErrors {
+    pub errors: Vec<ParseError>,
+    pub omitted: usize,
+}
+
+
+// A call to a Flax function and the line it was called from
+#[derive(Debug, PartialEq, Clone)]
+pub struct Frame {
+    pub name: String,
+    pub line: u64,
+}
+
+
+// A call in tail position waiting to be made, see FlaxFunction::call
+#[derive(Debug, PartialEq)]
+pub struct TailCall {
+    pub func: FunctionTypes,
+    pub paren: Token,
+    pub args: Vec<Value>,
 }
 
 
+// Traced wraps an error raised inside a function with the calls that led to it, innermost first.
+// Caused wraps an error with the error that led to it, like an argument failing inside a call
 #[derive(Debug, PartialEq)]
 pub enum RuntimeError {
     RuntimeError(String, u64, String),
     DivideByZero(u64),
+    Throw(Value, u64),
     Return(Option<Value>),
+    // 'return f(x)' from inside f, run by looping in the current call instead of a new one
+    TailCall(Box<TailCall>),
     Break,
+    Traced(Box<RuntimeError>, Vec<Frame>),
+    Caused(Box<RuntimeError>, Box<RuntimeError>),
 }
 
 impl RuntimeError {
@@ -42,41 +79,156 @@ impl RuntimeError {
     pub fn no_token_error(operator: &str, msg: String,  line: u64) -> RuntimeError {
         RuntimeError::RuntimeError(operator.to_string(), line, msg)
     }
+
+    pub fn with_cause(self, cause: RuntimeError) -> RuntimeError {
+        RuntimeError::Caused(Box::new(self), Box::new(cause))
+    }
+
+    // The line the error occurred on. Control flow signals have no line
+    #[allow(dead_code)]
+    pub fn line(&self) -> Option<u64> {
+        match self {
+            RuntimeError::RuntimeError(_op, line, _msg) => Some(*line),
+            RuntimeError::DivideByZero(line) => Some(*line),
+            RuntimeError::Throw(_value, line) => Some(*line),
+            RuntimeError::Traced(err, _frames) => err.line(),
+            RuntimeError::Caused(err, _cause) => err.line(),
+            _ => None,
+        }
+    }
+
+    // The lexeme of the token that caused the error
+    #[allow(dead_code)]
+    pub fn lexeme(&self) -> Option<&str> {
+        match self {
+            RuntimeError::RuntimeError(op, _line, _msg) => Some(op),
+            RuntimeError::DivideByZero(_line) => Some("/"),
+            RuntimeError::Throw(_value, _line) => Some("throw"),
+            RuntimeError::Traced(err, _frames) => err.lexeme(),
+            RuntimeError::Caused(err, _cause) => err.lexeme(),
+            _ => None,
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            RuntimeError::RuntimeError(_op, _line, msg) => msg.clone(),
+            RuntimeError::DivideByZero(_line) => String::from("Cannot Divide by 0"),
+            RuntimeError::Throw(value, _line) => format!("Uncaught error: {}", value),
+            RuntimeError::Return(_) | RuntimeError::TailCall(_) => String::from("'return' used outside of a function"),
+            RuntimeError::Break => String::from("'break' used outside of a loop"),
+            RuntimeError::Traced(err, _frames) => err.message(),
+            RuntimeError::Caused(err, _cause) => err.message(),
+        }
+    }
+
+    // The function calls that led to the error, innermost first. Empty for errors outside functions
+    #[allow(dead_code)]
+    pub fn backtrace(&self) -> &[Frame] {
+        match self {
+            RuntimeError::Traced(_err, frames) => frames,
+            _ => &[],
+        }
+    }
+
+    // The error that led to this one, if any
+    pub fn caused_by(&self) -> Option<&RuntimeError> {
+        match self {
+            RuntimeError::Caused(_err, cause) => Some(cause),
+            RuntimeError::Traced(err, _frames) => err.caused_by(),
+            _ => None,
+        }
+    }
+
+    // The error at the end of the chain of causes, the one that started it all
+    pub fn root_cause(&self) -> &RuntimeError {
+        match self.caused_by() {
+            Some(cause) => cause.root_cause(),
+            None => self,
+        }
+    }
+
+    // Whether this is a failure, rather than a thrown value or control flow like 'return'
+    pub fn is_failure(&self) -> bool {
+        match self {
+            RuntimeError::RuntimeError(..) | RuntimeError::DivideByZero(_) | RuntimeError::Caused(..) => true,
+            RuntimeError::Traced(err, _frames) => err.is_failure(),
+            _ => false,
+        }
+    }
+
+    // Whether this is a value thrown with 'throw'
+    pub fn is_thrown(&self) -> bool {
+        match self {
+            RuntimeError::Throw(..) => true,
+            RuntimeError::Traced(err, _frames) => err.is_thrown(),
+            _ => false,
+        }
+    }
+
+    // The error without its backtrace
+    pub fn untraced(self) -> RuntimeError {
+        match self {
+            RuntimeError::Traced(err, _frames) => *err,
+            err => err,
+        }
+    }
 }
 
 
 impl fmt::Display for RuntimeError {
     fn fmt(&self, f: &mut fmt::Formatter<>) -> fmt::Result {
-        match self {
-            RuntimeError::RuntimeError(_op, line, msg) => {
-                write!(f, "[RuntimeError line {}]: {}", line, msg)
-            },
-            RuntimeError::DivideByZero(line) => {
-                write!(f, "[RuntimeError line {}]: Cannot Divide by 0", line)
+        match self.line() {
+            Some(line) => write!(f, "[RuntimeError line {}]: {}", line, self.message())?,
+            None => write!(f, "RuntimeError")?,
+        }
+        // Deep recursion repeats the same frame many times, so runs of a frame are collapsed
+        let frames = self.backtrace();
+        let mut i = 0;
+        while i < frames.len() {
+            let repeats = frames[i..].iter().take_while(|frame| **frame == frames[i]).count();
+            write!(f, "\n    at {} (line {})", frames[i].name, frames[i].line)?;
+            if repeats > 1 {
+                write!(f, "\n    ... {} more times", repeats - 1)?;
             }
-            _ => write!(f, "RuntimeError"),
+            i += repeats;
+        }
+        match self.caused_by() {
+            Some(cause) => write!(f, "\ncaused by: {}", cause),
+            None => Ok(()),
         }
     }
 }
 
 impl Error for RuntimeError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
-        // Generic error, underlying cause isn't tracked.
-        None
+        self.caused_by().map(|cause| cause as &(dyn Error + 'static))
     }
 }
 
 
 
 impl LexError {
-    pub fn new(line: u64, msg: String) -> LexError {
-        LexError { line, msg }
+    pub fn new(line: u64, column: usize, msg: String) -> LexError {
+        LexError { line, column, msg }
+    }
+
+    pub fn line(&self) -> u64 {
+        self.line
+    }
+
+    pub fn column(&self) -> usize {
+        self.column
+    }
+
+    pub fn message(&self) -> &str {
+        &self.msg
     }
 }
 
 impl fmt::Display for LexError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{} at line: {}", self.msg, self.line)
+        write!(f, "{} at line: {}, column: {}", self.msg, self.line, self.column)
     }
 }
 
@@ -84,7 +236,25 @@ impl fmt::Display for LexError {
 
 impl ParseError {
     pub fn new(msg:String, line: u64,) -> ParseError {
-        ParseError { line, msg }
+        ParseError { line, msg, at_end: false }
+    }
+
+    pub fn at_end_of_input(mut self) -> ParseError {
+        self.at_end = true;
+        self
+    }
+
+    // Whether the error is an unexpected end of input, like an unclosed '{'
+    pub fn is_unexpected_eof(&self) -> bool {
+        self.at_end
+    }
+
+    pub fn line(&self) -> u64 {
+        self.line
+    }
+
+    pub fn message(&self) -> &str {
+        &self.msg
     }
 }
 
@@ -92,4 +262,49 @@ impl fmt::Display for ParseError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{} at line: {}", self.msg, self.line)
     }
-}
\ No newline at end of file
+}
+
+impl fmt::Display for ParseErrors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut lines: Vec<String> = self.errors.iter().map(|e| e.to_string()).collect();
+        match self.omitted {
+            0 => (),
+            1 => lines.push(String::from("... and 1 more error")),
+            n => lines.push(format!("... and {} more errors", n)),
+        }
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::lexer::TokenType;
+
+    #[test]
+    fn runtime_error_accessors() {
+        let token = Token::new(TokenType::Minus, "-".to_string(), 7);
+        let err = RuntimeError::str_error(&token, "Expected Number");
+        assert_eq!(Some(7), err.line());
+        assert_eq!(Some("-"), err.lexeme());
+        assert_eq!("Expected Number", err.message());
+
+        let err = RuntimeError::DivideByZero(3);
+        assert_eq!(Some(3), err.line());
+        assert_eq!(Some("/"), err.lexeme());
+        assert_eq!("Cannot Divide by 0", err.message());
+
+        assert_eq!(None, RuntimeError::Break.line());
+    }
+
+    #[test]
+    fn repeated_frames_are_collapsed() {
+        let frame = |name: &str, line| Frame { name: name.to_string(), line };
+        let frames = vec![frame("sum", 5), frame("sum", 5), frame("sum", 5), frame("main", 9)];
+        let err = RuntimeError::Traced(Box::new(RuntimeError::DivideByZero(5)), frames);
+        assert_eq!("[RuntimeError line 5]: Cannot Divide by 0\n    at sum (line 5)\n    ... 2 more times\n    at main (line 9)", err.to_string());
+    }
+}
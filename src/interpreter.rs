@@ -1,49 +1,322 @@
 use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::rc::Rc;
+use std::cell::{Cell, Ref, RefCell, RefMut};
+use std::collections::BTreeMap;
 
-use crate::ast::{Binary, Unary, Literal, Grouping, Expr, Stmt, Conditional, IfStatement, 
-    Logical, Call, Function, Return};
+use crate::ast::{Binary, Unary, Literal, Grouping, Expr, Resolution, Stmt, Conditional, IfStatement, 
+    Logical, Call, MethodCall, Match, Function, Return, TryCatch, ArrayLiteral, MapLiteral};
 use crate::callable::{FunctionTypes};
-use crate::errors::{RuntimeError};
+use crate::errors::{RuntimeError, TailCall, Frame};
 use crate::lexer::{TokenType, Token};
 use crate::environment::{ Environment };
 use crate::native_functions::NativeFunctions;
 use crate::strlib::StrLib;
+use crate::byteslib::BytesLib;
+use crate::lexer;
+use crate::parser::Parser;
+use crate::resolver;
+use crate::profiler::Profiler;
+use crate::arena::StringArena;
+use crate::collation;
+use crate::methods::{self, Receiver};
 
 
 
+// How deep Flax calls can nest. Tail calls made with 'return f(x)' from inside f don't count
+const MAX_CALL_DEPTH: usize = 1000;
+// Each nested call takes a few dozen kilobytes of Rust stack (more in debug builds), so
+// Flax runs on a thread with this much stack to reach MAX_CALL_DEPTH without overflowing
+pub const STACK_SIZE: usize = 256 * 1024 * 1024;
+
+// Looks up an environment variable by name
+pub type EnvVars = Box<dyn Fn(&str) -> Option<String>>;
+
 pub struct Interpreter {
-    pub globals: Environment
+    pub globals: Environment,
+    // The files currently being run, innermost import last. Used to resolve
+    // relative imports and to detect circular ones
+    files: Vec<PathBuf>,
+    // When on, '+' concatenates if either operand is a string (like JavaScript).
+    // Off by default, so strings are only concatenated with '++'
+    pub js_plus: bool,
+    // When set, a single loop that runs more times than this is stopped with an error.
+    // Unlimited by default
+    pub max_iterations: Option<u64>,
+    // Counts the expressions evaluated when attached. Nothing is counted when None
+    pub profiler: Option<Profiler>,
+    // How many digits after the decimal point print shows for floats. When None floats
+    // are shown in the shortest form that reads back as the same number
+    pub float_precision: Option<usize>,
+    // When set, strings made by concatenation reuse buffers from the arena. Off by default
+    pub arena: Option<StringArena>,
+    // When on, '<' and friends order strings like a dictionary ("é" < "f") instead of
+    // by code point ("f" < "é"). Off by default
+    pub unicode_collation: bool,
+    // When on, both branches of a '?' are evaluated and must give the same type of value.
+    // Off by default, where only the chosen branch is evaluated
+    pub strict_ternary: bool,
+    // When on, '==' treats two NaNs as equal. Off by default, where NaN equals nothing,
+    // not even itself, as in IEEE 754
+    pub reflexive_nan: bool,
+    // The Flax functions currently being called, outermost first
+    call_stack: Vec<Frame>,
+    // How many defers are waiting in the blocks of the innermost call. A tail call would
+    // run them before the call it makes, so none is made while there are any
+    pending_defers: usize,
+    // Where print, println and write send their text. Stdout unless replaced, like by a
+    // program embedding Flax. It is flushed after every call so pipes see output right away
+    pub output: Box<dyn Write>,
+    // Where env looks up environment variables. The process environment unless replaced
+    pub env_vars: EnvVars,
+    // What args returns: the command line arguments given to the script after '--'
+    pub script_args: Vec<String>,
 }
 
 impl Interpreter {
     pub fn new() -> Interpreter {
         let globals = Self::create_environment();
-        Interpreter { globals }
+        Interpreter { globals, files: Vec::new(), js_plus: false, max_iterations: None, profiler: None, float_precision: None, arena: None, unicode_collation: false, strict_ternary: false, reflexive_nan: false, call_stack: Vec::new(), pending_defers: 0, output: Box::new(io::stdout()),
+            env_vars: Box::new(|name| std::env::var(name).ok()), script_args: Vec::new() }
+    }
+
+    // Sets the file being run so imports are resolved relative to it
+    pub fn set_script(&mut self, filename: &str) {
+        let path = PathBuf::from(filename);
+        self.files.push(fs::canonicalize(&path).unwrap_or(path));
     }
 
     pub fn interpret(&mut self, statements: Vec<Stmt>) -> Result<(), RuntimeError> {
+        resolver::resolve(&statements, self.globals.names())?;
         let mut globals = self.globals.clone();
+        self.run_statements(&statements, &mut globals)
+    }
 
+    // Runs top-level statements in the given environment
+    pub fn run_statements(&mut self, statements: &[Stmt], env: &mut Environment) -> Result<(), RuntimeError> {
         for statement in statements {
-            statement.evaluate(self, &mut globals)?;
+            let value = statement.evaluate(self, env)?;
+            self.discard(value);
+            if let Some(arena) = self.arena.as_mut() {
+                arena.reset();
+            }
         }
         Ok(())
     }
 
+    // Runs a block expression in its own scope. Its value is the value of the last
+    // statement if that is an expression statement, and nil otherwise
+    fn block_value(&mut self, statements: &[Stmt], env: &mut Environment) -> Result<Value, RuntimeError> {
+        let mut new_env = env.new_lexical();
+        let mut deferred = Vec::new();
+        let mut result = Ok(Value::Nil);
+        for statement in statements {
+            if let Stmt::Defer(_, stmt) = statement {
+                deferred.push(stmt.as_ref());
+                self.pending_defers += 1;
+                result = Ok(Value::Nil);
+                continue;
+            }
+            result = statement.evaluate(self, &mut new_env).map(|value| match statement {
+                Stmt::ExprStmt(_) => value,
+                _ => Value::Nil,
+            });
+            if result.is_err() {
+                break;
+            }
+        }
+        let result = self.run_deferred(deferred, &mut new_env, result);
+        *env = new_env.return_outer_scope();
+        result
+    }
+
+    // Runs the statements a block deferred, the last one deferred first. They run however
+    // the block exited, including by an error, 'return' or 'break'. When a deferred
+    // statement fails while the block is failing or throwing, the block's error wins.
+    // Otherwise the first failure replaces the block's result, even a 'return' or 'break'
+    fn run_deferred<T>(&mut self, deferred: Vec<&Stmt>, env: &mut Environment, result: Result<T, RuntimeError>) -> Result<T, RuntimeError> {
+        self.pending_defers -= deferred.len();
+        let mut result = result;
+        for stmt in deferred.into_iter().rev() {
+            if let Err(err) = stmt.evaluate(self, env).map(|value| self.discard(value)) {
+                let failing = matches!(&result, Err(own) if own.is_failure() || own.is_thrown());
+                if !failing {
+                    result = Err(err);
+                }
+            }
+        }
+        result
+    }
+
+    // Called with values nothing will use. Their strings are given back to the arena
+    fn discard(&mut self, value: Value) {
+        if let (Some(arena), Value::STRING(s)) = (self.arena.as_mut(), value) {
+            arena.recycle(s);
+        }
+    }
+
+    // '++' builds its result in a buffer from the arena when there is one, and gives
+    // the operands' buffers back
+    fn concatenate(&mut self, pair: (Value, Value), token: &Token) -> Result<Value, RuntimeError> {
+        match (self.arena.as_mut(), pair) {
+            (Some(arena), (Value::STRING(left), Value::STRING(right))) => {
+                let mut s = arena.alloc(left.len() + right.len());
+                s.push_str(&left);
+                s.push_str(&right);
+                arena.recycle(left);
+                arena.recycle(right);
+                Ok(Value::STRING(s))
+            },
+            (_, pair) => concatenate_values(pair, token),
+        }
+    }
+
     pub fn interpret_function(&mut self, body: &Stmt, env: &mut Environment) -> Result<Value, RuntimeError> {
         let value = body.evaluate(self, env)?;
         Ok(value)
     }
 
 
+    // Writes the text to the output and flushes it
+    pub fn write_output(&mut self, text: &str) -> Result<(), RuntimeError> {
+        self.output.write_all(text.as_bytes())
+            .and_then(|_| self.output.flush())
+            .map_err(|e| RuntimeError::no_token_error("print", format!("Unable to write output: {}", e), 1000))
+    }
+
+    // The text print shows for a value
+    pub fn display(&self, value: &Value) -> String {
+        match self.float_precision {
+            Some(digits) => format!("{:.*}", digits, value),
+            None => value.to_string(),
+        }
+    }
+
+    // Runs the top level declarations of another file in the given environment so its
+    // functions and variables become available to the importer
+    pub fn import(&mut self, path: &Token, env: &mut Environment) -> Result<Value, RuntimeError> {
+        let relative = match self.files.last().and_then(|file| file.parent()) {
+            Some(dir) => dir.join(&path.lexeme),
+            None => PathBuf::from(&path.lexeme),
+        };
+        let file = fs::canonicalize(&relative)
+            .map_err(|e| RuntimeError::string_error(path, format!("Unable to import '{}': {}", path.lexeme, e)))?;
+        if self.files.contains(&file) {
+            return Err(RuntimeError::string_error(path, format!("Circular import of '{}'", path.lexeme)));
+        }
+
+        let source = fs::read_to_string(&file)
+            .map_err(|e| RuntimeError::string_error(path, format!("Unable to import '{}': {}", path.lexeme, e)))?;
+        let tokens = lexer::lex_source(&source).map_err(|errors| {
+            let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+            RuntimeError::string_error(path, format!("Error in import '{}': {}", path.lexeme, errors.join(", ")))
+        })?;
+        let statements = Parser::new(tokens).parse()
+            .map_err(|e| RuntimeError::string_error(path, format!("Error in import '{}': {}", path.lexeme, e)))?;
+
+        // Imports inside a block declare their names in the block, which the resolver
+        // can't know about. Those are looked up by name instead
+        if env.is_global() {
+            resolver::resolve(&statements, env.names())?;
+        }
+        self.files.push(file);
+        let result = statements.iter().try_for_each(|statement| statement.evaluate(self, env).map(|_| ()));
+        self.files.pop();
+        result.map(|_| Value::Nil)
+    }
+
+    // Calls a Flax function, keeping track of it on the call stack. Errors raised inside
+    // are traced with the stack as it was when they happened
+    pub fn call_function(&mut self, func: &FunctionTypes, paren: &Token, args: Vec<Value>, env: &mut Environment) -> Result<Value, RuntimeError> {
+        if self.call_stack.len() >= MAX_CALL_DEPTH {
+            return Err(RuntimeError::string_error(paren, format!("Maximum recursion depth of {} exceeded", MAX_CALL_DEPTH)));
+        }
+        self.call_stack.push(Frame { name: func.name(), line: paren.line });
+        let outer_defers = std::mem::replace(&mut self.pending_defers, 0);
+        let result = func.call(self, args, env);
+        self.pending_defers = outer_defers;
+        let result = match result {
+            Err(err @ RuntimeError::RuntimeError(..)) | Err(err @ RuntimeError::DivideByZero(_)) | Err(err @ RuntimeError::Throw(..)) => {
+                let frames = self.call_stack.iter().rev().cloned().collect();
+                Err(RuntimeError::Traced(Box::new(err), frames))
+            },
+            result => result,
+        };
+        self.call_stack.pop();
+        result
+    }
+
+    // Calls the value a call expression evaluated to with its evaluated arguments
+    fn call_value(&mut self, callee: Value, paren: &Token, arguments: Vec<Value>, env: &mut Environment) -> Result<Value, RuntimeError> {
+        if let Value::Callable(callable) = callee {
+            // Spread arguments are counted after they are expanded
+            let count = arguments.len();
+            if (callable.variadic() && count < callable.arity() as usize) || (!callable.variadic() && count != callable.arity() as usize) {
+                return Err(RuntimeError::str_error(paren, "Invalid callee"))
+            }
+            if let FunctionTypes::Function(_) = callable {
+                return self.call_function(&callable, paren, arguments, env)
+            }
+            return callable.call(self, arguments, env)
+        }
+        Err(RuntimeError::string_error(paren, format!("Can only call functions, given: {}", callee)))
+    }
+
+    // Whether the call is to the function currently running, by name. 'return f(x)' inside
+    // f can then be made without growing the stack
+    fn is_self_call(&self, call: &Call) -> bool {
+        match (&call.callee, self.call_stack.last()) {
+            (Expr::V(name, _), Some(frame)) => name.lexeme == frame.name,
+            _ => false,
+        }
+    }
+
     fn create_environment() -> Environment {
         let mut globals = Environment::new();
         globals.define(String::from("clock"), Some(Value::new_native_function(NativeFunctions::Clock)));
         globals.define(String::from("println"), Some(Value::new_native_function(NativeFunctions::new_println_func(Value::Nil))));
         globals.define(String::from("print"), Some(Value::new_native_function(NativeFunctions::new_print_func(Value::Nil))));
+        globals.define(String::from("vars"), Some(Value::new_native_function(NativeFunctions::Vars)));
+        globals.define(String::from("deep_equal"), Some(Value::new_native_function(NativeFunctions::DeepEqual)));
+        globals.define(String::from("copy"), Some(Value::new_native_function(NativeFunctions::Copy)));
+        globals.define(String::from("push"), Some(Value::new_native_function(NativeFunctions::Push)));
+        globals.define(String::from("freeze"), Some(Value::new_native_function(NativeFunctions::Freeze)));
+        globals.define(String::from("frozen"), Some(Value::new_native_function(NativeFunctions::Frozen)));
+        globals.define(String::from("arity"), Some(Value::new_native_function(NativeFunctions::Arity)));
+        globals.define(String::from("name"), Some(Value::new_native_function(NativeFunctions::Name)));
+        globals.define(String::from("str"), Some(Value::new_native_function(NativeFunctions::Str)));
+        globals.define(String::from("format"), Some(Value::new_native_function(NativeFunctions::Format)));
+        globals.define(String::from("to_json"), Some(Value::new_native_function(NativeFunctions::ToJson)));
+        globals.define(String::from("from_json"), Some(Value::new_native_function(NativeFunctions::FromJson)));
+        globals.define(String::from("zip"), Some(Value::new_native_function(NativeFunctions::Zip)));
+        globals.define(String::from("enumerate"), Some(Value::new_native_function(NativeFunctions::Enumerate)));
+        globals.define(String::from("env"), Some(Value::new_native_function(NativeFunctions::Env)));
+        globals.define(String::from("args"), Some(Value::new_native_function(NativeFunctions::Args)));
+        globals.define(String::from("write"), Some(Value::new_native_function(NativeFunctions::Write)));
+        globals.define(String::from("typeof"), Some(Value::new_native_function(NativeFunctions::TypeOf)));
+        globals.define(String::from("join"), Some(Value::new_native_function(NativeFunctions::Join)));
+        globals.define(String::from("join_print"), Some(Value::new_native_function(NativeFunctions::JoinPrint)));
+        globals.define(String::from("rem"), Some(Value::new_native_function(NativeFunctions::Rem)));
         globals.define(String::from("len"), Some(Value::new_str_function(StrLib::Len)));
         globals.define(String::from("charAt"), Some(Value::new_str_function(StrLib::CharAt)));
         globals.define(String::from("subString"), Some(Value::new_str_function(StrLib::SubStr)));
+        globals.define(String::from("eq_ignore_case"), Some(Value::new_str_function(StrLib::EqIgnoreCase)));
+        globals.define(String::from("upper"), Some(Value::new_str_function(StrLib::Upper)));
+        globals.define(String::from("chr"), Some(Value::new_str_function(StrLib::Chr)));
+        globals.define(String::from("ord"), Some(Value::new_str_function(StrLib::Ord)));
+        globals.define(String::from("is_number"), Some(Value::new_str_function(StrLib::IsNumber)));
+        globals.define(String::from("is_digit"), Some(Value::new_str_function(StrLib::IsDigit)));
+        globals.define(String::from("is_alpha"), Some(Value::new_str_function(StrLib::IsAlpha)));
+        globals.define(String::from("lower"), Some(Value::new_str_function(StrLib::Lower)));
+        globals.define(String::from("trim"), Some(Value::new_str_function(StrLib::Trim)));
+        globals.define(String::from("to_hex"), Some(Value::new_bytes_function(BytesLib::ToHex)));
+        globals.define(String::from("from_hex"), Some(Value::new_bytes_function(BytesLib::FromHex)));
+        globals.define(String::from("to_base64"), Some(Value::new_bytes_function(BytesLib::ToBase64)));
+        globals.define(String::from("from_base64"), Some(Value::new_bytes_function(BytesLib::FromBase64)));
 
         globals
     }
@@ -69,17 +342,49 @@ impl Visit for Stmt {
                 }
                 return Ok(Value::Nil); // Dummy Value
             },
+            Stmt::ConstDecl(token, expr) => {
+                let value = expr.evaluate(interpreter, env)?;
+                env.define_constant(token.lexeme.clone(), value);
+                Ok(Value::Nil) // Dummy Value
+            },
+            Stmt::Destructure(names, equal, expr) => {
+                let values = match expr.evaluate(interpreter, env)? {
+                    Value::ARRAY(values) => values.borrow().clone(),
+                    value => return Err(RuntimeError::string_error(equal,
+                        format!("Cannot destructure {} into {} variables, expected an Array", value.type_name(), names.len()))),
+                };
+                if values.len() != names.len() {
+                    return Err(RuntimeError::string_error(equal,
+                        format!("Cannot destructure {} values into {} variables", values.len(), names.len())));
+                }
+                for (name, value) in names.iter().zip(values) {
+                    env.define(name.lexeme.clone(), Some(value));
+                }
+                Ok(Value::Nil) // Dummy Value
+            },
             Stmt::Block(ref stmts) => {
                 let mut new_env = env.new_lexical();
-                for statement in stmts.iter() {
-                    statement.evaluate(interpreter, &mut new_env)?;
-                }
-                // TODO:: Better memory management
+                let mut deferred = Vec::new();
+                let result = stmts.iter().try_for_each(|statement| match statement {
+                    Stmt::Defer(_, stmt) => {
+                        deferred.push(stmt.as_ref());
+                        interpreter.pending_defers += 1;
+                        Ok(())
+                    },
+                    _ => statement.evaluate(interpreter, &mut new_env).map(|value| interpreter.discard(value)),
+                });
+                let result = interpreter.run_deferred(deferred, &mut new_env, result);
+                // The outer scope is restored even when the block exits early (error, break, return)
                 *env = new_env.return_outer_scope();
-                return Ok(Value::Nil); // Dummy Value
+                result.map(|_| Value::Nil) // Dummy Value
             },
-            Stmt::WhileStmt(ref cond, ref body) => {
+            Stmt::WhileStmt(ref token, ref cond, ref body) => {
+                let mut iterations: u64 = 0;
                 while is_truthy(&cond.evaluate(interpreter, env)?) {
+                   iterations += 1;
+                   if interpreter.max_iterations.is_some_and(|max| iterations > max) {
+                       return Err(RuntimeError::str_error(token, "loop iteration limit exceeded"));
+                   }
                    if let Err(err) = body.evaluate(interpreter, env) {
                        if err == RuntimeError::Break {
                            break;
@@ -93,6 +398,11 @@ impl Visit for Stmt {
             Stmt::FuncStmt(func) => func.evaluate(interpreter, env),
             Stmt::ReturnStmt(stmt) => stmt.evaluate(interpreter, env),
             Stmt::Break => Err(RuntimeError::Break),
+            Stmt::Import(path) => interpreter.import(path, env),
+            Stmt::TryStmt(stmt) => stmt.evaluate(interpreter, env),
+            Stmt::Throw(token, expr) => Err(RuntimeError::Throw(expr.evaluate(interpreter, env)?, token.line)),
+            // Blocks hold back their defers, so one only gets here outside of a block
+            Stmt::Defer(token, _) => Err(RuntimeError::str_error(token, "'defer' can only be used inside a block")),
             Stmt::IfStmt(ref stmt) => stmt.evaluate(interpreter, env),
         }
     }
@@ -101,6 +411,19 @@ impl Visit for Stmt {
 impl Visit for Return{
     fn evaluate(&self, interpreter: &mut Interpreter, env: &mut Environment) -> Result<Value, RuntimeError> {
         let value = match &self.expr {
+            Some(expr @ Expr::Cal(call)) if interpreter.is_self_call(call) && interpreter.pending_defers == 0 => {
+                if let Some(profiler) = interpreter.profiler.as_mut() {
+                    profiler.record(expr);
+                }
+                let callee = call.callee.evaluate(interpreter, env)?;
+                let args = evaluate_elements(&call.args, interpreter, env)?;
+                match callee {
+                    Value::Callable(func @ FunctionTypes::Function(_)) if args.len() == func.arity() as usize => {
+                        return Err(RuntimeError::TailCall(Box::new(TailCall { func, paren: call.tok.clone(), args })));
+                    },
+                    callee => Some(interpreter.call_value(callee, &call.tok, args, env)?),
+                }
+            },
             Some(expr) => Some(expr.evaluate(interpreter, env)?),
             None => None,
         };
@@ -127,9 +450,36 @@ impl Visit for IfStatement {
     }
 }
 
+impl Visit for TryCatch {
+    fn evaluate(&self, interpreter: &mut Interpreter, env: &mut Environment) -> Result<Value, RuntimeError> {
+        let result = match self.try_block.evaluate(interpreter, env) {
+            // A call returned from inside 'try' isn't in tail position, since its errors
+            // have to be caught here. Make the call now
+            Err(RuntimeError::TailCall(call)) => {
+                let TailCall { func, paren, args } = *call;
+                interpreter.call_function(&func, &paren, args, env).and_then(|value| Err(RuntimeError::Return(Some(value))))
+            },
+            result => result,
+        };
+        let caught = match result.map_err(RuntimeError::untraced) {
+            // Thrown values are caught as is, other errors as their message. An error with
+            // a cause is caught as the message of the error that started it.
+            // Control flow like return and break passes through
+            Err(RuntimeError::Throw(value, _line)) => value,
+            Err(err @ RuntimeError::RuntimeError(..)) | Err(err @ RuntimeError::DivideByZero(_)) | Err(err @ RuntimeError::Caused(..)) => Value::STRING(err.root_cause().message()),
+            result => return result,
+        };
+        let mut catch_env = env.new_lexical();
+        catch_env.define(self.name.lexeme.clone(), Some(caught));
+        let result = self.catch_block.evaluate(interpreter, &mut catch_env);
+        *env = catch_env.return_outer_scope();
+        result
+    }
+}
+
 impl Visit for Function {
     fn evaluate(&self, _interpreter: &mut Interpreter, env: &mut Environment) -> Result<Value, RuntimeError> {
-        let function = Value::Callable(FunctionTypes::new_function(self.clone()));
+        let function = Value::Callable(FunctionTypes::new_function(self.clone(), env.clone()));
         env.define(self.name.lexeme.clone(), Some(function));
         Ok(Value::Nil)
     }
@@ -138,6 +488,9 @@ impl Visit for Function {
 
 impl Visit for Expr {
     fn evaluate(&self, interpreter: &mut Interpreter, env: &mut Environment) -> Result<Value, RuntimeError> {
+        if let Some(profiler) = interpreter.profiler.as_mut() {
+            profiler.record(self);
+        }
         match self {
             Expr::L(ref inside_val)     => inside_val.evaluate(interpreter, env),
             Expr::B(ref inside_val)     => inside_val.evaluate(interpreter, env),
@@ -145,11 +498,26 @@ impl Visit for Expr {
             Expr::G(ref inside_val)     => inside_val.evaluate(interpreter, env),
             Expr::C(ref inside_val)     => inside_val.evaluate(interpreter, env),
             Expr::Log(ref inside_val)   => inside_val.evaluate(interpreter, env),
-            Expr::V(ref token)          => env.get(token),
+            Expr::V(ref token, ref depth) => match depth.0.get() {
+                Resolution::Unresolved => env.get(token),
+                Resolution::Local(hops) => env.get_at(Some(hops), token),
+                Resolution::Global => env.get_at(None, token),
+            },
             Expr::Cal(ref inside_val)   => inside_val.evaluate(interpreter, env),
-            Expr::A(ref token, expr)    => {
+            Expr::Method(ref inside_val) => inside_val.evaluate(interpreter, env),
+            Expr::Match(ref inside_val) => inside_val.evaluate(interpreter, env),
+            Expr::Arr(ref inside_val)   => inside_val.evaluate(interpreter, env),
+            Expr::Map(ref inside_val)   => inside_val.evaluate(interpreter, env),
+            Expr::Lambda(ref func)      => Ok(Value::Callable(FunctionTypes::new_function(*func.clone(), env.clone()))),
+            Expr::Block(ref statements) => interpreter.block_value(statements, env),
+            Expr::Spread(ref tok, _)    => Err(RuntimeError::str_error(tok, "'...' can only be used in array literals and argument lists")),
+            Expr::A(ref token, expr, ref depth) => {
                 let value: Value = expr.evaluate(interpreter, env)?;
-                env.assign(token, value.clone())?;
+                match depth.0.get() {
+                    Resolution::Unresolved => env.assign(token, value.clone())?,
+                    Resolution::Local(hops) => env.assign_at(Some(hops), token, value.clone())?,
+                    Resolution::Global => env.assign_at(None, token, value.clone())?,
+                }
                 Ok(value)
             }
         }
@@ -158,44 +526,69 @@ impl Visit for Expr {
 
 impl Visit for Literal {
     fn evaluate(&self, _interpreter: &mut Interpreter, _env: &mut Environment) -> Result<Value, RuntimeError> {
-        if self.val.parse::<f64>().is_ok() {
-            Ok(Value::NUMBER(self.val.parse::<f64>().unwrap()))
-        }
-        else if self.val.parse::<bool>().is_ok() && self.val != "true" && self.val != "false" {
-            Ok(Value::BOOL(self.val.parse::<bool>().unwrap()))
-        }
-        else if self.val.parse::<String>().is_ok() {
-            let s = self.val.parse::<String>().unwrap();
-            match &s[..] {
-                "nil" => Ok(Value::Nil),
-                "true" => Ok(Value::BOOL(true)),
-                "false" => Ok(Value::BOOL(false)),
-                _ => Ok(Value::STRING(s)),
-            }
+        match self.token_type {
+            TokenType::NUMBER => {
+                if let Some(value) = self.cache.get() {
+                    return Ok(value.as_ref().clone());
+                }
+                match parse_number(&self.val) {
+                    Some(value) => Ok(self.cache.get_or_init(|| Box::new(value)).as_ref().clone()),
+                    None => Err(RuntimeError::no_token_error(&self.val, format!("Invalid number literal, given: {}", self.val), self.line)),
+                }
+            },
+            TokenType::STRING => Ok(Value::STRING(self.val.clone())),
+            TokenType::TRUE => Ok(Value::BOOL(true)),
+            TokenType::FALSE => Ok(Value::BOOL(false)),
+            TokenType::Nil => Ok(Value::Nil),
+            _ => Err(RuntimeError::no_token_error(&self.val, format!("Invalid literal value, given: {}", self.val), self.line)),
         }
-        else {
-            return Err(RuntimeError::no_token_error("", String::from("Invalid literal value, given: {}"), 1)) //TODO: better error handling
+    }
+}
+
+// Integer looking literals become INTs when they fit in an i64 so they stay exact.
+// Anything with a decimal point or exponent, or too big for an i64, is a float
+fn parse_number(text: &str) -> Option<Value> {
+    #[cfg(test)]
+    test::NUMBER_PARSES.with(|count| count.set(count.get() + 1));
+    if !text.contains(['.', 'e', 'E']) {
+        if let Ok(int) = text.parse::<i64>() {
+            return Some(Value::INT(int));
         }
     }
+    text.parse::<f64>().ok().map(Value::NUMBER)
 }
 
 impl Visit for Binary {
     fn evaluate(&self, interpreter: &mut Interpreter, env: &mut Environment) -> Result<Value, RuntimeError> {
-        let right: Value = self.right.evaluate(interpreter, env)?;
+        // 'and' and 'or' only evaluate their right side when needed, so they are Logical
+        // nodes. Evaluating both operands here would break that
+        if let TokenType::And | TokenType::Or = self.operator.token_type {
+            return Err(RuntimeError::str_error(&self.operator, "logical operators must use short-circuit evaluation"));
+        }
+        // Errors from the operands are returned untouched so they keep the line of the
+        // innermost operator that failed, not this one
         let left: Value = self.left.evaluate(interpreter, env)?;
-        
+        let right: Value = self.right.evaluate(interpreter, env)?;
+
         match self.operator.token_type {
             TokenType::Minus => check_numbers((left, right), &self.operator),
-            TokenType::Plus => check_numbers((left, right), &self.operator),
+            TokenType::Plus => {
+                match (&left, &right) {
+                    (Value::STRING(_), _) | (_, Value::STRING(_)) if interpreter.js_plus => interpreter.concatenate((left, right), &self.operator),
+                    _ => check_addition((left, right), &self.operator),
+                }
+            },
             TokenType::Star => check_numbers((left, right), &self.operator),
             TokenType::Slash => check_numbers((left, right), &self.operator),
-            TokenType::PlusPlus => concatenate_values((left, right), &self.operator),
-            TokenType::EqualEqual => determine_equality((left, right), &self.operator),
-            TokenType::BangEqual => determine_equality((left, right), &self.operator),
-            TokenType::Less => determine_int_comparison((left, right), &self.operator),
-            TokenType::LessEqual => determine_int_comparison((left, right), &self.operator),
-            TokenType::Greater => determine_int_comparison((left, right), &self.operator),
-            TokenType::GreaterEqual => determine_int_comparison((left, right), &self.operator),
+            TokenType::Percent => check_numbers((left, right), &self.operator),
+            TokenType::StarStar => check_numbers((left, right), &self.operator),
+            TokenType::PlusPlus => interpreter.concatenate((left, right), &self.operator),
+            TokenType::EqualEqual => determine_equality((left, right), &self.operator, interpreter.reflexive_nan),
+            TokenType::BangEqual => determine_equality((left, right), &self.operator, interpreter.reflexive_nan),
+            TokenType::Less => determine_int_comparison((left, right), &self.operator, interpreter.unicode_collation),
+            TokenType::LessEqual => determine_int_comparison((left, right), &self.operator, interpreter.unicode_collation),
+            TokenType::Greater => determine_int_comparison((left, right), &self.operator, interpreter.unicode_collation),
+            TokenType::GreaterEqual => determine_int_comparison((left, right), &self.operator, interpreter.unicode_collation),
             _ => Err(RuntimeError::string_error(&self.operator, format!("Expected expression, given {}", self.operator.lexeme))),
         }
     }
@@ -210,26 +603,46 @@ impl Visit for Unary {
                 if let Value::NUMBER(v) = expr {
                    return Ok(Value::NUMBER(-1.0 * v));
                 }
-                Err(RuntimeError::str_error(&self.operator,  "Invalid unary expression.  Expected Number"))
+                if let Value::INT(v) = expr {
+                    return Ok(v.checked_neg().map_or(Value::NUMBER(-(v as f64)), Value::INT));
+                }
+                Err(RuntimeError::string_error(&self.operator, format!("Invalid unary expression. '{}' expects a number, given: {}", self.operator.lexeme, expr)))
+            },
+            // The bitwise complement of an integer. Floats without a fraction stay floats
+            TokenType::Tilde => match expr {
+                Value::INT(v) => Ok(Value::INT(!v)),
+                Value::NUMBER(v) if v.fract() == 0.0 && v >= i64::MIN as f64 && v < i64::MAX as f64 => Ok(Value::NUMBER(!(v as i64) as f64)),
+                _ => Err(RuntimeError::string_error(&self.operator, format!("'~' expects an integer, given: {}", expr))),
             },
-            TokenType::Bang => Ok(Value::BOOL(!is_truthy(&expr))),
-            _ => Err(RuntimeError::str_error(&self.operator, "Invalid token for Unary"))
+            TokenType::Bang | TokenType::Not => Ok(Value::BOOL(!is_truthy(&expr))),
+            _ => Err(RuntimeError::string_error(&self.operator, format!("Invalid token for Unary, given: {}", self.operator.lexeme)))
         }
     }
 }
 
 impl Visit for Conditional {
     fn evaluate(&self, interpreter: &mut Interpreter, env: &mut Environment) -> Result<Value, RuntimeError> {
+        // Only the selected branch is evaluated, so the other one can't fail or have side effects.
+        // strict_ternary evaluates both to check their types match
         let cond: Value = self.cond.evaluate(interpreter, env)?;
 
         match cond {
+            Value::BOOL(val) if interpreter.strict_ternary => {
+                let then_val = self.then_expr.evaluate(interpreter, env)?;
+                let else_val = self.else_expr.evaluate(interpreter, env)?;
+                if then_val.type_name() != else_val.type_name() {
+                    return Err(RuntimeError::no_token_error("?", format!("ternary branches differ in type ({} vs {})", then_val.type_of(), else_val.type_of()), self.line_num));
+                }
+                Ok(if val { then_val } else { else_val })
+            },
             Value::BOOL(val) => {
                 match val {
                     true => Ok(self.then_expr.evaluate(interpreter, env)?),
                     _ => Ok(self.else_expr.evaluate(interpreter, env)?),
                 }
             },
-            _ => Err(RuntimeError::no_token_error("?", format!("expected boolean given {}", cond), self.line_num)),
+            // Reported on the condition's line, the '?' can be on a later one
+            _ => Err(RuntimeError::no_token_error("?", format!("expected boolean given {}", cond), self.cond.line().unwrap_or(self.line_num))),
         }
     }
 }
@@ -242,11 +655,7 @@ impl Visit for Logical {
                 if is_truthy(&left) {
                    return Ok(left)
                 }
-                else if !is_truthy(&left) {
-                    return Ok(left)
-                } else {
-                    return Ok(self.right.evaluate(interpreter, env)?);
-                }
+                return Ok(self.right.evaluate(interpreter, env)?);
             },
             TokenType::And => {
                 if is_truthy(&left) {
@@ -263,17 +672,96 @@ impl Visit for Logical {
 impl Visit for Call {
     fn evaluate(&self, interpreter: &mut Interpreter, env: &mut Environment) -> Result<Value, RuntimeError> {
         let callee = self.callee.evaluate(interpreter, env)?;
-        let arguments: Vec<Value> = self.args.iter().map(|arg| arg.evaluate(interpreter, env).unwrap()).collect::<Vec<Value>>();
-        if let Value::Callable(callable) = callee {
-            if callable.arity() != self.args.len() as u8 {
-                return Err(RuntimeError::str_error(&self.tok, "Invalid callee"))
-            }
-           return Ok(callable.call(interpreter, arguments, env)?)       
+        let arguments = match evaluate_elements(&self.args, interpreter, env) {
+            Ok(arguments) => arguments,
+            // A builtin whose argument failed reports the failure as its cause. Only the
+            // outermost builtin does, an inner one's report is replaced by this one
+            Err(err) if err.is_failure() => return Err(match &callee {
+                Value::Callable(func) if !matches!(func, FunctionTypes::Function(_)) => {
+                    let cause = match err {
+                        RuntimeError::Caused(_err, cause) => *cause,
+                        err => err,
+                    };
+                    RuntimeError::string_error(&self.tok, format!("Could not evaluate the arguments to '{}'", func.name())).with_cause(cause)
+                },
+                _ => err,
+            }),
+            Err(err) => return Err(err),
+        };
+        interpreter.call_value(callee, &self.tok, arguments, env)
+    }
+}
+
+
+
+impl Visit for Match {
+    fn evaluate(&self, interpreter: &mut Interpreter, env: &mut Environment) -> Result<Value, RuntimeError> {
+        let value = self.value.evaluate(interpreter, env)?;
+        match self.arms.iter().find(|(pattern, _)| value.is_of_type(&pattern.lexeme)) {
+            Some((_, arm)) => arm.evaluate(interpreter, env),
+            None => Err(RuntimeError::string_error(&self.tok, format!("No match arm for {} and no '_' arm", value.type_of()))),
+        }
+    }
+}
+
+
+
+impl Visit for MethodCall {
+    fn evaluate(&self, interpreter: &mut Interpreter, env: &mut Environment) -> Result<Value, RuntimeError> {
+        let receiver = self.receiver.evaluate(interpreter, env)?;
+        let mut arguments = evaluate_elements(&self.args, interpreter, env)?;
+        let (method, position) = match methods::resolve(&receiver, &self.name.lexeme) {
+            Some(method) => method,
+            None => return Err(RuntimeError::string_error(&self.name,
+                format!("{} has no method '{}'", receiver.type_name(), self.name.lexeme))),
+        };
+        if arguments.len() + 1 != method.arity() as usize {
+            return Err(RuntimeError::string_error(&self.paren,
+                format!("'{}' expects {} arguments, given: {}", self.name.lexeme, method.arity() as usize - 1, arguments.len())));
+        }
+        match position {
+            Receiver::First => arguments.insert(0, receiver),
+            Receiver::Last => arguments.push(receiver),
+        }
+        method.call(interpreter, arguments, env)
+    }
+}
+
+impl Visit for ArrayLiteral {
+    fn evaluate(&self, interpreter: &mut Interpreter, env: &mut Environment) -> Result<Value, RuntimeError> {
+        Ok(Value::new_array(evaluate_elements(&self.elements, interpreter, env)?))
+    }
+}
+
+// Evaluates array elements or call arguments in order, expanding each '...xs' into the
+// elements of xs
+fn evaluate_elements(exprs: &[Expr], interpreter: &mut Interpreter, env: &mut Environment) -> Result<Vec<Value>, RuntimeError> {
+    let mut values = Vec::with_capacity(exprs.len());
+    for expr in exprs.iter() {
+        match expr {
+            Expr::Spread(tok, inner) => match inner.evaluate(interpreter, env)? {
+                Value::ARRAY(elements) => values.extend(elements.borrow().iter().cloned()),
+                value => return Err(RuntimeError::string_error(tok, format!("Can only spread an Array, given: {}", value.type_name()))),
+            },
+            expr => values.push(expr.evaluate(interpreter, env)?),
         }
-        Err(RuntimeError::no_token_error("", String::from("Can only call functions"), 10)) //TODO: Better error handling
     }
+    Ok(values)
 }
 
+impl Visit for MapLiteral {
+    fn evaluate(&self, interpreter: &mut Interpreter, env: &mut Environment) -> Result<Value, RuntimeError> {
+        let mut map = BTreeMap::new();
+        for (key, value) in self.entries.iter() {
+            let key = match key.evaluate(interpreter, env)? {
+                Value::STRING(key) => key,
+                other => return Err(RuntimeError::string_error(&self.tok, format!("Map keys must be strings, given: {}", other))),
+            };
+            map.insert(key, value.evaluate(interpreter, env)?);
+        }
+        Ok(Value::new_map(map))
+    }
+}
 
 
 impl Visit for Grouping {
@@ -288,8 +776,12 @@ pub enum Value {
     BOOL(bool),
     STRING(String),
     NUMBER(f64),
+    INT(i64),
     Nil,
-    Callable(FunctionTypes)
+    Callable(FunctionTypes),
+    ARRAY(Rc<Container<Vec<Value>>>),
+    MAP(Rc<Container<BTreeMap<String, Value>>>),
+    BYTES(Vec<u8>),
 }
 
 impl Value {
@@ -300,28 +792,203 @@ impl Value {
     pub fn new_str_function(func: StrLib) -> Value {
         Value::Callable(FunctionTypes::str_lib_func(func))
     }
+
+    pub fn new_bytes_function(func: BytesLib) -> Value {
+        Value::Callable(FunctionTypes::bytes_lib_func(func))
+    }
+
+    pub fn new_array(values: Vec<Value>) -> Value {
+        Value::ARRAY(Rc::new(Container::new(values)))
+    }
+
+    pub fn new_map(entries: BTreeMap<String, Value>) -> Value {
+        Value::MAP(Rc::new(Container::new(entries)))
+    }
+
+    // The name of the value's type, for error messages
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::BOOL(_) => "Bool",
+            Value::STRING(_) => "String",
+            Value::NUMBER(_) => "Number",
+            Value::INT(_) => "Int",
+            Value::Nil => "nil",
+            Value::Callable(_) => "Function",
+            Value::ARRAY(_) => "Array",
+            Value::MAP(_) => "Map",
+            Value::BYTES(_) => "Bytes",
+        }
+    }
+
+    // The name typeof gives the value's type, which is also what match arms are written as
+    pub fn type_of(&self) -> String {
+        self.type_name().to_lowercase()
+    }
+
+    // Whether a match arm for the type covers the value
+    pub fn is_of_type(&self, pattern: &str) -> bool {
+        match pattern {
+            "_" => true,
+            "number" => matches!(self, Value::NUMBER(_) | Value::INT(_)),
+            _ => self.type_of() == pattern,
+        }
+    }
+
+    // Numbers of either kind as a float, used when mixing INTs and NUMBERs
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::NUMBER(v) => Some(*v),
+            Value::INT(v) => Some(*v as f64),
+            _ => None,
+        }
+    }
+}
+
+
+
+// The shared storage behind arrays and maps. Once frozen it can still be read, but
+// every attempt to change it fails. Freezing only covers the container itself, not
+// the containers inside it
+#[derive(Debug)]
+pub struct Container<T> {
+    value: RefCell<T>,
+    frozen: Cell<bool>,
+}
+
+impl<T> Container<T> {
+    pub fn new(value: T) -> Container<T> {
+        Container { value: RefCell::new(value), frozen: Cell::new(false) }
+    }
+
+    pub fn borrow(&self) -> Ref<'_, T> {
+        self.value.borrow()
+    }
+
+    // Mutable access, unless the container is frozen
+    pub fn borrow_mut(&self, operator: &str) -> Result<RefMut<'_, T>, RuntimeError> {
+        if self.frozen.get() {
+            return Err(RuntimeError::no_token_error(operator, String::from("cannot modify frozen value"), 1000))
+        }
+        Ok(self.value.borrow_mut())
+    }
+
+    pub fn freeze(&self) {
+        self.frozen.set(true);
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        self.frozen.get()
+    }
 }
 
+impl<T: PartialEq> PartialEq for Container<T> {
+    fn eq(&self, other: &Container<T>) -> bool {
+        self.value == other.value
+    }
+}
 
 
 
 fn check_numbers(paris: (Value, Value), op: &Token) -> Result<Value, RuntimeError> {
     match paris {
-        (Value::NUMBER(left), Value::NUMBER(right)) => {
-            match op.token_type {
-                TokenType::Minus => Ok(Value::NUMBER(left - right)),
-                TokenType::Plus => Ok(Value::NUMBER(left + right)),
-                TokenType::Star => Ok(Value::NUMBER(left * right)),
-                TokenType::Slash => {
-                    if right == 0.0 {
-                        return Err(RuntimeError::DivideByZero(op.line))
-                    }
-                    Ok(Value::NUMBER(left / right))
-                },
-                _ => Err(RuntimeError::string_error(op, format!("Invalid binary operator for numbers, given {}", op.lexeme))),
+        (Value::INT(left), Value::INT(right)) => int_arithmetic(left, right, op),
+        (Value::NUMBER(left), Value::NUMBER(right)) => float_arithmetic(left, right, op),
+        (Value::INT(left), Value::NUMBER(right)) => float_arithmetic(left as f64, right, op),
+        (Value::NUMBER(left), Value::INT(right)) => float_arithmetic(left, right as f64, op),
+        _ => Err(RuntimeError::string_error(op, not_numbers_message(&paris, op))),
+    }
+}
+
+// Names the operands that aren't numbers along with their types
+fn not_numbers_message(pair: &(Value, Value), op: &Token) -> String {
+    let bad_left = pair.0.as_f64().is_none();
+    let bad_right = pair.1.as_f64().is_none();
+    let problem = match (bad_left, bad_right) {
+        (true, true) => format!("left is {} and right is {}", pair.0.type_of(), pair.1.type_of()),
+        (true, false) => format!("left is {}", pair.0.type_of()),
+        _ => format!("right is {}", pair.1.type_of()),
+    };
+    format!("operator '{}' requires numbers, but {}", op.lexeme, problem)
+}
+
+fn float_arithmetic(left: f64, right: f64, op: &Token) -> Result<Value, RuntimeError> {
+    match op.token_type {
+        TokenType::Minus => Ok(Value::NUMBER(left - right)),
+        TokenType::Plus => Ok(Value::NUMBER(left + right)),
+        TokenType::Star => Ok(Value::NUMBER(left * right)),
+        TokenType::Slash => {
+            if right == 0.0 {
+                return Err(RuntimeError::DivideByZero(op.line))
             }
-        }
-        _ => Err(RuntimeError::string_error(op, format!("'{}' can only be applied to numbers, given: {}, {}", op.lexeme.clone(), paris.0, paris.1))),
+            Ok(Value::NUMBER(left / right))
+        },
+        TokenType::Percent => {
+            if right == 0.0 {
+                return Err(RuntimeError::DivideByZero(op.line))
+            }
+            Ok(Value::NUMBER(floored_mod(left, right)))
+        },
+        TokenType::StarStar => Ok(Value::NUMBER(left.powf(right))),
+        _ => Err(RuntimeError::string_error(op, format!("Invalid binary operator for numbers, given {}", op.lexeme))),
+    }
+}
+
+// INT arithmetic stays an INT. Division that does not divide evenly and results
+// that overflow an i64 fall back to floats
+fn int_arithmetic(left: i64, right: i64, op: &Token) -> Result<Value, RuntimeError> {
+    let result = match op.token_type {
+        TokenType::Minus => left.checked_sub(right),
+        TokenType::Plus => left.checked_add(right),
+        TokenType::Star => left.checked_mul(right),
+        TokenType::Slash => {
+            if right == 0 {
+                return Err(RuntimeError::DivideByZero(op.line))
+            }
+            match left.checked_rem(right) {
+                Some(0) => left.checked_div(right),
+                _ => None,
+            }
+        },
+        TokenType::Percent => {
+            if right == 0 {
+                return Err(RuntimeError::DivideByZero(op.line))
+            }
+            // i64::MIN % -1 overflows, but the remainder is always 0
+            match left.checked_rem(right) {
+                Some(rem) if rem != 0 && (rem < 0) != (right < 0) => Some(rem + right),
+                Some(rem) => Some(rem),
+                None => Some(0),
+            }
+        },
+        // Negative exponents give fractions, so only non negative ones stay INTs
+        TokenType::StarStar => u32::try_from(right).ok().and_then(|exp| left.checked_pow(exp)),
+        _ => return Err(RuntimeError::string_error(op, format!("Invalid binary operator for numbers, given {}", op.lexeme))),
+    };
+    match result {
+        Some(value) => Ok(Value::INT(value)),
+        None => float_arithmetic(left as f64, right as f64, op),
+    }
+}
+
+// '%' is floored, so the result has the sign of the divisor like Python: -7 % 3 == 2.
+// The rem builtin gives the truncated remainder instead
+fn floored_mod(left: f64, right: f64) -> f64 {
+    let rem = left % right;
+    if rem != 0.0 && (rem < 0.0) != (right < 0.0) {
+        rem + right
+    } else {
+        rem
+    }
+}
+
+// '+' only adds numbers. Users coming from other languages often try to concatenate
+// strings with it, so point them towards '++' instead of the generic number error
+fn check_addition(pair: (Value, Value), op: &Token) -> Result<Value, RuntimeError> {
+    match pair {
+        (Value::STRING(_), _) | (_, Value::STRING(_)) => {
+            Err(RuntimeError::string_error(op, format!("{}. Use '++' to concatenate strings", not_numbers_message(&pair, op))))
+        },
+        _ => check_numbers(pair, op),
     }
 }
 
@@ -343,47 +1010,75 @@ fn concatenate_values(pairs: (Value, Value), token: &Token) -> Result<Value, Run
             s.push_str(&v2);
             Ok(Value::STRING(s))
         },
+        (Value::STRING(mut v), Value::INT(v2)) => {
+            v.push_str(&v2.to_string());
+            Ok(Value::STRING(v))
+        }
+        (Value::INT(v), Value::STRING(v2)) => {
+            let mut s = v.to_string();
+            s.push_str(&v2);
+            Ok(Value::STRING(s))
+        },
         _ => Err(RuntimeError::string_error(token, format!("'{}' can only be applied to String and Numbers, given: {}, {}", token.lexeme, pairs.0, pairs.1))),
     }
 }
 
 
-fn determine_equality(pair: (Value, Value), token: &Token) -> Result<Value, RuntimeError> {
+// Two NaNs are only equal when reflexive_nan is on
+fn determine_equality(pair: (Value, Value), token: &Token, reflexive_nan: bool) -> Result<Value, RuntimeError> {
+    let equal = match pair {
+        (Value::NUMBER(v), Value::NUMBER(v2)) if reflexive_nan && v.is_nan() && v2.is_nan() => true,
+        (left, right) => values_equal(&left, &right),
+    };
     match token.token_type {
-        TokenType::EqualEqual => {
-             match pair {
-                (Value::BOOL(v), Value::BOOL(v2)) => Ok(Value::BOOL(v == v2)),
-                (Value::Nil, Value::Nil) => Ok(Value::BOOL(true)),
-                (Value::STRING(v), Value::STRING(v2)) => Ok(Value::BOOL(v == v2)),
-                (Value::NUMBER(v), Value::NUMBER(v2)) => Ok(Value::BOOL(v == v2)),
-                _ => Ok(Value::BOOL(false)),
-            }
-        },
-        TokenType::BangEqual => {
-            match pair {
-                (Value::BOOL(v), Value::BOOL(v2)) =>Ok(Value::BOOL(v != v2)),
-                (Value::Nil, Value::Nil) => Ok(Value::BOOL(false)),
-                (Value::STRING(v), Value::STRING(v2)) => Ok(Value::BOOL(v != v2)),
-                (Value::NUMBER(v), Value::NUMBER(v2)) => Ok(Value::BOOL(v != v2)),
-                _ => Ok(Value::BOOL(true)),
-            }
-        },
+        TokenType::EqualEqual => Ok(Value::BOOL(equal)),
+        TokenType::BangEqual => Ok(Value::BOOL(!equal)),
         _ => Err(RuntimeError::str_error(token, "Invalid token type. Expected '==' or '!='.")),
     }
 }
 
-fn determine_int_comparison(pair: (Value, Value), token: &Token) -> Result<Value, RuntimeError> {
-    match pair {
-        (Value::NUMBER(val), Value::NUMBER(val2)) => {
-            match token.token_type {
-                TokenType::Less => Ok(Value::BOOL(val < val2)),
-                TokenType::LessEqual => Ok(Value::BOOL(val <= val2)),
-                TokenType::Greater => Ok(Value::BOOL(val > val2)),
-                TokenType::GreaterEqual => Ok(Value::BOOL(val >= val2)),
-                _ => panic!("Expected boolean values")
-            } 
-        }, 
-        _ => Err(RuntimeError::str_error(token, "Expected two numbers")),
+// '==' on arrays and maps checks whether both sides are the same container.
+// Use deep_equal to compare their contents
+pub fn values_equal(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::BOOL(v), Value::BOOL(v2)) => v == v2,
+        (Value::Nil, Value::Nil) => true,
+        (Value::STRING(v), Value::STRING(v2)) => v == v2,
+        (Value::NUMBER(v), Value::NUMBER(v2)) => v == v2,
+        (Value::INT(v), Value::INT(v2)) => v == v2,
+        // An INT equals a float with the same value, so '1 == 1.0'
+        (Value::INT(v), Value::NUMBER(v2)) | (Value::NUMBER(v2), Value::INT(v)) => *v as f64 == *v2,
+        (Value::BYTES(v), Value::BYTES(v2)) => v == v2,
+        (Value::ARRAY(v), Value::ARRAY(v2)) => Rc::ptr_eq(v, v2),
+        (Value::MAP(v), Value::MAP(v2)) => Rc::ptr_eq(v, v2),
+        _ => false,
+    }
+}
+
+// INTs are compared exactly and strings by code point, or like a dictionary when
+// unicode_collation is on. Only a mix of INTs and floats is compared as floats. nil
+// gets its own error since it usually means a variable was never given a value
+fn determine_int_comparison(pair: (Value, Value), token: &Token, unicode_collation: bool) -> Result<Value, RuntimeError> {
+    let ordering = match pair {
+        (Value::Nil, _) | (_, Value::Nil) => return Err(RuntimeError::str_error(token, "cannot order nil")),
+        (Value::INT(val), Value::INT(val2)) => val.partial_cmp(&val2),
+        // false < true. Booleans are never compared with numbers, so 'true > 0' is an error
+        (Value::BOOL(val), Value::BOOL(val2)) => val.partial_cmp(&val2),
+        (Value::STRING(val), Value::STRING(val2)) if unicode_collation => Some(collation::collate(&val, &val2)),
+        (Value::STRING(val), Value::STRING(val2)) => val.partial_cmp(&val2),
+        (left, right) => {
+            match (left.as_f64(), right.as_f64()) {
+                (Some(val), Some(val2)) => val.partial_cmp(&val2),
+                _ => return Err(RuntimeError::str_error(token, "Expected two numbers, two strings or two booleans")),
+            }
+        },
+    };
+    match token.token_type {
+        TokenType::Less => Ok(Value::BOOL(ordering == Some(Ordering::Less))),
+        TokenType::LessEqual => Ok(Value::BOOL(matches!(ordering, Some(Ordering::Less) | Some(Ordering::Equal)))),
+        TokenType::Greater => Ok(Value::BOOL(ordering == Some(Ordering::Greater))),
+        TokenType::GreaterEqual => Ok(Value::BOOL(matches!(ordering, Some(Ordering::Greater) | Some(Ordering::Equal)))),
+        _ => Err(RuntimeError::str_error(token, "Invalid token type. Expected '<', '<=', '>' or '>='.")),
     }
 }
 
@@ -399,14 +1094,1239 @@ fn is_truthy(value: &Value) -> bool {
 
  // *** DISPLAY trait implementations below ***
 
+// A precision ("{:.2}") sets how many digits floats are shown with, including the
+// floats inside arrays and maps. Without one floats use the shortest exact form
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter<>) -> fmt::Result {
+        let precision = f.precision();
+        let show = |value: &Value| match precision {
+            Some(digits) => format!("{:.*}", digits, value),
+            None => value.to_string(),
+        };
         match self {
             Value::BOOL(val) => write!(f, "{}", val),
             Value::Nil => write!(f, "nil"),
             Value::STRING(val) => write!(f, "\"{}\"", val),
-            Value::NUMBER(val) => write!(f, "{}", val),
+            Value::NUMBER(val) => match precision {
+                Some(digits) => write!(f, "{:.*}", digits, val),
+                None => write!(f, "{}", val),
+            },
+            Value::INT(val) => write!(f, "{}", val),
             Value::Callable(func) => write!(f, "{:?}", func),
+            Value::ARRAY(values) => {
+                let values: Vec<String> = values.borrow().iter().map(show).collect();
+                write!(f, "[{}]", values.join(", "))
+            },
+            // Printable ASCII is shown as is, everything else as a '\x' escape
+            Value::BYTES(bytes) => {
+                let escaped: String = bytes.iter().map(|b| match b {
+                    b'"' => String::from("\\\""),
+                    b'\\' => String::from("\\\\"),
+                    0x20..=0x7e => (*b as char).to_string(),
+                    _ => format!("\\x{:02x}", b),
+                }).collect();
+                write!(f, "b\"{}\"", escaped)
+            },
+            Value::MAP(entries) => {
+                let entries: Vec<String> = entries.borrow().iter().map(|(k, v)| format!("\"{}\": {}", k, show(v))).collect();
+                write!(f, "{{{}}}", entries.join(", "))
+            },
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::lexer::lex_source;
+    use crate::parser::Parser;
+    use std::cell::Cell;
+
+    thread_local! {
+        // How many times a number literal has been parsed on this thread
+        pub static NUMBER_PARSES: Cell<usize> = const { Cell::new(0) };
+    }
+
+    fn run(source: &str) -> Result<Environment, RuntimeError> {
+        let tokens = lex_source(source).unwrap();
+        let statements = Parser::new(tokens).parse().unwrap();
+        let mut interpreter = Interpreter::new();
+        resolver::resolve(&statements, interpreter.globals.names())?;
+        let mut env = interpreter.globals.clone();
+        for statement in statements {
+            statement.evaluate(&mut interpreter, &mut env)?;
+        }
+        Ok(env)
+    }
+
+    // Runs the source and returns the value of its last statement
+    fn eval(source: &str) -> Result<Value, RuntimeError> {
+        eval_with(Interpreter::new(), source)
+    }
+
+    fn eval_with(mut interpreter: Interpreter, source: &str) -> Result<Value, RuntimeError> {
+        let tokens = lex_source(source).unwrap();
+        let statements = Parser::new(tokens).parse().unwrap();
+        let mut env = interpreter.globals.clone();
+        let mut value = Value::Nil;
+        for statement in statements {
+            value = statement.evaluate(&mut interpreter, &mut env)?;
+        }
+        Ok(value)
+    }
+
+    fn lookup(env: &mut Environment, name: &str) -> Value {
+        env.get(&Token::new(TokenType::Identifier, name.to_string(), 1)).unwrap()
+    }
+
+    #[test]
+    fn else_if_chain() {
+        let mut env = run("
+            func classify(n) {
+                if n < 0 {
+                    return \"negative\";
+                } else if n == 0 {
+                    return \"zero\";
+                } else {
+                    return \"positive\";
+                }
+            }
+            let a = classify(-1);
+            let b = classify(0);
+            let c = classify(1);
+        ").unwrap();
+        assert_eq!(Value::STRING("negative".to_string()), lookup(&mut env, "a"));
+        assert_eq!(Value::STRING("zero".to_string()), lookup(&mut env, "b"));
+        assert_eq!(Value::STRING("positive".to_string()), lookup(&mut env, "c"));
+    }
+
+    #[test]
+    fn vars_lists_visible_names_once() {
+        let mut env = run("
+            let x = 1;
+            let result = nil;
+            {
+                let x = 2;
+                let y = 3;
+                result = vars();
+            }
+        ").unwrap();
+        let names = match lookup(&mut env, "result") {
+            Value::ARRAY(names) => names.borrow().clone(),
+            other => panic!("Expected an array, given {}", other),
+        };
+        let count = |name: &str| names.iter().filter(|v| **v == Value::STRING(name.to_string())).count();
+        assert_eq!(1, count("x"));
+        assert_eq!(1, count("y"));
+        assert_eq!(1, count("result"));
+        assert_eq!(1, count("vars"));
+    }
+
+    #[test]
+    fn plus_on_strings_suggests_concatenation() {
+        let err = run("\"a\" + 1;").unwrap_err();
+        assert!(err.to_string().contains("Use '++' to concatenate strings"));
+
+        let err = run("1 + \"a\";").unwrap_err();
+        assert!(err.to_string().contains("Use '++' to concatenate strings"));
+    }
+
+    #[test]
+    fn number_literal_kinds() {
+        let mut env = run("
+            let big = 9007199254740993;
+            let huge = 1000000000000000000000;
+            let decimal = 3.0;
+            let scientific = 1.5e3;
+        ").unwrap();
+        assert_eq!(Value::INT(9007199254740993), lookup(&mut env, "big"));
+        assert_eq!(Value::NUMBER(1e21), lookup(&mut env, "huge"));
+        assert_eq!(Value::NUMBER(3.0), lookup(&mut env, "decimal"));
+        assert_eq!(Value::NUMBER(1500.0), lookup(&mut env, "scientific"));
+    }
+
+    #[test]
+    fn string_literals_stay_strings() {
+        let mut env = run("let s = \"12\";").unwrap();
+        assert_eq!(Value::STRING("12".to_string()), lookup(&mut env, "s"));
+    }
+
+    #[test]
+    fn word_logical_operators() {
+        assert_eq!(Value::BOOL(true), eval("not false").unwrap());
+        assert_eq!(Value::BOOL(false), eval("not 1").unwrap());
+        assert_eq!(Value::BOOL(false), eval("true and false").unwrap());
+        assert_eq!(Value::INT(3), eval("nil or 3").unwrap());
+        assert_eq!(Value::INT(3), eval("false or nil or 3").unwrap());
+    }
+
+    #[test]
+    fn logical_operators_short_circuit() {
+        // The right hand side would fail with an undefined identifier if evaluated
+        assert_eq!(Value::BOOL(true), eval("true or missing").unwrap());
+        assert_eq!(Value::BOOL(false), eval("false and missing").unwrap());
+        assert!(eval("false or missing").is_err());
+    }
+
+    #[test]
+    fn constants_can_not_be_reassigned() {
+        let mut env = run("const x = 1; let y = x + 1;").unwrap();
+        assert_eq!(Value::INT(2), lookup(&mut env, "y"));
+
+        let err = run("const x = 1; x = 2;").unwrap_err();
+        assert_eq!("cannot assign to constant 'x'", err.message());
+        let err = run("const x = 1; { x += 2; }").unwrap_err();
+        assert_eq!("cannot assign to constant 'x'", err.message());
+
+        let mut env = run("let x = 1; x = 2;").unwrap();
+        assert_eq!(Value::INT(2), lookup(&mut env, "x"));
+    }
+
+    #[test]
+    fn large_integers_compare_exactly() {
+        // Both round to the same f64
+        assert_eq!(Value::BOOL(true), eval("9007199254740992 < 9007199254740993").unwrap());
+        assert_eq!(Value::BOOL(false), eval("9007199254740992 >= 9007199254740993").unwrap());
+        assert_eq!(Value::BOOL(true), eval("9223372036854775807 > 9223372036854775806").unwrap());
+    }
+
+    #[test]
+    fn mixed_and_string_comparison() {
+        assert_eq!(Value::BOOL(true), eval("1 < 1.5").unwrap());
+        assert_eq!(Value::BOOL(true), eval("2.0 >= 2").unwrap());
+        assert_eq!(Value::BOOL(true), eval("\"apple\" < \"banana\"").unwrap());
+        assert_eq!(Value::BOOL(false), eval("\"b\" <= \"a\"").unwrap());
+        assert!(eval("\"a\" < 1").is_err());
+    }
+
+    #[test]
+    fn block_expressions() {
+        let mut env = run("let x = { let t = 2; t * 3 }").unwrap();
+        assert_eq!(Value::INT(6), lookup(&mut env, "x"));
+        // The block's variables don't leak out, but it can change outer ones
+        let mut env = run("let count = 0;\nlet y = {\n    let t = 5;\n    count = count + 1;\n    t\n};\nlet t = nil;").unwrap();
+        assert_eq!(Value::INT(5), lookup(&mut env, "y"));
+        assert_eq!(Value::INT(1), lookup(&mut env, "count"));
+        assert_eq!(Value::INT(7), eval("1 + { 2 * 3 }").unwrap());
+
+        // '{}' and '{key: value}' are still maps
+        assert_eq!(Value::new_map(BTreeMap::new()), eval("let m = {}; m").unwrap());
+        assert!(matches!(eval("let m = {\"a\": 1}; m").unwrap(), Value::MAP(_)));
+    }
+
+    #[test]
+    fn block_expressions_ending_in_a_statement_are_nil() {
+        let mut env = run("let x = { let t = 2; }").unwrap();
+        assert_eq!(Value::Nil, lookup(&mut env, "x"));
+        let mut env = run("let x = {\n    if true {\n        1;\n    }\n};").unwrap();
+        assert_eq!(Value::Nil, lookup(&mut env, "x"));
+    }
+
+    #[test]
+    fn unicode_collation_setting() {
+        assert_eq!(Value::BOOL(false), eval("\"\u{e9}\" < \"f\"").unwrap());
+        assert_eq!(Value::BOOL(true), eval("\"Z\" < \"a\"").unwrap());
+
+        let mut interpreter = Interpreter::new();
+        interpreter.unicode_collation = true;
+        assert_eq!(Value::BOOL(true), eval_with(interpreter, "\"\u{e9}\" < \"f\"").unwrap());
+        let mut interpreter = Interpreter::new();
+        interpreter.unicode_collation = true;
+        assert_eq!(Value::BOOL(false), eval_with(interpreter, "\"Z\" < \"a\"").unwrap());
+    }
+
+    #[test]
+    fn ordering_nil_is_an_error() {
+        assert_eq!("cannot order nil", eval("nil < 3").unwrap_err().message());
+        assert_eq!("cannot order nil", eval("3 > nil").unwrap_err().message());
+        assert_eq!("cannot order nil", eval("nil >= nil").unwrap_err().message());
+        assert_eq!("cannot order nil", eval("let count;\ncount <= 10").unwrap_err().message());
+        // nil can still be checked for with '=='
+        assert_eq!(Value::BOOL(true), eval("nil == nil").unwrap());
+    }
+
+    // Writes each (name, source) pair into a fresh directory and returns its path
+    fn write_files(dir: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("flax_{}_{}", dir, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for (name, source) in files {
+            fs::write(dir.join(name), source).unwrap();
+        }
+        dir
+    }
+
+    fn run_file(path: &std::path::Path) -> Result<Environment, RuntimeError> {
+        let tokens = lexer::lex_file(path.to_str().unwrap()).unwrap();
+        let statements = Parser::new(tokens).parse().unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.set_script(path.to_str().unwrap());
+        let mut env = interpreter.globals.clone();
+        for statement in statements {
+            statement.evaluate(&mut interpreter, &mut env)?;
+        }
+        Ok(env)
+    }
+
+    #[test]
+    fn import_defines_functions() {
+        let dir = write_files("import", &[
+            ("lib.flax", "func double(x) { return x * 2; }"),
+            ("main.flax", "import \"lib.flax\";\nlet result = double(21);"),
+        ]);
+        let mut env = run_file(&dir.join("main.flax")).unwrap();
+        assert_eq!(Value::INT(42), lookup(&mut env, "result"));
+    }
+
+    #[test]
+    fn imports_inside_blocks_are_looked_up_by_name() {
+        let dir = write_files("block_import", &[
+            ("lib.flax", "func libfn() { return 7; }\nlet x = \"lib\";"),
+            ("main.flax", "let x = \"main\";\nlet result = nil;\nlet seen = nil;\n{\n    import \"lib.flax\";\n    result = libfn();\n    seen = x;\n}"),
+        ]);
+        let path = dir.join("main.flax");
+        let statements = Parser::new(lexer::lex_file(path.to_str().unwrap()).unwrap()).parse().unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.set_script(path.to_str().unwrap());
+        interpreter.interpret(statements).unwrap();
+        let mut env = interpreter.globals.clone();
+        assert_eq!(Value::INT(7), lookup(&mut env, "result"));
+        // The imported x hides the outer one inside the block
+        assert_eq!(Value::STRING("lib".to_string()), lookup(&mut env, "seen"));
+        assert_eq!(Value::STRING("main".to_string()), lookup(&mut env, "x"));
+    }
+
+    #[test]
+    fn circular_import_errors() {
+        let dir = write_files("circular_import", &[
+            ("a.flax", "import \"b.flax\";"),
+            ("b.flax", "import \"a.flax\";"),
+        ]);
+        let err = run_file(&dir.join("a.flax")).unwrap_err();
+        assert!(err.message().contains("Circular import of 'a.flax'"));
+    }
+
+    #[test]
+    fn malformed_literals_error() {
+        let mut interpreter = Interpreter::new();
+        let mut env = interpreter.globals.clone();
+
+        let literal = Literal::new("12abc".to_string(), TokenType::NUMBER, 1);
+        let err = literal.evaluate(&mut interpreter, &mut env).unwrap_err();
+        assert_eq!("Invalid number literal, given: 12abc", err.message());
+
+        let literal = Literal::new("+".to_string(), TokenType::Plus, 1);
+        assert!(literal.evaluate(&mut interpreter, &mut env).is_err());
+    }
+
+    #[test]
+    fn operator_errors_report_their_line() {
+        let err = run("let x = 1;\n\nlet y = true ++ x;").unwrap_err();
+        assert_eq!(Some(3), err.line());
+        assert_eq!(Some("++"), err.lexeme());
+
+        let err = run("let x = 1;\nlet y = x < \"a\";").unwrap_err();
+        assert_eq!(Some(2), err.line());
+
+        // Helpers handed an operator they don't handle error at that operator
+        let token = Token::new(TokenType::Plus, "+".to_string(), 5);
+        let err = determine_equality((Value::INT(1), Value::INT(1)), &token, false).unwrap_err();
+        assert_eq!(Some(5), err.line());
+        let err = determine_int_comparison((Value::INT(1), Value::INT(2)), &token, false).unwrap_err();
+        assert_eq!(Some(5), err.line());
+    }
+
+    #[test]
+    fn literal_errors_report_their_line() {
+        let mut interpreter = Interpreter::new();
+        let mut env = interpreter.globals.clone();
+        let statements = Parser::new(lex_source("let x = 1;\n\nlet y = (\n    2.5);").unwrap()).parse().unwrap();
+        let grouping = match &statements[1] {
+            Stmt::VarDecl(_, Some(Expr::G(grouping))) => grouping.clone(),
+            other => panic!("expected a grouping, got {:?}", other),
+        };
+        assert_eq!(3, grouping.line);
+        assert_eq!(Some(4), grouping.expr.line());
+
+        let literal = Literal::new("12abc".to_string(), TokenType::NUMBER, 7);
+        assert_eq!(Some(7), literal.evaluate(&mut interpreter, &mut env).unwrap_err().line());
+
+        // A ternary whose condition isn't a boolean errors on the condition's line
+        let err = run("let x = 1;\nlet y = x\n    ? 2\n    : 3;").unwrap_err();
+        assert_eq!("expected boolean given 1", err.message());
+        assert_eq!(Some(2), err.line());
+    }
+
+    #[test]
+    fn nested_errors_report_the_innermost_operator() {
+        let err = run("let x = 1 +\n(2 *\n\"x\");").unwrap_err();
+        assert_eq!(Some(2), err.line());
+        assert_eq!(Some("*"), err.lexeme());
+
+        // Operands are evaluated left to right, so the first failure is reported
+        let err = run("let x = (true ++ 1) +\n(2 * \"x\");").unwrap_err();
+        assert_eq!(Some("++"), err.lexeme());
+
+        let err = run("println(\n-\"x\");").unwrap_err();
+        assert_eq!(Some(2), err.line());
+
+        let err = run("let x = 1;\n\nx(2);").unwrap_err();
+        assert_eq!(Some(3), err.line());
+    }
+
+    #[test]
+    fn unary_errors_report_the_operator() {
+        let err = run("let x = 1;\nlet y = -\"x\";").unwrap_err();
+        assert_eq!(Some(2), err.line());
+        assert_eq!(Some("-"), err.lexeme());
+        assert!(err.message().contains("'-' expects a number"));
+
+        // The parser never builds these, but the interpreter shouldn't panic on them
+        let operator = Token::new(TokenType::Star, "*".to_string(), 4);
+        let unary = Expr::new_unary(operator, Expr::new_literal("1".to_string(), TokenType::NUMBER, 1));
+        let err = unary.evaluate(&mut Interpreter::new(), &mut Environment::new()).unwrap_err();
+        assert_eq!(Some(4), err.line());
+        assert_eq!(Some("*"), err.lexeme());
+    }
+
+    #[test]
+    fn try_catches_runtime_errors() {
+        let mut env = run("
+            let result = nil;
+            let before = false;
+            try {
+                before = true;
+                let x = 1 / 0;
+                result = \"unreachable\";
+            } catch (e) {
+                result = e;
+            }
+            let after = true;
+        ").unwrap();
+        assert_eq!(Value::STRING("Cannot Divide by 0".to_string()), lookup(&mut env, "result"));
+        assert_eq!(Value::BOOL(true), lookup(&mut env, "before"));
+        assert_eq!(Value::BOOL(true), lookup(&mut env, "after"));
+    }
+
+    #[test]
+    fn try_lets_control_flow_and_catch_errors_through() {
+        let mut env = run("
+            func first() {
+                try {
+                    return 1;
+                } catch (e) {
+                    return 2;
+                }
+            }
+            let value = first();
+        ").unwrap();
+        assert_eq!(Value::INT(1), lookup(&mut env, "value"));
+
+        let err = run("try { 1 / 0; } catch (e) { missing; }").unwrap_err();
+        assert_eq!("Undefined Identifier: missing", err.message());
+    }
+
+    #[test]
+    fn undefined_variable_suggestions() {
+        let err = run("let length = 3;\nprintln(lenght);").unwrap_err();
+        assert_eq!("Undefined Identifier: lenght, did you mean 'length'?", err.message());
+        // Names from outer scopes are suggested too
+        let err = run("let total = 0;\nfunc f() {\n    let count = 1;\n    return totl + cont;\n}\nf();").unwrap_err();
+        assert_eq!("Undefined Identifier: totl, did you mean 'total'?", err.message());
+
+        let err = run("let length = 3;\nprintln(width);").unwrap_err();
+        assert_eq!("Undefined Identifier: width", err.message());
+        // Short names aren't suggested when every character would have to change
+        let err = run("let a = 3;\nprintln(b);").unwrap_err();
+        assert_eq!("Undefined Identifier: b", err.message());
+    }
+
+    #[test]
+    fn break_keeps_assignments_from_its_iteration() {
+        let mut env = run("
+            let i = 0;
+            while true {
+                i += 1;
+                if i == 3 {
+                    break;
+                }
+            }
+        ").unwrap();
+        assert_eq!(Value::INT(3), lookup(&mut env, "i"));
+    }
+
+    #[test]
+    fn thrown_values_are_caught_as_is() {
+        let mut env = run("
+            let message = nil;
+            let code = nil;
+            try { throw \"bad input\"; } catch (e) { message = e; }
+            try { throw 404; } catch (e) { code = e; }
+        ").unwrap();
+        assert_eq!(Value::STRING("bad input".to_string()), lookup(&mut env, "message"));
+        assert_eq!(Value::INT(404), lookup(&mut env, "code"));
+
+        let err = run("\nthrow 1 + 2;").unwrap_err();
+        assert_eq!(RuntimeError::Throw(Value::INT(3), 2), err);
+    }
+
+    #[test]
+    fn strict_ternary_checks_both_branches() {
+        assert_eq!(Value::INT(1), eval("true ? 1 : \"one\"").unwrap());
+
+        let mut interpreter = Interpreter::new();
+        interpreter.strict_ternary = true;
+        assert_eq!(Value::STRING("b".to_string()), eval_with(interpreter, "false ? \"a\" : \"b\"").unwrap());
+
+        let mut interpreter = Interpreter::new();
+        interpreter.strict_ternary = true;
+        let err = eval_with(interpreter, "let x = 2.5;\ntrue ? x : \"none\"").unwrap_err();
+        assert_eq!("ternary branches differ in type (number vs string)", err.message());
+    }
+
+    #[test]
+    fn reflexive_nan_setting() {
+        // A negative number has no real square root
+        let source = "let nan = (-1.0) ** 0.5;\n";
+        assert_eq!(Value::BOOL(false), eval(&format!("{}nan == nan", source)).unwrap());
+        assert_eq!(Value::BOOL(true), eval(&format!("{}nan != nan", source)).unwrap());
+
+        let mut interpreter = Interpreter::new();
+        interpreter.reflexive_nan = true;
+        assert_eq!(Value::BOOL(true), eval_with(interpreter, &format!("{}nan == nan", source)).unwrap());
+        let mut interpreter = Interpreter::new();
+        interpreter.reflexive_nan = true;
+        assert_eq!(Value::BOOL(false), eval_with(interpreter, &format!("{}nan != nan", source)).unwrap());
+        let mut interpreter = Interpreter::new();
+        interpreter.reflexive_nan = true;
+        assert_eq!(Value::BOOL(false), eval_with(interpreter, &format!("{}nan == 1.0", source)).unwrap());
+    }
+
+    #[test]
+    fn js_plus_concatenates_strings() {
+        assert!(eval("\"a\" + 1").is_err());
+
+        let mut interpreter = Interpreter::new();
+        interpreter.js_plus = true;
+        assert_eq!(Value::STRING("a1".to_string()), eval_with(interpreter, "\"a\" + 1").unwrap());
+
+        let mut interpreter = Interpreter::new();
+        interpreter.js_plus = true;
+        assert_eq!(Value::INT(3), eval_with(interpreter, "1 + 2").unwrap());
+    }
+
+    #[test]
+    fn deep_equal_compares_contents() {
+        assert_eq!(Value::BOOL(true), eval("deep_equal([1, [2, {\"a\": [3]}]], [1, [2, {\"a\": [3]}]])").unwrap());
+        assert_eq!(Value::BOOL(false), eval("deep_equal([1, [2, 3]], [1, [2, 4]])").unwrap());
+        assert_eq!(Value::BOOL(false), eval("deep_equal([1, 2], [1, 2, 3])").unwrap());
+        assert_eq!(Value::BOOL(false), eval("deep_equal({\"a\": 1}, {\"b\": 1})").unwrap());
+        assert_eq!(Value::BOOL(false), eval("deep_equal({\"a\": 1}, [1])").unwrap());
+
+        // '==' still compares containers by identity
+        assert_eq!(Value::BOOL(false), eval("[1] == [1]").unwrap());
+        assert_eq!(Value::BOOL(true), eval("let a = [1]; a == a").unwrap());
+    }
+
+    #[test]
+    fn copies_are_independent() {
+        let mut env = run("
+            let a = [1, 2, 3];
+            let alias = a;
+            let b = copy(a);
+            push(b, 4);
+            push(alias, 5);
+
+            let inner = [1];
+            let nested = [inner, {\"k\": inner}];
+            let nested_copy = copy(nested);
+            push(inner, 2);
+            let unchanged = deep_equal(nested_copy, [[1], {\"k\": [1]}]);
+        ").unwrap();
+        assert_eq!("[1, 2, 3, 5]", lookup(&mut env, "a").to_string());
+        assert_eq!("[1, 2, 3, 4]", lookup(&mut env, "b").to_string());
+        assert_eq!(Value::BOOL(true), lookup(&mut env, "unchanged"));
+        assert_eq!(Value::INT(7), eval("copy(7)").unwrap());
+    }
+
+    #[test]
+    fn function_introspection() {
+        let mut env = run("
+            func add(a, b) { return a + b; }
+            let add_arity = arity(add);
+            let add_name = name(add);
+            let square = func (x) { return x * x; };
+            let square_arity = arity(square);
+            let square_name = name(square);
+            let nine = square(3);
+            let clock_name = name(clock);
+        ").unwrap();
+        assert_eq!(Value::INT(2), lookup(&mut env, "add_arity"));
+        assert_eq!(Value::STRING("add".to_string()), lookup(&mut env, "add_name"));
+        assert_eq!(Value::INT(1), lookup(&mut env, "square_arity"));
+        assert_eq!(Value::STRING("<anonymous>".to_string()), lookup(&mut env, "square_name"));
+        assert_eq!(Value::INT(9), lookup(&mut env, "nine"));
+        assert_eq!(Value::STRING("clock".to_string()), lookup(&mut env, "clock_name"));
+
+        assert!(run("arity(1);").is_err());
+        assert!(run("name(\"add\");").is_err());
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        assert_eq!(Value::STRING("00ff10".to_string()), eval("to_hex(from_hex(\"00FF10\"))").unwrap());
+        assert_eq!("b\"Hi\\x00\\xff\"", eval("from_hex(\"486900ff\")").unwrap().to_string());
+
+        for text in ["", "Zg==", "Zm8=", "Zm9v", "Zm9vYg==", "+/+/"].iter() {
+            let source = format!("to_base64(from_base64(\"{}\"))", text);
+            assert_eq!(Value::STRING(text.to_string()), eval(&source).unwrap());
+        }
+        assert_eq!(Value::BOOL(true), eval("from_base64(\"SGkA/w==\") == from_hex(\"486900ff\")").unwrap());
+    }
+
+    #[test]
+    fn malformed_bytes_error() {
+        assert!(eval("from_hex(\"abc\")").is_err());
+        assert!(eval("from_hex(\"zz\")").is_err());
+        assert!(eval("from_hex(\"\u{e9}\u{e9}\")").is_err());
+        assert!(eval("from_base64(\"Zm9\")").is_err());
+        assert!(eval("from_base64(\"Zm9*\")").is_err());
+        assert!(eval("from_base64(\"Z===\")").is_err());
+        assert!(eval("from_base64(\"Zg==Zm9v\")").is_err());
+        assert!(eval("to_hex(\"abc\")").is_err());
+    }
+
+    #[test]
+    fn format_fills_placeholders() {
+        assert_eq!(Value::STRING("1 + 2 = 3".to_string()), eval("format(\"{} + {} = {}\", 1, 2, 3)").unwrap());
+        assert_eq!(Value::STRING("hi [1, \"a\"] nil".to_string()), eval("format(\"{} {} {}\", \"hi\", [1, \"a\"], nil)").unwrap());
+        assert_eq!(Value::STRING("{} {x}".to_string()), eval("format(\"{{}} {{{}}}\", \"x\")").unwrap());
+        assert_eq!(Value::STRING("plain".to_string()), eval("format(\"plain\")").unwrap());
+        assert_eq!(Value::STRING("2.5".to_string()), eval("str(2.5)").unwrap());
+
+        let err = eval("format(\"{} and {}\", 1)").unwrap_err();
+        assert!(err.message().contains("2 placeholders but was given 1 argument"));
+        assert!(eval("format(\"{}\", 1, 2)").is_err());
+        assert!(eval("format(\"{\", 1)").is_err());
+        assert!(eval("format()").is_err());
+    }
+
+    #[test]
+    fn loop_iteration_limit() {
+        let mut interpreter = Interpreter::new();
+        interpreter.max_iterations = Some(1000);
+        let err = eval_with(interpreter, "\nwhile true {}").unwrap_err();
+        assert_eq!(Some(2), err.line());
+        assert_eq!("loop iteration limit exceeded", err.message());
+
+        let mut interpreter = Interpreter::new();
+        interpreter.max_iterations = Some(1000);
+        let source = "
+            let total = 0;
+            let i = 0;
+            while i < 10 {
+                let j = 0;
+                while j < 1000 { j = j + 1; total = total + 1; }
+                i = i + 1;
+            }
+            total";
+        assert_eq!(Value::INT(10000), eval_with(interpreter, source).unwrap());
+    }
+
+    #[test]
+    fn ints_equal_matching_floats() {
+        assert_eq!(Value::BOOL(true), eval("1 == 1.0").unwrap());
+        assert_eq!(Value::BOOL(true), eval("2.0 == 2").unwrap());
+        assert_eq!(Value::BOOL(false), eval("1 != 1.0").unwrap());
+        assert_eq!(Value::BOOL(false), eval("1 == 1.5").unwrap());
+        assert_eq!(Value::BOOL(true), eval("1 != 1.5").unwrap());
+    }
+
+    #[test]
+    fn profiler_counts_evaluations() {
+        let mut interpreter = Interpreter::new();
+        interpreter.profiler = Some(Profiler::new());
+        let tokens = lex_source("
+            let i = 0;
+            let total = 0;
+            while i < 100 {
+                total = total + i * 2;
+                i = i + 1;
+            }
+        ").unwrap();
+        let statements = Parser::new(tokens).parse().unwrap();
+        interpreter.interpret(statements).unwrap();
+
+        let profiler = interpreter.profiler.unwrap();
+        // 101 checks of the condition, then 3 operations in each of the 100 iterations
+        assert_eq!(101 + 100 * 3, profiler.count("binary"));
+        assert_eq!(200, profiler.count("assignment"));
+        assert_eq!(0, profiler.count("call"));
+        assert!(profiler.to_string().lines().any(|line| line == "assignment   200"));
+    }
+
+    #[test]
+    fn modulo_is_floored() {
+        assert_eq!(Value::INT(2), eval("-7 % 3").unwrap());
+        assert_eq!(Value::INT(-2), eval("7 % -3").unwrap());
+        assert_eq!(Value::INT(1), eval("7 % 3").unwrap());
+        assert_eq!(Value::INT(0), eval("-6 % 3").unwrap());
+        assert_eq!(Value::NUMBER(1.5), eval("-1.5 % 3").unwrap());
+        assert_eq!(Value::INT(-1), eval("rem(-7, 3)").unwrap());
+        assert_eq!(Value::INT(1), eval("rem(7, -3)").unwrap());
+        assert_eq!(Value::NUMBER(-1.5), eval("rem(-1.5, 3)").unwrap());
+
+        assert_eq!(RuntimeError::DivideByZero(1), eval("1 % 0").unwrap_err());
+        assert!(matches!(eval("rem(1, 0)").unwrap_err(), RuntimeError::DivideByZero(_)));
+        assert!(matches!(eval("rem(1.5, 0.0)").unwrap_err(), RuntimeError::DivideByZero(_)));
+    }
+
+    #[test]
+    fn case_insensitive_string_comparison() {
+        assert_eq!(Value::BOOL(true), eval("eq_ignore_case(\"ABC\", \"abc\")").unwrap());
+        assert_eq!(Value::BOOL(true), eval("eq_ignore_case(\"ÉCOLE\", \"école\")").unwrap());
+        assert_eq!(Value::BOOL(false), eval("eq_ignore_case(\"a\", \"b\")").unwrap());
+        assert!(eval("eq_ignore_case(\"a\", 1)").is_err());
+    }
+
+    #[test]
+    fn chr_and_ord() {
+        assert_eq!(Value::STRING("A".to_string()), eval("chr(65)").unwrap());
+        assert_eq!(Value::STRING("\u{1F600}".to_string()), eval("chr(128512)").unwrap());
+        assert_eq!(Value::INT(65), eval("ord(\"A\")").unwrap());
+        assert_eq!(Value::INT(233), eval("ord(\"école\")").unwrap());
+        assert_eq!(Value::STRING("z".to_string()), eval("chr(ord(\"y\") + 1)").unwrap());
+
+        // Past the last code point, and a surrogate
+        assert!(eval("chr(1114112)").is_err());
+        assert!(eval("chr(55296)").is_err());
+        assert!(eval("chr(-1)").is_err());
+        assert!(eval("chr(65.5)").is_err());
+        let err = eval("ord(\"\")").unwrap_err();
+        assert!(err.to_string().contains("ord expects a non-empty String"), "{}", err);
+    }
+
+    #[test]
+    fn errors_in_functions_have_a_backtrace() {
+        let err = run("
+            func inner(x) {
+                return x / 0;
+            }
+            func middle(x) {
+                return inner(x);
+            }
+            func outer(x) {
+                return middle(x);
+            }
+            outer(1);
+        ").unwrap_err();
+        assert_eq!(Some(3), err.line());
+        let frames: Vec<(&str, u64)> = err.backtrace().iter().map(|frame| (&frame.name[..], frame.line)).collect();
+        assert_eq!(vec![("inner", 6), ("middle", 9), ("outer", 11)], frames);
+        assert_eq!("[RuntimeError line 3]: Cannot Divide by 0\n    at inner (line 6)\n    at middle (line 9)\n    at outer (line 11)",
+            err.to_string());
+
+        // Errors caught inside a function don't keep a trace
+        let value = eval("
+            func fail() { throw \"boom\"; }
+            func safe() {
+                try { fail(); } catch (e) { return e; }
+            }
+            safe()
+        ").unwrap();
+        assert_eq!(Value::STRING("boom".to_string()), value);
+    }
+
+    #[test]
+    fn frozen_containers_reject_changes() {
+        let mut env = run("
+            let fixed = [1, 2];
+            let same = freeze(fixed);
+            let open = [1, 2];
+            push(open, 3);
+            let fixed_is_frozen = frozen(fixed);
+            let open_is_frozen = frozen(open);
+            let thawed = copy(fixed);
+            push(thawed, 3);
+        ").unwrap();
+        assert_eq!(Value::BOOL(true), lookup(&mut env, "fixed_is_frozen"));
+        assert_eq!(Value::BOOL(false), lookup(&mut env, "open_is_frozen"));
+        assert_eq!("[1, 2, 3]", lookup(&mut env, "open").to_string());
+        assert_eq!("[1, 2, 3]", lookup(&mut env, "thawed").to_string());
+        assert_eq!(Value::BOOL(true), eval("let a = [1]; freeze(a) == a").unwrap());
+
+        let err = run("let fixed = freeze([1, 2]);\npush(fixed, 3);").unwrap_err();
+        assert_eq!("cannot modify frozen value", err.message());
+        assert_eq!(Value::BOOL(false), eval("frozen(1)").unwrap());
+        assert!(eval("freeze(1)").is_err());
+    }
+
+    #[test]
+    fn arithmetic_promotion_matrix() {
+        // (operator, int/int, int/float, float/int, float/float) for the operands 7 and 2
+        let cases = [
+            ("+", Value::INT(9), Value::NUMBER(9.0), Value::NUMBER(9.0), Value::NUMBER(9.0)),
+            ("-", Value::INT(5), Value::NUMBER(5.0), Value::NUMBER(5.0), Value::NUMBER(5.0)),
+            ("*", Value::INT(14), Value::NUMBER(14.0), Value::NUMBER(14.0), Value::NUMBER(14.0)),
+            ("/", Value::NUMBER(3.5), Value::NUMBER(3.5), Value::NUMBER(3.5), Value::NUMBER(3.5)),
+            ("%", Value::INT(1), Value::NUMBER(1.0), Value::NUMBER(1.0), Value::NUMBER(1.0)),
+            ("**", Value::INT(49), Value::NUMBER(49.0), Value::NUMBER(49.0), Value::NUMBER(49.0)),
+        ];
+        for (op, int_int, int_float, float_int, float_float) in cases.iter() {
+            assert_eq!(*int_int, eval(&format!("7 {} 2", op)).unwrap(), "7 {} 2", op);
+            assert_eq!(*int_float, eval(&format!("7 {} 2.0", op)).unwrap(), "7 {} 2.0", op);
+            assert_eq!(*float_int, eval(&format!("7.0 {} 2", op)).unwrap(), "7.0 {} 2", op);
+            assert_eq!(*float_float, eval(&format!("7.0 {} 2.0", op)).unwrap(), "7.0 {} 2.0", op);
+        }
+
+        // Division stays an INT only when it divides evenly
+        assert_eq!(Value::INT(4), eval("8 / 2").unwrap());
+        assert_eq!(Value::NUMBER(2.5), eval("5 / 2").unwrap());
+        assert_eq!(Value::NUMBER(-2.5), eval("-5 / 2").unwrap());
+
+        assert_eq!(Value::NUMBER(0.25), eval("2 ** -2").unwrap());
+        assert_eq!(Value::INT(-4), eval("-2 ** 2").unwrap());
+        assert_eq!(Value::INT(512), eval("2 ** 3 ** 2").unwrap());
+        assert_eq!(Value::NUMBER(2f64.powi(64)), eval("2 ** 64").unwrap());
+        assert_eq!(Value::NUMBER((i64::MAX as f64) + 1.0), eval("9223372036854775807 + 1").unwrap());
+    }
+
+    #[test]
+    fn conditional_only_evaluates_the_selected_branch() {
+        assert_eq!(Value::INT(1), eval("true ? 1 : 1 / 0").unwrap());
+        assert_eq!(Value::INT(2), eval("false ? 1 / 0 : 2").unwrap());
+        assert_eq!(Value::INT(0), eval("let x = 0; true ? 1 : (x = 5); x").unwrap());
+        assert!(eval("true ? 1 / 0 : 2").is_err());
+    }
+
+    #[test]
+    #[allow(clippy::approx_constant)]
+    fn float_precision_setting() {
+        let mut interpreter = Interpreter::new();
+        assert_eq!("3.14159265", interpreter.display(&Value::NUMBER(3.14159265)));
+        interpreter.float_precision = Some(2);
+        assert_eq!("3.14", interpreter.display(&Value::NUMBER(3.14159265)));
+        assert_eq!("7", interpreter.display(&Value::INT(7)));
+        assert_eq!("\"3.14159265\"", interpreter.display(&Value::STRING("3.14159265".to_string())));
+        let nested = eval("[0.5, {\"pi\": 3.14159265}]").unwrap();
+        assert_eq!("[0.50, {\"pi\": 3.14}]", interpreter.display(&nested));
+    }
+
+    #[test]
+    fn method_calls() {
+        assert_eq!(Value::STRING("ABC".to_string()), eval("\"abc\".upper()").unwrap());
+        assert_eq!(Value::STRING("X".to_string()), eval("\"  x \".trim().upper()").unwrap());
+        assert_eq!(Value::STRING("b".to_string()), eval("let s = \"abc\"; s.charAt(1)").unwrap());
+        assert_eq!(eval("len(\"héllo\")").unwrap(), eval("\"héllo\".len()").unwrap());
+        assert_eq!(Value::BOOL(true), eval("\"Flax\".eq_ignore_case(\"FLAX\")").unwrap());
+        assert_eq!(Value::STRING("abc".to_string()), eval("lower(\"ABC\")").unwrap());
+
+        let err = eval("\"abc\".shout()").unwrap_err();
+        assert!(err.to_string().contains("String has no method 'shout'"), "{}", err);
+        let err = eval("(1).upper()").unwrap_err();
+        assert!(err.to_string().contains("Int has no method 'upper'"), "{}", err);
+        let err = eval("\"abc\".upper(1)").unwrap_err();
+        assert!(err.to_string().contains("'upper' expects 0 arguments, given: 1"), "{}", err);
+    }
+
+    #[test]
+    fn multiple_return_values() {
+        let mut env = run("
+            func divmod(a, b) {
+                return a / b, a % b;
+            }
+            let q, r = divmod(7, 2);
+            let first, rest = [1, [2, 3]];
+        ").unwrap();
+        assert_eq!(Value::NUMBER(3.5), lookup(&mut env, "q"));
+        assert_eq!(Value::INT(1), lookup(&mut env, "r"));
+        assert_eq!(Value::INT(1), lookup(&mut env, "first"));
+        assert_eq!(Value::INT(2), eval("func pair() { return 1, 2; } let a, b = pair(); b").unwrap());
+    }
+
+    #[test]
+    fn destructuring_mismatch_errors() {
+        let err = eval("let a, b, c = [1, 2];").unwrap_err();
+        assert!(err.to_string().contains("Cannot destructure 2 values into 3 variables"), "{}", err);
+        let err = eval("let a, b = \"ab\";").unwrap_err();
+        assert!(err.to_string().contains("Cannot destructure String into 2 variables"), "{}", err);
+    }
+
+    #[test]
+    fn booleans_are_ordered() {
+        assert_eq!(Value::BOOL(true), eval("false < true").unwrap());
+        assert_eq!(Value::BOOL(true), eval("true > false").unwrap());
+        assert_eq!(Value::BOOL(false), eval("true < true").unwrap());
+        assert_eq!(Value::BOOL(true), eval("true >= true").unwrap());
+        let err = eval("true > 0").unwrap_err();
+        assert!(err.to_string().contains("Expected two numbers, two strings or two booleans"), "{}", err);
+        assert!(eval("1 <= false").is_err());
+    }
+
+    #[test]
+    fn logical_binary_is_rejected() {
+        let x = || Token::new(TokenType::Identifier, "x".to_string(), 1);
+        for (token_type, lexeme) in [(TokenType::And, "and"), (TokenType::Or, "or")].iter() {
+            let mut interpreter = Interpreter::new();
+            let mut env = interpreter.globals.clone();
+            env.define("x".to_string(), Some(Value::INT(0)));
+            // 'x = 1 and x = 2' as a Binary. Neither side may run
+            let binary = Expr::new_binary(Expr::new_assignment(x(), Expr::new_literal("1".to_string(), TokenType::NUMBER, 1)),
+                Token::new(token_type.clone(), lexeme.to_string(), 1),
+                Expr::new_assignment(x(), Expr::new_literal("2".to_string(), TokenType::NUMBER, 1)));
+            let err = binary.evaluate(&mut interpreter, &mut env).unwrap_err();
+            assert!(err.to_string().contains("logical operators must use short-circuit evaluation"), "{}", err);
+            assert_eq!(Value::INT(0), lookup(&mut env, "x"));
+        }
+        assert_eq!(Value::INT(0), eval("let x = 0; false and (x = 1); x").unwrap());
+    }
+
+    #[test]
+    fn number_literals_are_parsed_once() {
+        let statements = Parser::new(lex_source("let total = 0\nlet i = 0\nwhile i < 100 {\n    total = total + 2.5\n    i = i + 1\n}").unwrap()).parse().unwrap();
+        let mut interpreter = Interpreter::new();
+        let mut env = interpreter.globals.clone();
+        let before = NUMBER_PARSES.with(|count| count.get());
+        for statement in statements.iter() {
+            statement.evaluate(&mut interpreter, &mut env).unwrap();
+        }
+        // 0, 0, 100, 2.5 and 1, each parsed once
+        assert_eq!(5, NUMBER_PARSES.with(|count| count.get()) - before);
+        assert_eq!(Value::NUMBER(250.0), lookup(&mut env, "total"));
+
+        // The cache doesn't make literals unequal
+        let literal = Literal::new("7".to_string(), TokenType::NUMBER, 1);
+        literal.evaluate(&mut interpreter, &mut env).unwrap();
+        assert_eq!(Literal::new("7".to_string(), TokenType::NUMBER, 1), literal);
+    }
+
+    #[test]
+    fn spread_arrays() {
+        assert_eq!("[1, 2, 3, 4]", eval("let xs = [2, 3]; [1, ...xs, 4]").unwrap().to_string());
+        assert_eq!("[]", eval("[...[], ...[]]").unwrap().to_string());
+        assert_eq!(Value::INT(6), eval("func add(a, b, c) { return a + b + c; } let args = [2, 3]; add(1, ...args)").unwrap());
+        assert_eq!(Value::STRING("b".to_string()), eval("\"abc\".charAt(...[1])").unwrap());
+        // Arity is checked once the arguments are expanded
+        assert!(eval("func add(a, b) { return a + b; } add(...[1, 2, 3])").is_err());
+        assert!(eval("func add(a, b) { return a + b; } add(...[1])").is_err());
+
+        let err = eval("[...5]").unwrap_err();
+        assert!(err.to_string().contains("Can only spread an Array, given: Int"), "{}", err);
+        assert!(eval("println(...\"ab\")").is_err());
+    }
+
+    #[test]
+    fn arithmetic_errors_name_the_bad_operands() {
+        let message = |source: &str| eval(source).unwrap_err().message().to_string();
+        assert_eq!("operator '-' requires numbers, but right is string", message("1 - \"x\""));
+        assert_eq!("operator '-' requires numbers, but left is string", message("\"x\" - 1"));
+        assert_eq!("operator '-' requires numbers, but left is string and right is string", message("\"a\" - \"b\""));
+        assert_eq!("operator '*' requires numbers, but left is bool and right is nil", message("true * nil"));
+        assert_eq!("operator '+' requires numbers, but right is string. Use '++' to concatenate strings", message("1.5 + \"x\""));
+    }
+
+    #[test]
+    fn guard_returns_early() {
+        let mut env = run("
+            let log = []
+            func half(n) {
+                guard n % 2 == 0 else {
+                    return nil
+                }
+                push(log, n)
+                return n / 2
+            }
+            let odd = half(7)
+            let even = half(8)
+        ").unwrap();
+        assert_eq!(Value::Nil, lookup(&mut env, "odd"));
+        assert_eq!(Value::INT(4), lookup(&mut env, "even"));
+        assert_eq!("[8]", lookup(&mut env, "log").to_string());
+
+        assert_eq!(Value::INT(3), eval("let i = 0; while true { i = i + 1; guard i < 3 else { break; } } i").unwrap());
+        assert!(eval("guard false else { throw \"stop\"; }").is_err());
+    }
+
+    #[test]
+    fn tail_calls_run_in_constant_stack() {
+        let source = "
+            func countdown(n, total) {
+                if n == 0 {
+                    return total
+                }
+                return countdown(n - 1, total + 1)
+            }
+            countdown(100000, 0)";
+        assert_eq!(Value::INT(100000), eval(source).unwrap());
+
+        // Returning from inside try still catches the call's errors
+        let source = "
+            func f(n) {
+                if n == 0 {
+                    throw \"done\"
+                }
+                try {
+                    return f(n - 1)
+                } catch (e) {
+                    return e ++ \"!\"
+                }
+            }
+            f(3)";
+        assert_eq!(Value::STRING("done!".to_string()), eval(source).unwrap());
+    }
+
+    #[test]
+    fn deep_recursion_is_an_error() {
+        let source = "
+            func sum(n) {
+                if n == 0 {
+                    return 0
+                }
+                return n + sum(n - 1)
+            }
+            sum(100000)";
+        // Test threads have a small stack, so run on one like main does
+        std::thread::Builder::new().stack_size(STACK_SIZE).spawn(move || {
+            let err = eval(source).unwrap_err();
+            assert!(err.message().contains("Maximum recursion depth of 1000 exceeded"), "{}", err.message());
+            assert_eq!(Value::INT(499500), eval(&source.replace("100000", "999")).unwrap());
+        }).unwrap().join().unwrap();
+    }
+
+    #[test]
+    fn arrays_print_with_brackets_or_a_separator() {
+        let interpreter = Interpreter::new();
+        let array = eval("[1, 2, 3]").unwrap();
+        assert_eq!("[1, 2, 3]", interpreter.display(&array));
+
+        assert_eq!(Value::STRING("1;2;3".to_string()), eval("join([1, 2, 3], \";\")").unwrap());
+        assert_eq!(Value::STRING("a, 2.5, [nil]".to_string()), eval("join([\"a\", 2.5, [nil]], \", \")").unwrap());
+        assert_eq!(Value::STRING("".to_string()), eval("join([], \";\")").unwrap());
+        assert_eq!(Value::Nil, eval("join_print([1, 2], \",\")").unwrap());
+
+        let err = eval("join(\"abc\", \",\")").unwrap_err();
+        assert_eq!("join expects an Array and a String separator, given: \"abc\", \",\"", err.message());
+        assert!(eval("join_print([1], 2)").is_err());
+    }
+
+    #[test]
+    fn builtin_argument_errors_keep_their_cause() {
+        let err = eval("let x = 1;\nlen(\n    x / 0\n);").unwrap_err();
+        assert_eq!("Could not evaluate the arguments to 'len'", err.message());
+        assert_eq!(Some(4), err.line());
+        let cause = err.caused_by().unwrap();
+        assert_eq!(&RuntimeError::DivideByZero(3), cause);
+        assert_eq!("[RuntimeError line 4]: Could not evaluate the arguments to 'len'\ncaused by: [RuntimeError line 3]: Cannot Divide by 0", err.to_string());
+        assert!(std::error::Error::source(&err).is_some());
+
+        // Calls to Flax functions and thrown values are passed on as they are
+        assert_eq!(RuntimeError::DivideByZero(2), eval("func f(x) { return x; }\nf(1 / 0);").unwrap_err());
+        assert!(matches!(eval("len({ throw 1; });").unwrap_err(), RuntimeError::Throw(..)));
+        let mut env = run("let caught = nil;\ntry {\n    len(1 / 0);\n} catch (e) {\n    caught = e;\n}").unwrap();
+        assert_eq!(Value::STRING("Cannot Divide by 0".to_string()), lookup(&mut env, "caught"));
+
+        // Builtins nested in builtins report once, at the outermost call
+        let err = eval("println(to_json(1 / 0));").unwrap_err();
+        assert_eq!("[RuntimeError line 1]: Could not evaluate the arguments to 'println'\ncaused by: [RuntimeError line 1]: Cannot Divide by 0", err.to_string());
+        assert_eq!(&RuntimeError::DivideByZero(1), err.root_cause());
+    }
+
+    #[test]
+    fn match_on_type() {
+        let source = "func describe(x) {\n    return match x {\n        number => \"number \" ++ str(x),\n        string => \"string \" ++ x,\n        nil => \"nothing\",\n        _ => \"other \" ++ typeof(x),\n    };\n}\n";
+        let describe = |value: &str| eval(&format!("{}describe({});", source, value)).unwrap();
+        assert_eq!(Value::STRING("number 4".to_string()), describe("4"));
+        assert_eq!(Value::STRING("number 2.5".to_string()), describe("2.5"));
+        assert_eq!(Value::STRING("string hi".to_string()), describe("\"hi\""));
+        assert_eq!(Value::STRING("nothing".to_string()), describe("nil"));
+        assert_eq!(Value::STRING("other array".to_string()), describe("[1]"));
+
+        // Only the matching arm is evaluated
+        assert_eq!(Value::INT(1), eval("match true { bool => 1, _ => 1 / 0 }").unwrap());
+        assert_eq!(Value::STRING("int".to_string()), eval("typeof(3)").unwrap());
+
+        let err = eval("match \"s\" { number => 1, nil => 2 }").unwrap_err();
+        assert_eq!("No match arm for string and no '_' arm", err.message());
+    }
+
+    // Collects what a script prints so tests can check it
+    #[derive(Clone, Default)]
+    struct Captured(Rc<RefCell<Vec<u8>>>);
+
+    impl io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Captured {
+        fn text(&self) -> String {
+            String::from_utf8(self.0.borrow().clone()).unwrap()
+        }
+    }
+
+    #[test]
+    fn write_leaves_out_the_newline() {
+        let output = Captured::default();
+        let mut interpreter = Interpreter::new();
+        interpreter.output = Box::new(output.clone());
+        eval_with(interpreter, "write(\"a\");\nwrite(\"b\");\nprint(1);\nprintln([2]);\nwrite(\"c\");").unwrap();
+        assert_eq!("ab1\n[2]\nc", output.text());
+    }
+
+    #[test]
+    fn string_checks() {
+        assert_eq!(Value::BOOL(true), eval("is_number(\"3.14\")").unwrap());
+        assert_eq!(Value::BOOL(true), eval("is_number(\"-2e10\")").unwrap());
+        assert_eq!(Value::BOOL(false), eval("is_number(\"3.x\")").unwrap());
+        assert_eq!(Value::BOOL(false), eval("is_number(\"inf\")").unwrap());
+        assert_eq!(Value::BOOL(false), eval("is_number(\"\")").unwrap());
+        assert_eq!(Value::BOOL(true), eval("is_digit(\"0123\")").unwrap());
+        assert_eq!(Value::BOOL(false), eval("is_digit(\"1.5\")").unwrap());
+        assert_eq!(Value::BOOL(true), eval("is_alpha(\"abc\")").unwrap());
+        assert_eq!(Value::BOOL(true), eval("is_alpha(\"école\")").unwrap());
+        assert_eq!(Value::BOOL(false), eval("is_alpha(\"a b\")").unwrap());
+
+        let err = eval("is_alpha(12)").unwrap_err();
+        assert_eq!("is_alpha expects String, given: 12", err.message());
+    }
+
+    #[test]
+    fn bitwise_not() {
+        assert_eq!(Value::INT(-1), eval("~0").unwrap());
+        assert_eq!(Value::INT(-6), eval("~5").unwrap());
+        assert_eq!(Value::INT(5), eval("~~5").unwrap());
+        assert_eq!(Value::NUMBER(-3.0), eval("~2.0").unwrap());
+        assert_eq!(Value::INT(-10), eval("~3 * 2 - 2").unwrap());
+
+        let err = eval("~\"x\"").unwrap_err();
+        assert_eq!("'~' expects an integer, given: \"x\"", err.message());
+        assert!(eval("~1.5").is_err());
+        assert!(eval("~nil").is_err());
+    }
+
+    #[test]
+    fn zip_and_enumerate() {
+        assert_eq!("[[1, 3], [2, 4]]", eval("zip([1, 2], [3, 4])").unwrap().to_string());
+        assert_eq!("[[1, \"a\"]]", eval("zip([1, 2, 3], [\"a\"])").unwrap().to_string());
+        assert_eq!("[]", eval("zip([], [1])").unwrap().to_string());
+        assert_eq!("[[0, \"a\"], [1, \"b\"]]", eval("enumerate([\"a\", \"b\"])").unwrap().to_string());
+
+        let err = eval("zip([1], \"ab\")").unwrap_err();
+        assert_eq!("zip expects two Arrays, given: [1], \"ab\"", err.message());
+        assert_eq!("enumerate expects an Array, given: nil", eval("enumerate(nil)").unwrap_err().message());
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let source = "let data = {\"name\": \"flax\", \"tags\": [1, 2.5, true, nil], \"nested\": {\"empty\": []}};\nlet text = to_json(data);\nlet back = from_json(text);";
+        let mut env = run(source).unwrap();
+        assert_eq!(Value::STRING("{\"name\": \"flax\", \"nested\": {\"empty\": []}, \"tags\": [1, 2.5, true, null]}".to_string()), lookup(&mut env, "text"));
+        assert_eq!(Value::BOOL(true), eval(&format!("{}\ndeep_equal(data, back)", source)).unwrap());
+        assert_eq!(Value::BOOL(true), eval("deep_equal([1, 2, 3], from_json(\" [1, 2, 3] \"))").unwrap());
+        assert_eq!(Value::NUMBER(1.0), eval("from_json(\"1.0\")").unwrap());
+
+        let err = eval("from_json(\"[1, 2\")").unwrap_err();
+        assert!(err.message().starts_with("from_json was given invalid JSON"), "{}", err);
+        assert!(eval("from_json(1)").is_err());
+        let err = eval("to_json([1, len])").unwrap_err();
+        assert_eq!("to_json can't convert a Function to JSON, given: <fn len>", err.message());
+    }
+
+    #[test]
+    fn env_and_args_read_what_was_given() {
+        let mut interpreter = Interpreter::new();
+        interpreter.env_vars = Box::new(|name| match name {
+            "HOME" => Some("/home/flax".to_string()),
+            _ => None,
+        });
+        interpreter.script_args = vec!["input.txt".to_string(), "-v".to_string()];
+        let mut env = interpreter.globals.clone();
+        let statements = Parser::new(lex_source("let home = env(\"HOME\");\nlet missing = env(\"NOT_SET\");\nlet given = deep_equal(args(), [\"input.txt\", \"-v\"]);").unwrap()).parse().unwrap();
+        interpreter.run_statements(&statements, &mut env).unwrap();
+
+        assert_eq!(Value::STRING("/home/flax".to_string()), lookup(&mut env, "home"));
+        assert_eq!(Value::Nil, lookup(&mut env, "missing"));
+        assert_eq!(Value::BOOL(true), lookup(&mut env, "given"));
+
+        assert_eq!(Value::BOOL(true), eval("deep_equal(args(), [])").unwrap());
+        assert_eq!("env expects a String, given: 1", eval("env(1)").unwrap_err().message());
+    }
+
+    #[test]
+    fn defers_run_when_their_block_exits() {
+        let source = "let log = [];
+{
+    defer push(log, \"first\");
+    defer push(log, \"second\");
+    push(log, \"body\");
+}
+let in_order = deep_equal(log, [\"body\", \"second\", \"first\"]);";
+        let mut env = run(source).unwrap();
+        assert_eq!(Value::BOOL(true), lookup(&mut env, "in_order"));
+
+        // A thrown error still runs the defer before the catch sees it
+        let source = "let log = [];
+try {
+    defer push(log, \"cleanup\");
+    throw \"oops\";
+    push(log, \"unreachable\");
+} catch (e) {
+    push(log, e);
+}
+let in_order = deep_equal(log, [\"cleanup\", \"oops\"]);";
+        let mut env = run(source).unwrap();
+        assert_eq!(Value::BOOL(true), lookup(&mut env, "in_order"));
+
+        // So does returning early from a function, after the returned value is worked out
+        let source = "let log = [];
+func f() {
+    defer push(log, \"done\");
+    return deep_equal(log, []);
+}
+let returned = f();
+let in_order = deep_equal(log, [\"done\"]);";
+        let mut env = run(source).unwrap();
+        assert_eq!(Value::BOOL(true), lookup(&mut env, "returned"));
+        assert_eq!(Value::BOOL(true), lookup(&mut env, "in_order"));
+        // A call in tail position waits for the function's defers, same as any other call
+        let source = "let log = [];
+func f(n) {
+    defer push(log, n);
+    if n == 0 {
+        return 0;
+    }
+    return f(n - 1);
+}
+f(2);
+let in_order = deep_equal(log, [0, 1, 2]);";
+        let mut env = run(source).unwrap();
+        assert_eq!(Value::BOOL(true), lookup(&mut env, "in_order"));
+        // A failing defer isn't hidden by a 'return' or 'break'
+        assert_eq!(RuntimeError::DivideByZero(2), eval("func h() {\n    defer 1 / 0;\n    return 1;\n}\nh();").unwrap_err().untraced());
+        assert_eq!(RuntimeError::DivideByZero(3), eval("while true {\n    {\n        defer 1 / 0;\n        break;\n    }\n}").unwrap_err());
+        // But the block's own failure is kept over the defer's
+        let err = eval("{\n    defer 1 / 0;\n    throw \"first\";\n}").unwrap_err();
+        assert_eq!(RuntimeError::Throw(Value::STRING("first".to_string()), 3), err);
+    }
+
+    #[test]
+    fn builtins_are_named_after_their_globals() {
+        let mut interpreter = Interpreter::new();
+        let names = interpreter.globals.names();
+        assert!(names.contains(&String::from("subString")));
+        for name in names {
+            match lookup(&mut interpreter.globals, &name) {
+                Value::Callable(func) => assert_eq!(name, func.name()),
+                other => panic!("{} is not a builtin: {:?}", name, other),
+            }
         }
+        assert_eq!(Value::STRING(String::from("subString")), eval("name(subString)").unwrap());
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,323 @@
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
+
+// A small JSON reader and writer, enough for exchanging data with other tools.
+// Object keys keep the order they were written in.
+//
+// Integers and floats are kept apart so numbers survive a round trip through Flax:
+// 1 reads as an Int and 1.0 as a Float, and they are written back the same way
+
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    // Builds an object from (key, value) pairs
+    pub fn object(entries: Vec<(&str, Json)>) -> Json {
+        Json::Object(entries.into_iter().map(|(key, value)| (key.to_string(), value)).collect())
+    }
+
+    pub fn string(s: &str) -> Json {
+        Json::String(s.to_string())
+    }
+
+    // The value of a key, when this is an object that has it
+    #[allow(dead_code)]
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    // Written over several lines, indented two spaces per level
+    pub fn pretty(&self) -> String {
+        let mut out = String::new();
+        self.write_pretty(&mut out, 0);
+        out
+    }
+
+    fn write_pretty(&self, out: &mut String, depth: usize) {
+        let indent = "  ".repeat(depth + 1);
+        match self {
+            Json::Array(items) if !items.is_empty() => {
+                out.push_str("[\n");
+                for (i, item) in items.iter().enumerate() {
+                    out.push_str(&indent);
+                    item.write_pretty(out, depth + 1);
+                    out.push_str(if i + 1 < items.len() { ",\n" } else { "\n" });
+                }
+                out.push_str(&"  ".repeat(depth));
+                out.push(']');
+            },
+            Json::Object(entries) if !entries.is_empty() => {
+                out.push_str("{\n");
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    out.push_str(&indent);
+                    out.push_str(&quote(key));
+                    out.push_str(": ");
+                    value.write_pretty(out, depth + 1);
+                    out.push_str(if i + 1 < entries.len() { ",\n" } else { "\n" });
+                }
+                out.push_str(&"  ".repeat(depth));
+                out.push('}');
+            },
+            json => out.push_str(&json.to_string()),
+        }
+    }
+}
+
+// The compact form, all on one line
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<>) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Int(n) => write!(f, "{}", n),
+            // JSON has no NaN or infinity
+            Json::Float(n) if !n.is_finite() => write!(f, "null"),
+            Json::Float(n) if n.fract() == 0.0 && n.abs() < 1e16 => write!(f, "{:.1}", n),
+            Json::Float(n) => write!(f, "{}", n),
+            Json::String(s) => write!(f, "{}", quote(s)),
+            Json::Array(items) => {
+                let items: Vec<String> = items.iter().map(|item| item.to_string()).collect();
+                write!(f, "[{}]", items.join(", "))
+            },
+            Json::Object(entries) => {
+                let entries: Vec<String> = entries.iter().map(|(k, v)| format!("{}: {}", quote(k), v)).collect();
+                write!(f, "{{{}}}", entries.join(", "))
+            },
+        }
+    }
+}
+
+fn quote(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+
+// Reads JSON text. The error says what was wrong
+pub fn parse(text: &str) -> Result<Json, String> {
+    let mut it = text.chars().peekable();
+    let json = parse_value(&mut it)?;
+    skip_whitespace(&mut it);
+    match it.next() {
+        None => Ok(json),
+        Some(c) => Err(format!("Unexpected '{}' after JSON value", c)),
+    }
+}
+
+fn skip_whitespace(it: &mut Peekable<Chars>) {
+    while it.next_if(|c| matches!(c, ' ' | '\t' | '\n' | '\r')).is_some() {}
+}
+
+fn expect(it: &mut Peekable<Chars>, word: &str, json: Json) -> Result<Json, String> {
+    for expected in word.chars() {
+        if it.next() != Some(expected) {
+            return Err(format!("Invalid literal, expected '{}'", word));
+        }
+    }
+    Ok(json)
+}
+
+fn parse_value(it: &mut Peekable<Chars>) -> Result<Json, String> {
+    skip_whitespace(it);
+    match it.peek() {
+        Some('n') => expect(it, "null", Json::Null),
+        Some('t') => expect(it, "true", Json::Bool(true)),
+        Some('f') => expect(it, "false", Json::Bool(false)),
+        Some('"') => parse_string(it).map(Json::String),
+        Some('[') => parse_array(it),
+        Some('{') => parse_object(it),
+        Some('-') | Some('0'..='9') => parse_number(it),
+        Some(c) => Err(format!("Unexpected '{}'", c)),
+        None => Err(String::from("Unexpected end of JSON")),
+    }
+}
+
+fn parse_array(it: &mut Peekable<Chars>) -> Result<Json, String> {
+    it.next(); // eat the '['
+    let mut items = Vec::new();
+    skip_whitespace(it);
+    if it.next_if_eq(&']').is_some() {
+        return Ok(Json::Array(items));
+    }
+    loop {
+        items.push(parse_value(it)?);
+        skip_whitespace(it);
+        match it.next() {
+            Some(',') => continue,
+            Some(']') => return Ok(Json::Array(items)),
+            _ => return Err(String::from("Expected ',' or ']' in array")),
+        }
+    }
+}
+
+fn parse_object(it: &mut Peekable<Chars>) -> Result<Json, String> {
+    it.next(); // eat the '{'
+    let mut entries = Vec::new();
+    skip_whitespace(it);
+    if it.next_if_eq(&'}').is_some() {
+        return Ok(Json::Object(entries));
+    }
+    loop {
+        skip_whitespace(it);
+        if it.peek() != Some(&'"') {
+            return Err(String::from("Expected a string key in object"));
+        }
+        let key = parse_string(it)?;
+        skip_whitespace(it);
+        if it.next() != Some(':') {
+            return Err(String::from("Expected ':' after object key"));
+        }
+        entries.push((key, parse_value(it)?));
+        skip_whitespace(it);
+        match it.next() {
+            Some(',') => continue,
+            Some('}') => return Ok(Json::Object(entries)),
+            _ => return Err(String::from("Expected ',' or '}' in object")),
+        }
+    }
+}
+
+fn parse_string(it: &mut Peekable<Chars>) -> Result<String, String> {
+    it.next(); // eat the '"'
+    let mut s = String::new();
+    loop {
+        match it.next() {
+            Some('"') => return Ok(s),
+            Some('\\') => match it.next() {
+                Some('"') => s.push('"'),
+                Some('\\') => s.push('\\'),
+                Some('/') => s.push('/'),
+                Some('b') => s.push('\u{8}'),
+                Some('f') => s.push('\u{c}'),
+                Some('n') => s.push('\n'),
+                Some('r') => s.push('\r'),
+                Some('t') => s.push('\t'),
+                Some('u') => s.push(parse_unicode_escape(it)?),
+                _ => return Err(String::from("Invalid escape in string")),
+            },
+            Some(c) if (c as u32) < 0x20 => return Err(String::from("Control character in string")),
+            Some(c) => s.push(c),
+            None => return Err(String::from("Unterminated string")),
+        }
+    }
+}
+
+// The character after a '\u'. Characters outside the Basic Multilingual Plane are
+// written as two escapes (a surrogate pair)
+fn parse_unicode_escape(it: &mut Peekable<Chars>) -> Result<char, String> {
+    let high = parse_hex4(it)?;
+    let code = if (0xD800..0xDC00).contains(&high) {
+        if it.next() != Some('\\') || it.next() != Some('u') {
+            return Err(String::from("Unpaired surrogate in string"));
+        }
+        let low = parse_hex4(it)?;
+        if !(0xDC00..0xE000).contains(&low) {
+            return Err(String::from("Unpaired surrogate in string"));
+        }
+        0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+    } else {
+        high
+    };
+    char::from_u32(code).ok_or_else(|| String::from("Invalid unicode escape in string"))
+}
+
+fn parse_hex4(it: &mut Peekable<Chars>) -> Result<u32, String> {
+    let mut code = 0;
+    for _ in 0..4 {
+        let digit = it.next().and_then(|c| c.to_digit(16)).ok_or("Invalid unicode escape in string")?;
+        code = code * 16 + digit;
+    }
+    Ok(code)
+}
+
+fn parse_number(it: &mut Peekable<Chars>) -> Result<Json, String> {
+    let mut num = String::new();
+    while let Some(c) = it.next_if(|c| matches!(c, '-' | '+' | '.' | 'e' | 'E' | '0'..='9')) {
+        num.push(c);
+    }
+    let is_float = num.contains(['.', 'e', 'E']);
+    let digits = num.strip_prefix('-').unwrap_or(&num);
+    // JSON doesn't allow leading zeros, a leading '.' or a trailing '.'
+    let leading_zero = digits.len() > 1 && digits.starts_with('0') && digits[1..].starts_with(|c: char| c.is_ascii_digit());
+    let malformed = !digits.starts_with(|c: char| c.is_ascii_digit()) || leading_zero
+        || num.contains(".e") || num.contains(".E") || num.ends_with('.');
+    if malformed {
+        return Err(format!("Invalid number '{}'", num));
+    }
+    if !is_float {
+        if let Ok(n) = num.parse::<i64>() {
+            return Ok(Json::Int(n));
+        }
+    }
+    num.parse::<f64>().map(Json::Float).map_err(|_| format!("Invalid number '{}'", num))
+}
+
+
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn writes_json() {
+        let json = Json::object(vec![
+            ("name", Json::string("say \"hi\"\n")),
+            ("sizes", Json::Array(vec![Json::Int(1), Json::Float(2.0), Json::Float(0.5), Json::Null])),
+            ("empty", Json::Object(Vec::new())),
+        ]);
+        assert_eq!("{\"name\": \"say \\\"hi\\\"\\n\", \"sizes\": [1, 2.0, 0.5, null], \"empty\": {}}", json.to_string());
+        assert_eq!("{\n  \"name\": \"say \\\"hi\\\"\\n\",\n  \"sizes\": [\n    1,\n    2.0,\n    0.5,\n    null\n  ],\n  \"empty\": {}\n}", json.pretty());
+    }
+
+    #[test]
+    fn reads_what_it_writes() {
+        let json = Json::object(vec![
+            ("text", Json::string("tab\t \u{e9} \u{1F600} \u{1}")),
+            ("numbers", Json::Array(vec![Json::Int(-3), Json::Float(1e300), Json::Float(-0.25)])),
+            ("nested", Json::object(vec![("ok", Json::Bool(true)), ("list", Json::Array(Vec::new()))])),
+        ]);
+        assert_eq!(Ok(json.clone()), parse(&json.to_string()));
+        assert_eq!(Ok(json.clone()), parse(&json.pretty()));
+    }
+
+    #[test]
+    fn reads_escapes_and_numbers() {
+        assert_eq!(Ok(Json::string("\u{1F600}/")), parse("\"\\ud83d\\ude00\\/\""));
+        assert_eq!(Ok(Json::Float(1500.0)), parse(" 1.5e3 "));
+        assert_eq!(Ok(Json::Int(0)), parse("0"));
+    }
+
+    #[test]
+    fn rejects_malformed_json() {
+        let malformed = ["", "[1, 2", "{\"a\" 1}", "{a: 1}", "tru", "01", "1.", ".5", "\"abc", "[1,]", "1 2", "\"\\ud83d\""];
+        for text in malformed.iter() {
+            assert!(parse(text).is_err(), "{} should not parse", text);
+        }
+    }
+}
@@ -1,6 +1,7 @@
 use std::iter::Peekable;
 use std::fmt;
 use std::fs::File;
+use std::io;
 use std::io::BufReader;
 use std::io::prelude::*;
 use crate::errors::LexError;
@@ -8,15 +9,15 @@ use crate::errors::LexError;
 #[derive(PartialEq, Debug, Clone)]
 pub enum TokenType {
     // operators 
-    Plus, Minus, Star, Slash, EqualEqual, Equal, PlusPlus, Greater, Less,
+    Plus, Minus, Tilde, Star, StarStar, Slash, Percent, EqualEqual, Equal, PlusPlus, Greater, Less,
      GreaterEqual, LessEqual, Bang, BangEqual, Semicolon, Colon, Question,
-     PlusEqual, MinusEqual, Comma,
+     PlusEqual, MinusEqual, Comma, Dot, DotDotDot, Arrow,
 
     // Grouping
-    LeftParen, RightParen, LeftBrace, RightBrace,
+    LeftParen, RightParen, LeftBrace, RightBrace, LeftBracket, RightBracket,
 
     // Reserved Identifiers
-    Identifier, Let, If, Else, And, Or, While, Break, Func, Return,
+    Identifier, Let, Const, If, Guard, Match, Else, And, Or, Not, While, Break, Func, Return, Import, Try, Catch, Throw, Defer,
 
     // Prims
     NUMBER, STRING, TRUE, FALSE, Nil,
@@ -32,75 +33,182 @@ pub struct Token {
     pub line: u64,
 }
 
+// The type, lexeme and line, like "Minus '-' @line 3"
 impl fmt::Display for Token {
     fn fmt(&self, f: &mut fmt::Formatter<>) -> fmt::Result {
-        write!(f, "'{}'", self.lexeme)
+        write!(f, "{:?} '{}' @line {}", self.token_type, self.lexeme, self.line)
     }
 }
 
+// One token per line, for looking at what the Lexer made of some source
+pub fn dump_tokens(tokens: &[Token]) -> String {
+    tokens.iter().map(|token| token.to_string()).collect::<Vec<String>>().join("\n")
+}
+
 impl Token {
     pub fn new(token_type: TokenType, lexeme: String, line: u64) -> Token {
         Token { token_type, lexeme, line }
     }
 }
 
+// The lexers report every bad token they find, not just the first
 #[allow(dead_code)]
-pub fn lex_file(filename: &str) -> Result<Vec<Token>, LexError> {
+pub fn lex_file(filename: &str) -> Result<Vec<Token>, Vec<LexError>> {
     let file = File::open(filename).expect("Unable to file file");
     let buf_reader = BufReader::new(file);
+    lex_lines(buf_reader.lines())
+}
+
+// Lexes a multi-line source string the same way lex_file lexes a file
+pub fn lex_source(source: &str) -> Result<Vec<Token>, Vec<LexError>> {
+    lex_lines(source.lines().map(|line| Ok(line.to_string())))
+}
+
+fn lex_lines<I: Iterator<Item=io::Result<String>>>(lines: I) -> Result<Vec<Token>, Vec<LexError>> {
     let mut tokens = Vec::new();
+    let mut errors = Vec::new();
     let mut max = 0;
 
-    for (i, val) in buf_reader.lines().enumerate() {
+    for (i, val) in lines.enumerate() {
         match val {
-            Ok(line) => tokens.append(&mut lex(line, (i + 1) as u64)?),
-            Err(e) => return Err(LexError::new(i as u64, format!("Error reading from file: {}", e))),
+            // A leading '#!' line lets scripts run with `#!/usr/bin/env flax`. It is
+            // skipped but still counted, so the next line is line 2
+            Ok(ref line) if i == 0 && line.starts_with("#!") => (),
+            Ok(line) => match lex(line, (i + 1) as u64) {
+                Ok(mut line_tokens) => tokens.append(&mut line_tokens),
+                Err(mut line_errors) => errors.append(&mut line_errors),
+            },
+            Err(e) => {
+                errors.push(LexError::new((i + 1) as u64, 1, format!("Error reading from file: {}", e)));
+                return Err(errors);
+            },
         }
         max = i;
     }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
     // Add EOF: 1 is a dummy value that is never used
     tokens.push(Token::new(TokenType::EOF, String::new(), max as u64));
     Ok(tokens)
 }
 
 
-pub fn lex_line(line: String) -> Result<Vec<Token>, LexError> {
+// Lexes a single line of source
+#[cfg(test)]
+pub fn lex_line(line: String) -> Result<Vec<Token>, Vec<LexError>> {
     let mut tokens = lex(line, 1)?;
     tokens.push(Token::new(TokenType::EOF, String::new(), 1));
     Ok(tokens)
 }
 
-fn lex(line: String, line_num: u64) -> Result<Vec<Token>, LexError> {
+// A token along with the whitespace and comments (trivia) in front of it, for tools
+// like formatters that need to keep them. Line breaks are part of the trivia
+#[derive(PartialEq, Debug, Clone)]
+pub struct TriviaToken {
+    pub token: Token,
+    pub leading_trivia: String,
+}
+
+// Lexes the source like lex_source, but keeps the trivia the parser doesn't need.
+// Trivia at the end of the source is attached to the EOF token
+pub fn scan_with_trivia(source: &str) -> Result<Vec<TriviaToken>, Vec<LexError>> {
     let mut tokens = Vec::new();
+    let mut errors = Vec::new();
+    let mut trivia = String::new();
+    let mut max = 0;
+
+    for (i, line) in source.lines().enumerate() {
+        let line_num = (i + 1) as u64;
+        if i > 0 {
+            trivia.push('\n');
+        }
+        if i == 0 && line.starts_with("#!") {
+            trivia.push_str(line);
+            continue;
+        }
+        let chars: Vec<char> = line.chars().collect();
+        let mut it = chars.iter().copied().peekable();
+        let mut line_tokens = Vec::new();
+        while it.peek().is_some() {
+            let start = chars.len() - it.len();
+            if let Err(msg) = lex_token(line_num, &mut line_tokens, &mut it) {
+                errors.push(LexError::new(line_num, start + 1, msg));
+            }
+            let end = chars.len() - it.len();
+            match line_tokens.pop() {
+                Some(token) => tokens.push(TriviaToken { token, leading_trivia: std::mem::take(&mut trivia) }),
+                None => trivia.extend(&chars[start..end]),
+            }
+        }
+        max = i;
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    tokens.push(TriviaToken { token: Token::new(TokenType::EOF, String::new(), max as u64), leading_trivia: trivia });
+    Ok(tokens)
+}
+
+// Lexes a line, skipping past any bad tokens so the rest of the line is still checked
+fn lex(line: String, line_num: u64) -> Result<Vec<Token>, Vec<LexError>> {
+    let mut tokens = Vec::new();
+    let mut errors = Vec::new();
+    let length = line.chars().count();
     let mut it = line.chars().peekable();
 
-    while let Some(c) = it.peek() {
+    while it.peek().is_some() {
+        let start = it.clone();
+        if let Err(msg) = lex_token(line_num, &mut tokens, &mut it) {
+            let column = length - start.count() + 1;
+            errors.push(LexError::new(line_num, column, msg));
+        }
+    }
+    if errors.is_empty() {
+        Ok(tokens)
+    } else {
+        Err(errors)
+    }
+}
+
+// Lexes the token at the front of the iterator. Errors are just the message, lex adds the position
+fn lex_token<I: Iterator<Item=char> + Clone>(line_num: u64, tokens: &mut Vec<Token>, it: &mut Peekable<I>) -> Result<(), String> {
+    if let Some(c) = it.peek() {
         match c {
-            '0'..='9' => add_token(get_number(line_num, &mut it), &mut tokens),
-            '"' => add_token(get_string(line_num, &mut it)?, &mut tokens),
-            '(' => add_and_consume(Token::new(TokenType::LeftParen, c.to_string(), line_num), &mut tokens, &mut it),
-            ')' => add_and_consume(Token::new(TokenType::RightParen, c.to_string(), line_num), &mut tokens, &mut it),
-            '{' => add_and_consume(Token::new(TokenType::LeftBrace, c.to_string(), line_num), &mut tokens, &mut it),
-            '}' => add_and_consume(Token::new(TokenType::RightBrace, c.to_string(), line_num), &mut tokens, &mut it),
-            '*' => add_and_consume(Token::new(TokenType::Star, c.to_string(), line_num), &mut tokens, &mut it),
-            //'/' => add_and_consume(Token::new(TokenType::Slash, c.to_string(), line_num), &mut tokens, &mut it),
-            '/' => determine_comments(*c, &mut tokens, line_num, &mut it)?,
-            ';' => add_and_consume(Token::new(TokenType::Semicolon, c.to_string(), line_num), &mut tokens, &mut it),
-            ':' => add_and_consume(Token::new(TokenType::Colon, c.to_string(), line_num), &mut tokens, &mut it),
-            '?' => add_and_consume(Token::new(TokenType::Question, c.to_string(), line_num), &mut tokens, &mut it),
-            ',' => add_and_consume(Token::new(TokenType::Comma, c.to_string(), line_num), &mut tokens, &mut it),
-            '-' => check_ahead_and_add(&mut tokens, line_num, &mut it)?,
-            '+' => check_ahead_and_add(&mut tokens, line_num, &mut it)?,
-            '=' => check_ahead_and_add(&mut tokens, line_num, &mut it)?,
-            '!' => check_ahead_and_add(&mut tokens, line_num, &mut it)?,
-            '>' => check_ahead_and_add(&mut tokens, line_num, &mut it)?,
-            '<' => check_ahead_and_add(&mut tokens, line_num, &mut it)?,
-            'A'..='Z' | 'a'..='z' => add_identifier(&mut tokens, line_num, &mut it)?,
+            '0'..='9' => add_token(get_number(line_num, it)?, tokens),
+            '"' => add_token(get_string(line_num, it)?, tokens),
+            '(' => add_and_consume(Token::new(TokenType::LeftParen, c.to_string(), line_num), tokens, it),
+            ')' => add_and_consume(Token::new(TokenType::RightParen, c.to_string(), line_num), tokens, it),
+            '{' => add_and_consume(Token::new(TokenType::LeftBrace, c.to_string(), line_num), tokens, it),
+            '}' => add_and_consume(Token::new(TokenType::RightBrace, c.to_string(), line_num), tokens, it),
+            '[' => add_and_consume(Token::new(TokenType::LeftBracket, c.to_string(), line_num), tokens, it),
+            ']' => add_and_consume(Token::new(TokenType::RightBracket, c.to_string(), line_num), tokens, it),
+            '*' => add_star(tokens, line_num, it),
+            '%' => add_and_consume(Token::new(TokenType::Percent, c.to_string(), line_num), tokens, it),
+            '~' => add_and_consume(Token::new(TokenType::Tilde, c.to_string(), line_num), tokens, it),
+            //'/' => add_and_consume(Token::new(TokenType::Slash, c.to_string(), line_num), tokens, it),
+            '/' => determine_comments(*c, tokens, line_num, it)?,
+            ';' => add_and_consume(Token::new(TokenType::Semicolon, c.to_string(), line_num), tokens, it),
+            ':' => add_and_consume(Token::new(TokenType::Colon, c.to_string(), line_num), tokens, it),
+            '?' => add_and_consume(Token::new(TokenType::Question, c.to_string(), line_num), tokens, it),
+            ',' => add_and_consume(Token::new(TokenType::Comma, c.to_string(), line_num), tokens, it),
+            '.' => add_dot(tokens, line_num, it),
+            '-' => check_ahead_and_add(tokens, line_num, it)?,
+            '+' => check_ahead_and_add(tokens, line_num, it)?,
+            '=' => check_ahead_and_add(tokens, line_num, it)?,
+            '!' => check_ahead_and_add(tokens, line_num, it)?,
+            '>' => check_ahead_and_add(tokens, line_num, it)?,
+            '<' => check_ahead_and_add(tokens, line_num, it)?,
+            'A'..='Z' | 'a'..='z' | '_' => add_identifier(tokens, line_num, it)?,
             ' ' => {it.next();},
-            _ => return Err(LexError::new(line_num, format!("Invalid Character '{}'", c))),
+            _ => {
+                let msg = format!("Invalid Character '{}'", c);
+                it.next();
+                return Err(msg);
+            },
         }
     }
-    Ok(tokens)
+    Ok(())
 }
 
 
@@ -109,9 +217,19 @@ fn add_and_consume<I: Iterator<Item=char>>(token: Token, v: &mut Vec<Token>, it:
     it.next();
 }
 
+// '*' is multiplication and '**' is exponentiation
+fn add_star<I: Iterator<Item=char>>(tokens: &mut Vec<Token>, line_num: u64, it: &mut Peekable<I>) {
+    it.next(); // eat the first '*'
+    if it.next_if_eq(&'*').is_some() {
+        tokens.push(Token::new(TokenType::StarStar, String::from("**"), line_num));
+    } else {
+        tokens.push(Token::new(TokenType::Star, String::from("*"), line_num));
+    }
+}
+
 
 
-fn determine_comments<I: Iterator<Item=char>>(c: char, tokens: &mut Vec<Token>, line_num: u64, it: &mut Peekable<I>) -> Result<(), LexError> {
+fn determine_comments<I: Iterator<Item=char>>(c: char, tokens: &mut Vec<Token>, line_num: u64, it: &mut Peekable<I>) -> Result<(), String> {
     it.next();
     if let Some(val) = it.peek() {
         return match val {
@@ -135,7 +253,20 @@ fn determine_comments<I: Iterator<Item=char>>(c: char, tokens: &mut Vec<Token>,
     Ok(())
 }
 
-fn check_ahead_and_add<I: Iterator<Item=char>>(tokens: &mut Vec<Token>, line_num: u64, it: &mut Peekable<I>) -> Result<(), LexError> {
+// '.' calls a method and '...' spreads an array
+fn add_dot<I: Iterator<Item=char> + Clone>(tokens: &mut Vec<Token>, line_num: u64, it: &mut Peekable<I>) {
+    let mut ahead = it.clone();
+    ahead.next();
+    if ahead.next() == Some('.') && ahead.next() == Some('.') {
+        *it = ahead;
+        tokens.push(Token::new(TokenType::DotDotDot, String::from("..."), line_num));
+    } else {
+        it.next();
+        tokens.push(Token::new(TokenType::Dot, String::from("."), line_num));
+    }
+}
+
+fn check_ahead_and_add<I: Iterator<Item=char>>(tokens: &mut Vec<Token>, line_num: u64, it: &mut Peekable<I>) -> Result<(), String> {
     let token: String = take_op(it);
     let t = match &token[..] {
         ">" => Token::new(TokenType::Greater, token, line_num),
@@ -151,14 +282,15 @@ fn check_ahead_and_add<I: Iterator<Item=char>>(tokens: &mut Vec<Token>, line_num
         "+=" => Token::new(TokenType::PlusEqual, token, line_num),
         "-=" => Token::new(TokenType::MinusEqual, token, line_num),
         "!=" => Token::new(TokenType::BangEqual, token, line_num),
-        _ => return Err(LexError::new(line_num, format!("Invalid Character '{}'", token))),
+        "=>" => Token::new(TokenType::Arrow, token, line_num),
+        _ => return Err(format!("Invalid Character '{}'", token)),
     };
     add_token(t, tokens);
     Ok(())
 }
 
 
-fn get_string<I: Iterator<Item=char>>(line_num: u64, it: &mut Peekable<I>) -> Result<Token, LexError> {
+fn get_string<I: Iterator<Item=char>>(line_num: u64, it: &mut Peekable<I>) -> Result<Token, String> {
     it.next(); // Consume the leading "
     let mut res = String::new();
     while let Some(c) = it.peek() {
@@ -169,7 +301,7 @@ fn get_string<I: Iterator<Item=char>>(line_num: u64, it: &mut Peekable<I>) -> Re
         res.push(*c);
         it.next();
     }
-    Err(LexError::new(line_num, res))
+    Err(format!("Unterminated string \"{}", res))
 }
 
 fn take_op<I: Iterator<Item=char>>(it: &mut Peekable<I>) -> String {
@@ -184,12 +316,13 @@ fn take_op<I: Iterator<Item=char>>(it: &mut Peekable<I>) -> String {
     return s;
 }
 
-fn add_identifier<I: Iterator<Item=char>>(tokens: &mut Vec<Token>, line_num: u64, it: &mut Peekable<I>) -> Result<(), LexError> {
+fn add_identifier<I: Iterator<Item=char>>(tokens: &mut Vec<Token>, line_num: u64, it: &mut Peekable<I>) -> Result<(), String> {
 
     let mut identifier = String::new();
     while let Some(c) = it.peek() {
         match c {
-            'A'..='Z' | 'a'..='z' => {
+            // Digits are allowed after the first character
+            'A'..='Z' | 'a'..='z' | '_' | '0'..='9' => {
                 identifier.push(*c)
             },
             _ => break,
@@ -197,7 +330,7 @@ fn add_identifier<I: Iterator<Item=char>>(tokens: &mut Vec<Token>, line_num: u64
         it.next();
     }
     if identifier == String::new() {
-        return Err(LexError::new(line_num, "Invalid identifier".to_string()));
+        return Err("Invalid identifier".to_string());
     }
     let identifier_type = determine_identifier(&identifier);
     add_token(Token::new(identifier_type, identifier, line_num), tokens);
@@ -210,14 +343,23 @@ fn determine_identifier(s: &String) -> TokenType {
         "false" => TokenType::FALSE,
         "nil"   => TokenType::Nil,
         "let"   => TokenType::Let,
+        "const" => TokenType::Const,
         "if"    => TokenType::If,
+        "guard" => TokenType::Guard,
+        "match" => TokenType::Match,
         "else"  => TokenType::Else,
         "and"   => TokenType::And,
         "or"    => TokenType::Or,
+        "not"   => TokenType::Not,
         "while" => TokenType::While,
         "break" => TokenType::Break,
         "func"  => TokenType::Func,
         "return" => TokenType::Return,
+        "import" => TokenType::Import,
+        "try"   => TokenType::Try,
+        "catch" => TokenType::Catch,
+        "throw" => TokenType::Throw,
+        "defer" => TokenType::Defer,
         _ => TokenType::Identifier,
     }
 }
@@ -227,18 +369,53 @@ fn add_token(token: Token, v: &mut Vec<Token>) {
     v.push(token);
 }
 
-fn get_number<I: Iterator<Item=char>>(line_num: u64, it: &mut Peekable<I>) -> Token {
+fn get_number<I: Iterator<Item=char> + Clone>(line_num: u64, it: &mut Peekable<I>) -> Result<Token, String> {
     let mut num = String::new();
-    while let Some(val) = it.peek() {
+    while let Some(&val) = it.peek() {
         match val {
             '0'..='9' | '.' => {
-                num.push(*val);
+                num.push(val);
+            }
+            'e' | 'E' if is_exponent(it) => {
+                num.push(val);
+                it.next();
+                // The sign (if any) belongs to the exponent
+                if let Some(sign) = it.next_if(|c| *c == '+' || *c == '-') {
+                    num.push(sign);
+                }
+                continue;
             }
             _ => break,
         }
         it.next();
     }
-    Token::new(TokenType::NUMBER, num, line_num)
+    if out_of_range(&num) {
+        return Err(format!("numeric literal out of range, given: {}", num))
+    }
+    Ok(Token::new(TokenType::NUMBER, num, line_num))
+}
+
+// Floats too big for an f64 would become infinity, and ones too small would lose
+// every digit and become 0. Malformed numbers are left for the interpreter to report
+fn out_of_range(num: &str) -> bool {
+    match num.parse::<f64>() {
+        Ok(value) if value.is_infinite() => true,
+        Ok(0.0) => {
+            let mantissa = num.split(['e', 'E']).next().unwrap_or("");
+            mantissa.chars().any(|c| ('1'..='9').contains(&c))
+        },
+        _ => false,
+    }
+}
+
+// An 'e' only starts an exponent when it is followed by digits, optionally signed
+fn is_exponent<I: Iterator<Item=char> + Clone>(it: &Peekable<I>) -> bool {
+    let mut ahead = it.clone();
+    ahead.next(); // skip the 'e'
+    if let Some('+') | Some('-') = ahead.peek() {
+        ahead.next();
+    }
+    matches!(ahead.peek(), Some('0'..='9'))
 }
 
 
@@ -292,9 +469,33 @@ mod test {
         assert_eq!(expected, tokens);
     }
 
+    #[test]
+    fn lex_scientific_numbers() {
+        let tokens = lex_line("1e10 2.5E-3 4e+2".to_string()).unwrap();
+        let expected = vec![
+            Token::new(TokenType::NUMBER, "1e10".to_string(), 1),
+            Token::new(TokenType::NUMBER, "2.5E-3".to_string(), 1),
+            Token::new(TokenType::NUMBER, "4e+2".to_string(), 1),
+            Token::new(TokenType::EOF, String::new(), 1),
+        ];
+
+        assert_eq!(expected, tokens);
+    }
+
+    #[test]
+    fn out_of_range_numbers() {
+        let errors = lex_line("1e400".to_string()).unwrap_err();
+        assert_eq!("numeric literal out of range, given: 1e400 at line: 1, column: 1", errors[0].to_string());
+        assert!(lex_line("2.5e-400".to_string()).is_err());
+
+        assert!(lex_line("1e10 0.0 0e400 1e-300".to_string()).is_ok());
+        // Integers too large for an i64 are still fine as floats
+        assert!(lex_line("99999999999999999999".to_string()).is_ok());
+    }
+
     #[test]
     fn lex_single_operators() {
-        let tokens = lex_line("() {} ; : ? * / - ,".to_string()).unwrap();
+        let tokens = lex_line("() {} ; : ? * / - , ~".to_string()).unwrap();
         let expected = vec![
             Token::new(TokenType::LeftParen, "(".to_string(), 1),
             Token::new(TokenType::RightParen, ")".to_string(), 1),
@@ -307,12 +508,28 @@ mod test {
             Token::new(TokenType::Slash, "/".to_string(), 1),
             Token::new(TokenType::Minus, "-".to_string(), 1),
             Token::new(TokenType::Comma, ",".to_string(), 1),
+            Token::new(TokenType::Tilde, "~".to_string(), 1),
             Token::new(TokenType::EOF, String::new(), 1),
         ];
 
         assert_eq!(expected, tokens);
     }
 
+    #[test]
+    fn tokens_display_their_type_and_line() {
+        assert_eq!("Minus '-' @line 3", Token::new(TokenType::Minus, "-".to_string(), 3).to_string());
+        let tokens = lex_line("x = 1.5".to_string()).unwrap();
+        assert_eq!("Identifier 'x' @line 1\nEqual '=' @line 1\nNUMBER '1.5' @line 1\nEOF '' @line 1", dump_tokens(&tokens));
+    }
+
+    #[test]
+    fn lex_dots() {
+        let tokens = lex_line("s.len() ...xs ..".to_string()).unwrap();
+        let types: Vec<TokenType> = tokens.into_iter().map(|token| token.token_type).collect();
+        assert_eq!(vec![TokenType::Identifier, TokenType::Dot, TokenType::Identifier, TokenType::LeftParen, TokenType::RightParen,
+            TokenType::DotDotDot, TokenType::Identifier, TokenType::Dot, TokenType::Dot, TokenType::EOF], types);
+    }
+
     #[test]
     fn lex_with_brackets() {
         let tokens = lex_line("((1 + 2) / 3) * 4".to_string()).unwrap();
@@ -336,7 +553,7 @@ mod test {
 
     #[test]
     fn lex_with_look_ahead() {
-        let tokens = lex_line(">= > + ++ < <= ! != = == - -= +=".to_string()).unwrap();
+        let tokens = lex_line(">= > + ++ < <= ! != = == - -= += =>".to_string()).unwrap();
         let expected = vec![
             Token::new(TokenType::GreaterEqual, ">=".to_string(), 1),
             Token::new(TokenType::Greater, ">".to_string(), 1),
@@ -351,6 +568,7 @@ mod test {
             Token::new(TokenType::Minus, "-".to_string(), 1),
             Token::new(TokenType::MinusEqual, "-=".to_string(), 1),
             Token::new(TokenType::PlusEqual, "+=".to_string(), 1),
+            Token::new(TokenType::Arrow, "=>".to_string(), 1),
             Token::new(TokenType::EOF, String::new(), 1),
         ];
         assert_eq!(expected, tokens);
@@ -408,15 +626,29 @@ mod test {
         assert_eq!(expected, tokens);
     }
 
+    #[test]
+    fn identifiers_with_underscores_and_digits() {
+        let tokens = lex_line("deep_equal _tmp x2 [".to_string()).unwrap();
+        let expected = vec![
+            Token::new(TokenType::Identifier, "deep_equal".to_string(), 1),
+            Token::new(TokenType::Identifier, "_tmp".to_string(), 1),
+            Token::new(TokenType::Identifier, "x2".to_string(), 1),
+            Token::new(TokenType::LeftBracket, "[".to_string(), 1),
+            Token::new(TokenType::EOF, String::new(), 1),
+        ];
+        assert_eq!(expected, tokens);
+    }
+
     #[test]
     fn reserved_identifiers() {
-        let tokens = lex_line("let if else and or while break func return".to_string()).unwrap();
+        let tokens = lex_line("let if else and or not while break func return".to_string()).unwrap();
         let expected = vec![
             Token::new(TokenType::Let, "let".to_string(), 1),
             Token::new(TokenType::If, "if".to_string(), 1),
             Token::new(TokenType::Else, "else".to_string(), 1),
             Token::new(TokenType::And, "and".to_string(), 1),
             Token::new(TokenType::Or, "or".to_string(), 1),
+            Token::new(TokenType::Not, "not".to_string(), 1),
             Token::new(TokenType::While, "while".to_string(), 1),
             Token::new(TokenType::Break, "break".to_string(), 1),
             Token::new(TokenType::Func, "func".to_string(), 1),
@@ -452,4 +684,64 @@ mod test {
         ];
         assert_eq!(expected, tokens);
     }
+
+    #[test]
+    fn lex_skips_shebang() {
+        let tokens = lex_source("#!/usr/bin/env flax\nlet x = 1;").unwrap();
+        assert_eq!(Token::new(TokenType::Let, "let".to_string(), 2), tokens[0]);
+        assert_eq!(6, tokens.len());
+    }
+
+    #[test]
+    fn lex_shebang_after_first_line() {
+        assert!(lex_source("let x = 1;\n#!/usr/bin/env flax").is_err());
+    }
+
+    #[test]
+    fn unterminated_string_position() {
+        let errors = lex_source("let a = 1;\nlet s = \"abc").unwrap_err();
+        assert_eq!(vec![LexError::new(2, 9, "Unterminated string \"abc".to_string())], errors);
+    }
+
+    #[test]
+    fn unexpected_character_position() {
+        let errors = lex_line("let x = @;".to_string()).unwrap_err();
+        assert_eq!(vec![LexError::new(1, 9, "Invalid Character '@'".to_string())], errors);
+        // Columns count characters, not bytes
+        let errors = lex_line("\"\u{e9}\u{e9}\" $".to_string()).unwrap_err();
+        assert_eq!(6, errors[0].column());
+    }
+
+    #[test]
+    fn every_lex_error_is_reported() {
+        let errors = lex_source("let x = @;\nlet y = 1 $ 2;\nlet z = \"oops").unwrap_err();
+        let positions: Vec<(u64, usize)> = errors.iter().map(|e| (e.line(), e.column())).collect();
+        assert_eq!(vec![(1, 9), (2, 11), (3, 9)], positions);
+        // Two bad characters on one line are both found
+        assert_eq!(2, lex_line("@ 1 + #".to_string()).unwrap_err().len());
+    }
+
+    #[test]
+    fn trivia_is_kept() {
+        let tokens = scan_with_trivia("let x = 1; // the answer\n  // is wrong\nx = 2;").unwrap();
+        let lexemes: Vec<&str> = tokens.iter().map(|t| &t.token.lexeme[..]).collect();
+        assert_eq!(vec!["let", "x", "=", "1", ";", "x", "=", "2", ";", ""], lexemes);
+        assert_eq!("", tokens[0].leading_trivia);
+        assert_eq!(" ", tokens[1].leading_trivia);
+        assert_eq!(" // the answer\n  // is wrong\n", tokens[5].leading_trivia);
+        assert_eq!(3, tokens[5].token.line);
+
+        // Without the trivia the tokens are the ones the parser sees
+        let source = "#!/usr/bin/env flax\nlet s = \"a b\" ++ x;  // done";
+        let tokens = scan_with_trivia(source).unwrap();
+        assert_eq!("#!/usr/bin/env flax\n", tokens[0].leading_trivia);
+        assert_eq!("  // done", tokens.last().unwrap().leading_trivia);
+        let plain: Vec<Token> = tokens.into_iter().map(|t| t.token).collect();
+        assert_eq!(lex_source(source).unwrap(), plain);
+    }
+
+    #[test]
+    fn clean_source_lexes() {
+        assert!(lex_source("let x = 1;\nprintln(x ** 2);").is_ok());
+    }
 }
\ No newline at end of file
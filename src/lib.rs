@@ -0,0 +1,32 @@
+// Flax as a library, for programs that embed it. The flax binary is run_repl on a
+// thread with a bigger stack
+mod lexer;
+mod ast;
+mod repl;
+mod parser;
+mod errors;
+mod interpreter;
+mod environment;
+mod callable;
+mod native_functions;
+mod strlib;
+mod byteslib;
+mod printer;
+mod profiler;
+mod report;
+mod optimizer;
+mod arena;
+mod collation;
+mod json;
+mod ast_json;
+mod methods;
+mod resolver;
+mod program;
+mod lint;
+
+pub use repl::run_repl;
+pub use interpreter::{Value, STACK_SIZE};
+pub use environment::Environment;
+pub use errors::{RuntimeError, LexError};
+pub use lexer::{Token, TokenType, TriviaToken, scan_with_trivia};
+pub use program::CompiledProgram;
@@ -0,0 +1,138 @@
+use std::fmt;
+
+use crate::ast::{Expr, Stmt};
+
+// Looks for code that is valid but probably not what was meant. Warnings are shown
+// before a file runs and don't stop it from running.
+//
+// - An assignment used as the condition of an 'if', 'while' or '?', like 'if (x = 5)'.
+//   It assigns and then tests the value, where 'x == 5' was most likely intended
+
+
+#[derive(Debug, PartialEq)]
+pub struct Warning {
+    pub line: u64,
+    pub message: String,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<>) -> fmt::Result {
+        write!(f, "[Warning line {}]: {}", self.line, self.message)
+    }
+}
+
+
+pub fn lint(statements: &[Stmt]) -> Vec<Warning> {
+    let mut warnings = Vec::new();
+    statements.iter().for_each(|statement| lint_stmt(statement, &mut warnings));
+    warnings
+}
+
+fn lint_stmt(statement: &Stmt, warnings: &mut Vec<Warning>) {
+    match statement {
+        Stmt::ExprStmt(expr) | Stmt::ConstDecl(_, expr) | Stmt::Destructure(_, _, expr) | Stmt::Throw(_, expr) => lint_expr(expr, warnings),
+        Stmt::VarDecl(_, initializer) => initializer.iter().for_each(|expr| lint_expr(expr, warnings)),
+        Stmt::Block(statements) => statements.iter().for_each(|statement| lint_stmt(statement, warnings)),
+        Stmt::IfStmt(if_stmt) => {
+            check_condition(&if_stmt.conditional, warnings);
+            lint_expr(&if_stmt.conditional, warnings);
+            lint_stmt(&if_stmt.then_block, warnings);
+            if_stmt.else_block.iter().for_each(|block| lint_stmt(block, warnings));
+        },
+        Stmt::WhileStmt(_, cond, body) => {
+            check_condition(cond, warnings);
+            lint_expr(cond, warnings);
+            lint_stmt(body, warnings);
+        },
+        Stmt::FuncStmt(func) => lint_stmt(&func.body, warnings),
+        Stmt::ReturnStmt(ret) => ret.expr.iter().for_each(|expr| lint_expr(expr, warnings)),
+        Stmt::TryStmt(stmt) => {
+            lint_stmt(&stmt.try_block, warnings);
+            lint_stmt(&stmt.catch_block, warnings);
+        },
+        Stmt::Defer(_, stmt) => lint_stmt(stmt, warnings),
+        Stmt::Import(_) | Stmt::Break => (),
+    }
+}
+
+fn lint_expr(expr: &Expr, warnings: &mut Vec<Warning>) {
+    match expr {
+        Expr::L(_) | Expr::V(_, _) => (),
+        Expr::A(_, value, _) => lint_expr(value, warnings),
+        Expr::U(unary) => lint_expr(&unary.expr, warnings),
+        Expr::B(binary) => {
+            lint_expr(&binary.left, warnings);
+            lint_expr(&binary.right, warnings);
+        },
+        Expr::Log(logical) => {
+            lint_expr(&logical.left, warnings);
+            lint_expr(&logical.right, warnings);
+        },
+        Expr::G(grouping) => lint_expr(&grouping.expr, warnings),
+        Expr::C(cond) => {
+            check_condition(&cond.cond, warnings);
+            lint_expr(&cond.cond, warnings);
+            lint_expr(&cond.then_expr, warnings);
+            lint_expr(&cond.else_expr, warnings);
+        },
+        Expr::Cal(call) => {
+            lint_expr(&call.callee, warnings);
+            call.args.iter().for_each(|arg| lint_expr(arg, warnings));
+        },
+        Expr::Method(method) => {
+            lint_expr(&method.receiver, warnings);
+            method.args.iter().for_each(|arg| lint_expr(arg, warnings));
+        },
+        Expr::Arr(arr) => arr.elements.iter().for_each(|element| lint_expr(element, warnings)),
+        Expr::Map(map) => map.entries.iter().for_each(|(key, value)| {
+            lint_expr(key, warnings);
+            lint_expr(value, warnings);
+        }),
+        Expr::Lambda(func) => lint_stmt(&func.body, warnings),
+        Expr::Block(statements) => statements.iter().for_each(|statement| lint_stmt(statement, warnings)),
+        Expr::Spread(_, expr) => lint_expr(expr, warnings),
+        Expr::Match(m) => {
+            lint_expr(&m.value, warnings);
+            m.arms.iter().for_each(|(_, value)| lint_expr(value, warnings));
+        },
+    }
+}
+
+// Warns when the condition is an assignment, even one wrapped in parentheses
+fn check_condition(cond: &Expr, warnings: &mut Vec<Warning>) {
+    match cond {
+        Expr::G(grouping) => check_condition(&grouping.expr, warnings),
+        Expr::A(name, _, _) => warnings.push(Warning {
+            line: name.line,
+            message: format!("Assignment to '{}' used as a condition, did you mean '=='?", name.lexeme),
+        }),
+        _ => (),
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::lexer::lex_source;
+    use crate::parser::Parser;
+
+    fn warnings(source: &str) -> Vec<String> {
+        let statements = Parser::new(lex_source(source).unwrap()).parse().unwrap();
+        lint(&statements).iter().map(|warning| warning.to_string()).collect()
+    }
+
+    #[test]
+    fn assignment_as_condition() {
+        assert_eq!(vec!["[Warning line 2]: Assignment to 'x' used as a condition, did you mean '=='?"],
+            warnings("let x = 1;\nif (x = 5) {\n    println(x);\n}"));
+        assert!(warnings("let x = 1;\nif (x == 5) {\n    println(x);\n}").is_empty());
+
+        // Inside functions, in loops and ternaries
+        assert_eq!(2, warnings("func f(y) {\n    while y = next() {\n        return (y = 1) ? 1 : 2;\n    }\n}").len());
+        // Assigning inside a condition that also compares is left alone
+        assert!(warnings("while (line = next()) != nil {\n    println(line);\n}").is_empty());
+    }
+}
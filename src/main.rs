@@ -1,15 +1,12 @@
-mod lexer;
-mod ast;
-mod repl;
-mod parser;
-mod errors;
-mod interpreter;
-mod environment;
-mod callable;
-mod native_functions;
-mod strlib;
+use std::thread;
 
 fn main() {
-
-    repl::run_repl();
-}
\ No newline at end of file
+    // Deeply nested Flax calls need more stack than the main thread has
+    let repl = thread::Builder::new()
+        .stack_size(basic_lang::STACK_SIZE)
+        .spawn(basic_lang::run_repl)
+        .expect("Unable to start the interpreter");
+    if repl.join().is_err() {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,38 @@
+use crate::callable::FunctionTypes;
+use crate::interpreter::Value;
+use crate::native_functions::NativeFunctions;
+use crate::strlib::StrLib;
+use crate::byteslib::BytesLib;
+
+// Method calls like '"abc".upper()' are another way to call a builtin, with the
+// receiver passed as one of its arguments: '"abc".upper()' is 'upper("abc")'.
+// The table below lists the methods each type of value has.
+
+
+// Where the receiver goes in the builtin's arguments
+#[derive(Debug, PartialEq)]
+pub enum Receiver {
+    First,
+    // charAt and subString take the string after the indexes
+    Last,
+}
+
+pub fn resolve(receiver: &Value, name: &str) -> Option<(FunctionTypes, Receiver)> {
+    let method = match (receiver, name) {
+        (Value::STRING(_), "len") => (FunctionTypes::str_lib_func(StrLib::Len), Receiver::First),
+        (Value::STRING(_), "upper") => (FunctionTypes::str_lib_func(StrLib::Upper), Receiver::First),
+        (Value::STRING(_), "lower") => (FunctionTypes::str_lib_func(StrLib::Lower), Receiver::First),
+        (Value::STRING(_), "trim") => (FunctionTypes::str_lib_func(StrLib::Trim), Receiver::First),
+        (Value::STRING(_), "eq_ignore_case") => (FunctionTypes::str_lib_func(StrLib::EqIgnoreCase), Receiver::First),
+        (Value::STRING(_), "charAt") => (FunctionTypes::str_lib_func(StrLib::CharAt), Receiver::Last),
+        (Value::STRING(_), "subString") => (FunctionTypes::str_lib_func(StrLib::SubStr), Receiver::Last),
+        (Value::BYTES(_), "to_hex") => (FunctionTypes::bytes_lib_func(BytesLib::ToHex), Receiver::First),
+        (Value::BYTES(_), "to_base64") => (FunctionTypes::bytes_lib_func(BytesLib::ToBase64), Receiver::First),
+        (Value::ARRAY(_), "push") => (FunctionTypes::new_native_func(NativeFunctions::Push), Receiver::First),
+        (Value::ARRAY(_), "copy") | (Value::MAP(_), "copy") => (FunctionTypes::new_native_func(NativeFunctions::Copy), Receiver::First),
+        (Value::ARRAY(_), "freeze") | (Value::MAP(_), "freeze") => (FunctionTypes::new_native_func(NativeFunctions::Freeze), Receiver::First),
+        (Value::ARRAY(_), "frozen") | (Value::MAP(_), "frozen") => (FunctionTypes::new_native_func(NativeFunctions::Frozen), Receiver::First),
+        _ => return None,
+    };
+    Some(method)
+}
@@ -1,7 +1,8 @@
 use std::fmt;
+use crate::json::{self, Json};
 use crate::errors::RuntimeError;
 use crate::callable::Callable;
-use crate::interpreter::{Value, Interpreter};
+use crate::interpreter::{Value, Interpreter, values_equal};
 use crate::environment::Environment;
 
 #[derive(PartialEq, Clone)]
@@ -9,6 +10,27 @@ pub enum NativeFunctions {
     Clock,
     Println(Box<Println>),
     Print(Box<Print>),
+    Vars,
+    DeepEqual,
+    Copy,
+    Push,
+    Arity,
+    Name,
+    Str,
+    Format,
+    Rem,
+    Freeze,
+    Frozen,
+    Join,
+    JoinPrint,
+    TypeOf,
+    Write,
+    Zip,
+    Enumerate,
+    ToJson,
+    FromJson,
+    Env,
+    Args,
 }
 
 #[derive(PartialEq, Clone)]
@@ -29,6 +51,36 @@ impl NativeFunctions {
     pub fn new_println_func(value: Value) -> NativeFunctions {
         NativeFunctions::Println(Box::new(Println{ value }))
     }
+
+    // The name the builtin is bound to in the globals
+    pub fn name(&self) -> &'static str {
+        match self {
+            NativeFunctions::Clock => "clock",
+            NativeFunctions::Println(_) => "println",
+            NativeFunctions::Print(_) => "print",
+            NativeFunctions::Vars => "vars",
+            NativeFunctions::DeepEqual => "deep_equal",
+            NativeFunctions::Copy => "copy",
+            NativeFunctions::Push => "push",
+            NativeFunctions::Arity => "arity",
+            NativeFunctions::Name => "name",
+            NativeFunctions::Str => "str",
+            NativeFunctions::Format => "format",
+            NativeFunctions::Rem => "rem",
+            NativeFunctions::Freeze => "freeze",
+            NativeFunctions::Frozen => "frozen",
+            NativeFunctions::Join => "join",
+            NativeFunctions::JoinPrint => "join_print",
+            NativeFunctions::TypeOf => "typeof",
+            NativeFunctions::Write => "write",
+            NativeFunctions::Zip => "zip",
+            NativeFunctions::Enumerate => "enumerate",
+            NativeFunctions::ToJson => "to_json",
+            NativeFunctions::FromJson => "from_json",
+            NativeFunctions::Env => "env",
+            NativeFunctions::Args => "args",
+        }
+    }
 }
 
 impl Callable for NativeFunctions {
@@ -37,6 +89,28 @@ impl Callable for NativeFunctions {
             NativeFunctions::Clock => clock(),
             NativeFunctions::Println(expr) => expr.call(interpreter, args, env),
             NativeFunctions::Print(expr) => expr.call(interpreter, args, env), 
+            NativeFunctions::Vars => vars(env),
+            NativeFunctions::DeepEqual => Ok(Value::BOOL(deep_equal(&args[0], &args[1]))),
+            NativeFunctions::Copy => Ok(deep_copy(&args[0])),
+            NativeFunctions::Push => push(&args[0], args[1].clone()),
+            NativeFunctions::Arity => arity(&args[0]),
+            NativeFunctions::Name => name(&args[0]),
+            NativeFunctions::Str => Ok(Value::STRING(str(&args[0]))),
+            NativeFunctions::Format => format(&args[0], &args[1..]),
+            NativeFunctions::Rem => rem(&args[0], &args[1]),
+            NativeFunctions::Freeze => freeze(&args[0]),
+            NativeFunctions::Frozen => Ok(Value::BOOL(frozen(&args[0]))),
+            NativeFunctions::Join => join("join", &args[0], &args[1]).map(Value::STRING),
+            NativeFunctions::JoinPrint => join_print(interpreter, &args[0], &args[1]),
+            NativeFunctions::TypeOf => Ok(Value::STRING(args[0].type_of())),
+            NativeFunctions::Write => write(interpreter, &args[0]),
+            NativeFunctions::Zip => zip(&args[0], &args[1]),
+            NativeFunctions::Enumerate => enumerate(&args[0]),
+            NativeFunctions::ToJson => value_to_json(&args[0]).map(|json| Value::STRING(json.to_string()))
+                .map_err(|msg| RuntimeError::no_token_error("to_json", msg, 1000)),
+            NativeFunctions::FromJson => from_json(&args[0]),
+            NativeFunctions::Env => env_var(interpreter, &args[0]),
+            NativeFunctions::Args => Ok(Value::new_array(interpreter.script_args.iter().cloned().map(Value::STRING).collect())),
         }
     }
     fn arity(&self) -> u8 {
@@ -44,8 +118,33 @@ impl Callable for NativeFunctions {
             NativeFunctions::Clock => 0,
             NativeFunctions::Println(expr) => expr.arity(),
             NativeFunctions::Print(expr) => expr.arity(),
+            NativeFunctions::Vars => 0,
+            NativeFunctions::DeepEqual => 2,
+            NativeFunctions::Copy => 1,
+            NativeFunctions::Push => 2,
+            NativeFunctions::Arity => 1,
+            NativeFunctions::Name => 1,
+            NativeFunctions::Str => 1,
+            NativeFunctions::Format => 1,
+            NativeFunctions::Rem => 2,
+            NativeFunctions::Freeze => 1,
+            NativeFunctions::Frozen => 1,
+            NativeFunctions::Join => 2,
+            NativeFunctions::JoinPrint => 2,
+            NativeFunctions::TypeOf => 1,
+            NativeFunctions::Write => 1,
+            NativeFunctions::Zip => 2,
+            NativeFunctions::Enumerate => 1,
+            NativeFunctions::ToJson => 1,
+            NativeFunctions::FromJson => 1,
+            NativeFunctions::Env => 1,
+            NativeFunctions::Args => 0,
         }
     }
+
+    fn variadic(&self) -> bool {
+        matches!(self, NativeFunctions::Format)
+    }
 }
 
 fn clock() -> Result<Value, RuntimeError> {
@@ -54,13 +153,245 @@ fn clock() -> Result<Value, RuntimeError> {
     Ok(Value::NUMBER(epoch_time as f64))
 }
 
+// Lists the names of every variable visible from the calling scope
+fn vars(env: &Environment) -> Result<Value, RuntimeError> {
+    let names = env.names().into_iter().map(Value::STRING).collect();
+    Ok(Value::new_array(names))
+}
+
+// Compares arrays and maps by their contents instead of by identity, recursing into
+// nested containers. Everything else is compared the same way '==' does
+fn deep_equal(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::ARRAY(v), Value::ARRAY(v2)) => {
+            let (v, v2) = (v.borrow(), v2.borrow());
+            v.len() == v2.len() && v.iter().zip(v2.iter()).all(|(a, b)| deep_equal(a, b))
+        },
+        (Value::MAP(v), Value::MAP(v2)) => {
+            let (v, v2) = (v.borrow(), v2.borrow());
+            v.len() == v2.len() && v.iter().all(|(key, a)| v2.get(key).is_some_and(|b| deep_equal(a, b)))
+        },
+        _ => values_equal(left, right),
+    }
+}
+
+// Arrays and maps are shared when assigned, so copy them all the way down to get
+// a value that can be changed without affecting the original
+fn deep_copy(value: &Value) -> Value {
+    match value {
+        Value::ARRAY(values) => Value::new_array(values.borrow().iter().map(deep_copy).collect()),
+        Value::MAP(entries) => Value::new_map(entries.borrow().iter().map(|(k, v)| (k.clone(), deep_copy(v))).collect()),
+        _ => value.clone(),
+    }
+}
+
+fn push(array: &Value, value: Value) -> Result<Value, RuntimeError> {
+    if let Value::ARRAY(values) = array {
+        values.borrow_mut("push")?.push(value);
+        return Ok(Value::Nil)
+    }
+    Err(RuntimeError::no_token_error("push", format!("push expects an Array, given: {}", array), 1000))
+}
+
+// Pairs up the elements of two arrays: zip([1, 2], [3, 4]) is [[1, 3], [2, 4]]. The
+// longer array's extra elements are left out
+fn zip(left: &Value, right: &Value) -> Result<Value, RuntimeError> {
+    match (left, right) {
+        (Value::ARRAY(left), Value::ARRAY(right)) => {
+            let pairs = left.borrow().iter().zip(right.borrow().iter())
+                .map(|(a, b)| Value::new_array(vec![a.clone(), b.clone()]))
+                .collect();
+            Ok(Value::new_array(pairs))
+        },
+        _ => Err(RuntimeError::no_token_error("zip", format!("zip expects two Arrays, given: {}, {}", left, right), 1000)),
+    }
+}
+
+// Pairs each element with its index: enumerate(["a", "b"]) is [[0, "a"], [1, "b"]]
+fn enumerate(array: &Value) -> Result<Value, RuntimeError> {
+    match array {
+        Value::ARRAY(values) => {
+            let pairs = values.borrow().iter().enumerate()
+                .map(|(i, value)| Value::new_array(vec![Value::INT(i as i64), value.clone()]))
+                .collect();
+            Ok(Value::new_array(pairs))
+        },
+        _ => Err(RuntimeError::no_token_error("enumerate", format!("enumerate expects an Array, given: {}", array), 1000)),
+    }
+}
+
+// JSON for a value. Ints and floats stay distinct, so 1 is written as 1 and 1.0 as 1.0.
+// Functions and bytes have no JSON form
+fn value_to_json(value: &Value) -> Result<Json, String> {
+    match value {
+        Value::Nil => Ok(Json::Null),
+        Value::BOOL(b) => Ok(Json::Bool(*b)),
+        Value::INT(n) => Ok(Json::Int(*n)),
+        Value::NUMBER(n) => Ok(Json::Float(*n)),
+        Value::STRING(s) => Ok(Json::String(s.clone())),
+        Value::ARRAY(values) => values.borrow().iter().map(value_to_json).collect::<Result<Vec<Json>, String>>().map(Json::Array),
+        Value::MAP(entries) => entries.borrow().iter()
+            .map(|(key, value)| value_to_json(value).map(|json| (key.clone(), json)))
+            .collect::<Result<Vec<(String, Json)>, String>>()
+            .map(Json::Object),
+        Value::Callable(_) | Value::BYTES(_) => Err(format!("to_json can't convert a {} to JSON, given: {}", value.type_name(), value)),
+    }
+}
+
+// Reads JSON text into values: objects become maps and arrays become arrays
+fn from_json(text: &Value) -> Result<Value, RuntimeError> {
+    match text {
+        Value::STRING(text) => json::parse(text).map(json_to_value)
+            .map_err(|msg| RuntimeError::no_token_error("from_json", format!("from_json was given invalid JSON: {}", msg), 1000)),
+        _ => Err(RuntimeError::no_token_error("from_json", format!("from_json expects a String, given: {}", text), 1000)),
+    }
+}
+
+fn json_to_value(json: Json) -> Value {
+    match json {
+        Json::Null => Value::Nil,
+        Json::Bool(b) => Value::BOOL(b),
+        Json::Int(n) => Value::INT(n),
+        Json::Float(n) => Value::NUMBER(n),
+        Json::String(s) => Value::STRING(s),
+        Json::Array(items) => Value::new_array(items.into_iter().map(json_to_value).collect()),
+        Json::Object(entries) => Value::new_map(entries.into_iter().map(|(key, value)| (key, json_to_value(value))).collect()),
+    }
+}
+
+fn arity(func: &Value) -> Result<Value, RuntimeError> {
+    if let Value::Callable(func) = func {
+        return Ok(Value::INT(func.arity() as i64))
+    }
+    Err(RuntimeError::no_token_error("arity", format!("arity expects a Function, given: {}", func), 1000))
+}
+
+fn name(func: &Value) -> Result<Value, RuntimeError> {
+    if let Value::Callable(func) = func {
+        return Ok(Value::STRING(func.name()))
+    }
+    Err(RuntimeError::no_token_error("name", format!("name expects a Function, given: {}", func), 1000))
+}
+
+// The string form of a value. Unlike Display, strings are not quoted
+fn str(value: &Value) -> String {
+    match value {
+        Value::STRING(s) => s.clone(),
+        _ => value.to_string(),
+    }
+}
+
+// The string forms of an array's elements with the separator between them, without
+// brackets: join([1, 2, 3], ";") is "1;2;3"
+fn join(func: &str, array: &Value, separator: &Value) -> Result<String, RuntimeError> {
+    match (array, separator) {
+        (Value::ARRAY(values), Value::STRING(separator)) => {
+            Ok(values.borrow().iter().map(str).collect::<Vec<String>>().join(separator))
+        },
+        _ => Err(RuntimeError::no_token_error(func, format!("{} expects an Array and a String separator, given: {}, {}", func, array, separator), 1000)),
+    }
+}
+
+// Prints an array on its own line the way join writes it, for output like CSV rows
+fn join_print(interpreter: &mut Interpreter, array: &Value, separator: &Value) -> Result<Value, RuntimeError> {
+    let line = join("join_print", array, separator)? + "\n";
+    interpreter.write_output(&line)?;
+    Ok(Value::Nil)
+}
+
+// Writes a value without a newline after it, so several writes make up one line.
+// Strings are written without quotes
+fn write(interpreter: &mut Interpreter, value: &Value) -> Result<Value, RuntimeError> {
+    let text = match value {
+        Value::STRING(s) => s.clone(),
+        _ => interpreter.display(value),
+    };
+    interpreter.write_output(&text)?;
+    Ok(Value::Nil)
+}
+
+// The value of an environment variable, nil when it isn't set
+fn env_var(interpreter: &Interpreter, name: &Value) -> Result<Value, RuntimeError> {
+    match name {
+        Value::STRING(name) => Ok((interpreter.env_vars)(name).map_or(Value::Nil, Value::STRING)),
+        _ => Err(RuntimeError::no_token_error("env", format!("env expects a String, given: {}", name), 1000)),
+    }
+}
+
+// Replaces each '{}' in the template with the next argument. '{{' and '}}' are literal braces
+fn format(template: &Value, args: &[Value]) -> Result<Value, RuntimeError> {
+    let template = match template {
+        Value::STRING(template) => template,
+        _ => return Err(RuntimeError::no_token_error("format", format!("format expects a String template, given: {}", template), 1000)),
+    };
+    let mut result = String::new();
+    let mut placeholders = 0;
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('{', Some('{')) | ('}', Some('}')) => {
+                chars.next();
+                result.push(c);
+            },
+            ('{', Some('}')) => {
+                chars.next();
+                if let Some(arg) = args.get(placeholders) {
+                    result.push_str(&str(arg));
+                }
+                placeholders += 1;
+            },
+            ('{', _) | ('}', _) => {
+                return Err(RuntimeError::no_token_error("format", format!("Unmatched '{}' in format string. Use '{}{}' for a literal brace", c, c, c), 1000))
+            },
+            _ => result.push(c),
+        }
+    }
+    if placeholders != args.len() {
+        let given = if args.len() == 1 { "argument" } else { "arguments" };
+        return Err(RuntimeError::no_token_error("format", format!("Format string has {} placeholders but was given {} {}", placeholders, args.len(), given), 1000))
+    }
+    Ok(Value::STRING(result))
+}
+
+// Makes an array or map read only and returns it
+fn freeze(container: &Value) -> Result<Value, RuntimeError> {
+    match container {
+        Value::ARRAY(values) => values.freeze(),
+        Value::MAP(entries) => entries.freeze(),
+        _ => return Err(RuntimeError::no_token_error("freeze", format!("freeze expects an Array or Map, given: {}", container), 1000)),
+    }
+    Ok(container.clone())
+}
+
+fn frozen(value: &Value) -> bool {
+    match value {
+        Value::ARRAY(values) => values.is_frozen(),
+        Value::MAP(entries) => entries.is_frozen(),
+        _ => false,
+    }
+}
+
+// The truncated remainder, which has the sign of the dividend: rem(-7, 3) == -1
+fn rem(left: &Value, right: &Value) -> Result<Value, RuntimeError> {
+    match (left, right) {
+        (_, Value::INT(0)) => Err(RuntimeError::DivideByZero(1000)),
+        (Value::INT(left), Value::INT(right)) => Ok(Value::INT(left.checked_rem(*right).unwrap_or(0))),
+        _ => match (left.as_f64(), right.as_f64()) {
+            (Some(_), Some(0.0)) => Err(RuntimeError::DivideByZero(1000)),
+            (Some(left), Some(right)) => Ok(Value::NUMBER(left % right)),
+            _ => Err(RuntimeError::no_token_error("rem", format!("rem expects two Numbers, given: {}, {}", left, right), 1000)),
+        },
+    }
+}
+
 impl Callable for Print {
-    fn call(&self, _interpreter: &mut Interpreter, args: Vec<Value>, _env: &mut Environment) -> Result<Value, RuntimeError> {
+    fn call(&self, interpreter: &mut Interpreter, args: Vec<Value>, _env: &mut Environment) -> Result<Value, RuntimeError> {
         if args.len() < 1 {
-            print!("");
+            interpreter.write_output("\n")?;
             return Ok(Value::Nil)
         }
-        print!("{}", args[0]);
+        let line = interpreter.display(&args[0]) + "\n";
+        interpreter.write_output(&line)?;
         Ok(Value::Nil)
     }
 
@@ -70,12 +401,13 @@ impl Callable for Print {
 }
 
 impl Callable for Println {
-    fn call(&self, _interpreter: &mut Interpreter, args: Vec<Value>, _env: &mut Environment) -> Result<Value, RuntimeError> {
+    fn call(&self, interpreter: &mut Interpreter, args: Vec<Value>, _env: &mut Environment) -> Result<Value, RuntimeError> {
         if args.len() < 1 {
-            println!("");
+            interpreter.write_output("\n")?;
             return Ok(Value::Nil)
         }
-        println!("{}", args[0]);
+        let line = interpreter.display(&args[0]) + "\n";
+        interpreter.write_output(&line)?;
         Ok(Value::Nil)
     }
 
@@ -88,10 +420,6 @@ impl Callable for Println {
 
 impl fmt::Debug for NativeFunctions {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            NativeFunctions::Clock => write!(f, "<fn clock>", ),
-            NativeFunctions::Println(_value) => write!(f, "<fn println>"),
-            NativeFunctions::Print(_value) => write!(f, "<fn print>"),
-        }
+        write!(f, "<fn {}>", self.name())
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,216 @@
+use crate::ast::{Expr, Stmt, Function, IfStatement, TryCatch, Return};
+use crate::lexer::TokenType;
+
+// Rewrites the Abstract Syntax Tree into a simpler tree that evaluates the same way.
+// A rewrite is only made when it can't change what the program does, including
+// which errors it raises.
+//
+// Groupings are removed: the parser has already turned the parentheses into the shape
+// of the tree, so '(1 + 2) * 3' evaluates the same without its Grouping node.
+
+
+pub fn optimize(statements: Vec<Stmt>) -> Vec<Stmt> {
+    statements.into_iter().map(optimize_stmt).collect()
+}
+
+fn optimize_stmt(stmt: Stmt) -> Stmt {
+    match stmt {
+        Stmt::ExprStmt(expr) => Stmt::ExprStmt(optimize_expr(expr)),
+        Stmt::VarDecl(name, expr) => Stmt::VarDecl(name, expr.map(optimize_expr)),
+        Stmt::ConstDecl(name, expr) => Stmt::ConstDecl(name, optimize_expr(expr)),
+        Stmt::Destructure(names, equal, expr) => Stmt::Destructure(names, equal, optimize_expr(expr)),
+        Stmt::Block(statements) => Stmt::new_block(optimize(*statements)),
+        Stmt::IfStmt(stmt) => {
+            let IfStatement { conditional, then_block, else_block } = *stmt;
+            Stmt::new_if(optimize_expr(conditional), optimize_stmt(then_block), else_block.map(optimize_stmt))
+        },
+        Stmt::WhileStmt(tok, cond, body) => Stmt::new_while(tok, optimize_expr(cond), optimize_stmt(*body)),
+        Stmt::FuncStmt(func) => Stmt::FuncStmt(Box::new(optimize_function(*func))),
+        Stmt::ReturnStmt(ret) => {
+            let Return { tok, expr } = *ret;
+            Stmt::new_return(tok, expr.map(optimize_expr))
+        },
+        Stmt::TryStmt(stmt) => {
+            let TryCatch { try_block, name, catch_block } = *stmt;
+            Stmt::new_try(optimize_stmt(try_block), name, optimize_stmt(catch_block))
+        },
+        Stmt::Throw(tok, expr) => Stmt::Throw(tok, optimize_expr(expr)),
+        Stmt::Defer(tok, stmt) => Stmt::Defer(tok, Box::new(optimize_stmt(*stmt))),
+        Stmt::Import(_) | Stmt::Break => stmt,
+    }
+}
+
+fn optimize_function(func: Function) -> Function {
+    Function { name: func.name, params: func.params, body: optimize_stmt(func.body) }
+}
+
+pub fn optimize_expr(expr: Expr) -> Expr {
+    match expr {
+        Expr::U(mut unary) => {
+            unary.expr = optimize_expr(unary.expr);
+            match simplify_double_unary(&unary.operator.token_type, &unary.expr) {
+                Some(inner) => inner,
+                None => Expr::U(unary),
+            }
+        },
+        Expr::B(mut binary) => {
+            binary.left = optimize_expr(binary.left);
+            binary.right = optimize_expr(binary.right);
+            Expr::B(binary)
+        },
+        Expr::G(grouping) => optimize_expr(grouping.expr),
+        Expr::C(mut cond) => {
+            cond.cond = optimize_expr(cond.cond);
+            cond.then_expr = optimize_expr(cond.then_expr);
+            cond.else_expr = optimize_expr(cond.else_expr);
+            Expr::C(cond)
+        },
+        Expr::A(tok, value, depth) => Expr::A(tok, Box::new(optimize_expr(*value)), depth),
+        Expr::Log(mut logical) => {
+            logical.left = optimize_expr(logical.left);
+            logical.right = optimize_expr(logical.right);
+            Expr::Log(logical)
+        },
+        Expr::Cal(mut call) => {
+            call.callee = optimize_expr(call.callee);
+            call.args = call.args.into_iter().map(optimize_expr).collect();
+            Expr::Cal(call)
+        },
+        Expr::Method(mut method) => {
+            method.receiver = optimize_expr(method.receiver);
+            method.args = method.args.into_iter().map(optimize_expr).collect();
+            Expr::Method(method)
+        },
+        Expr::Arr(mut arr) => {
+            arr.elements = arr.elements.into_iter().map(optimize_expr).collect();
+            Expr::Arr(arr)
+        },
+        Expr::Map(mut map) => {
+            map.entries = map.entries.into_iter().map(|(k, v)| (optimize_expr(k), optimize_expr(v))).collect();
+            Expr::Map(map)
+        },
+        Expr::Lambda(func) => Expr::Lambda(Box::new(optimize_function(*func))),
+        Expr::Block(statements) => Expr::new_block(optimize(statements)),
+        Expr::Spread(tok, expr) => Expr::Spread(tok, Box::new(optimize_expr(*expr))),
+        Expr::Match(mut m) => {
+            m.value = optimize_expr(m.value);
+            m.arms = m.arms.into_iter().map(|(pattern, value)| (pattern, optimize_expr(value))).collect();
+            Expr::Match(m)
+        },
+        Expr::L(_) | Expr::V(_, _) => expr,
+    }
+}
+
+// '- -5' is 5, and '!!x' is x when x is already a boolean. Anything else keeps both
+// operators: '- -"a"' has to raise an error, '!!nil' has to be false, and '- -x' is a
+// float when x is the smallest int, since negating it once overflows to a float
+fn simplify_double_unary(outer: &TokenType, operand: &Expr) -> Option<Expr> {
+    let inner = match operand {
+        Expr::U(inner) => inner,
+        _ => return None,
+    };
+    match (outer, &inner.operator.token_type) {
+        (TokenType::Minus, TokenType::Minus) if is_number_literal(&inner.expr) => Some(inner.expr.clone()),
+        (TokenType::Bang, TokenType::Bang)
+        | (TokenType::Bang, TokenType::Not)
+        | (TokenType::Not, TokenType::Bang)
+        | (TokenType::Not, TokenType::Not) if is_boolean(&inner.expr) => Some(inner.expr.clone()),
+        _ => None,
+    }
+}
+
+// A number literal is never the smallest int, which has no literal of its own
+fn is_number_literal(expr: &Expr) -> bool {
+    matches!(expr, Expr::L(lit) if lit.token_type == TokenType::NUMBER)
+}
+
+// Whether the expression always evaluates to a boolean (or raises an error)
+fn is_boolean(expr: &Expr) -> bool {
+    match expr {
+        Expr::L(lit) => lit.token_type == TokenType::TRUE || lit.token_type == TokenType::FALSE,
+        Expr::U(unary) => unary.operator.token_type == TokenType::Bang || unary.operator.token_type == TokenType::Not,
+        Expr::B(binary) => matches!(binary.operator.token_type,
+            TokenType::EqualEqual | TokenType::BangEqual | TokenType::Less | TokenType::LessEqual
+            | TokenType::Greater | TokenType::GreaterEqual),
+        // 'and' and 'or' return one of their operands
+        Expr::Log(logical) => is_boolean(&logical.left) && is_boolean(&logical.right),
+        _ => false,
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::lexer::lex_line;
+    use crate::parser::Parser;
+    use crate::interpreter::{Interpreter, Visit};
+
+    fn optimized(source: &str) -> String {
+        let expr = Parser::new(lex_line(source.to_string()).unwrap()).parse_expression().unwrap();
+        optimize_expr(expr).to_string()
+    }
+
+    #[test]
+    fn double_negation_of_numbers() {
+        assert_eq!("5", optimized("- -5"));
+        assert_eq!("('-' ('-' ('+' 1 2)))", optimized("- -(1 + 2)"));
+        assert_eq!("5", optimized("-(-5)"));
+        assert_eq!("('-' 5)", optimized("- - -5"));
+        // x may not be a number, and '- -x' has to raise an error when it isn't
+        assert_eq!("('-' ('-' x))", optimized("- -x"));
+        assert_eq!("('-' ('-' a))", optimized("- -\"a\""));
+        // m - 1 is the smallest int, which '-' turns into a float, so '- -' isn't a no-op
+        assert_eq!("('-' ('-' ('-' m 1)))", optimized("- -(m - 1)"));
+        let source = "let m = -9223372036854775807;\nlet t = typeof(- -(m - 1));";
+        let statements = Parser::new(crate::lexer::lex_source(source).unwrap()).parse().unwrap();
+        let mut interpreter = Interpreter::new();
+        let mut env = interpreter.globals.clone();
+        for statement in optimize(statements) {
+            statement.evaluate(&mut interpreter, &mut env).unwrap();
+        }
+        let t = env.get(&crate::lexer::Token::new(TokenType::Identifier, String::from("t"), 1)).unwrap();
+        assert_eq!(crate::interpreter::Value::STRING(String::from("number")), t);
+    }
+
+    #[test]
+    fn double_not_of_booleans() {
+        assert_eq!("true", optimized("! !true"));
+        assert_eq!("true", optimized("not not true"));
+        assert_eq!("('<' 1 2)", optimized("! !(1 < 2)"));
+        // '! !' turns these into booleans, so it has to stay
+        assert_eq!("('!' ('!' nil))", optimized("! !nil"));
+        assert_eq!("('!' ('!' x))", optimized("! !x"));
+    }
+
+    #[test]
+    fn groupings_are_removed() {
+        assert_eq!("('*' ('+' 1 2) 3)", optimized("(1 + 2) * 3"));
+        assert_eq!("('+' 1 ('*' 2 3))", optimized("((1 + (2 * 3)))"));
+
+        // Printing the optimized tree puts back only the parentheses precedence needs
+        let sources = ["(1 + 2) * 3", "((1 + (2 * 3)))", "-(2 ** 2)", "(-2) ** 2", "(true ? 1 : 2) + 1",
+            "(10 - 4) - (3 - 1)", "\"a\" ++ (\"b\" ++ \"c\")"];
+        for source in sources.iter() {
+            let expr = Parser::new(lex_line(source.to_string()).unwrap()).parse_expression().unwrap();
+            let optimized = optimize_expr(expr.clone());
+            let printed = crate::printer::to_source(&optimized);
+            let reparsed = Parser::new(lex_line(printed.clone()).unwrap()).parse_expression().unwrap();
+            assert_eq!(optimized.to_string(), optimize_expr(reparsed).to_string(), "{}", source);
+
+            let mut interpreter = Interpreter::new();
+            let mut env = interpreter.globals.clone();
+            let value = expr.evaluate(&mut interpreter, &mut env).unwrap();
+            assert_eq!(value, optimized.evaluate(&mut interpreter, &mut env).unwrap(), "{} as {}", source, printed);
+        }
+    }
+
+    #[test]
+    fn statements_are_optimized() {
+        let tokens = crate::lexer::lex_source("func f() {\n    return - -1;\n}\nif ! !true {\n    let x = - -2;\n}").unwrap();
+        let statements = optimize(Parser::new(tokens).parse().unwrap());
+        assert_eq!("func f() {\n    return 1;\n}\nif true {\n    let x = 2;\n}", crate::printer::program_to_source(&statements));
+    }
+}
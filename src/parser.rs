@@ -1,80 +1,194 @@
 use crate::ast;
 use crate::lexer;
 use crate::errors;
-use errors::ParseError;
-use ast::{Expr, Stmt};
+use errors::{ParseError, ParseErrors};
+use ast::{Expr, Stmt, MATCH_PATTERNS};
 use lexer::{ Token, TokenType };
 /** Precedence      Operators           Associates
- * unary               -                   right
- * multiplication      * /                  left
- * addition            + -                  left 
- * 
- * 
- * expression       => equality
- * equality         => comparison ( ( '==' | '!=' ) comparison )*
- * comparison       => addition ( ('>' | '<' | '>=' '<=' ) addition )*
- * addition         => multiplication ( ('+' | '-') multiplication )*
- * multiplication   => unary ( ('*' | '/') unary )*
- * unary           => ('-') unary
- *                    | primary
+ * power               **                  right
+ * unary               - ~ ! not           right
+ * multiplication      * / %                left
+ * addition            + - ++               left
+ * comparison          > >= < <=            left
+ * equality            == !=                left
+ * conditional         ? :                  right
+ * and                 and                  left
+ * or                  or                   left
+ *
+ * The binary operators are parsed by precedence climbing (see infix_operator)
+ *
+ * expression       => assignment
+ * binary           => unary ( OPERATOR binary )*
+ * unary           => ('-' | '~' | '!' | 'not') unary
+ *                    | call ( '**' binary )*
  * literal          => NUMBER | STRING | true | false | nil
  *                    | "(" expression ")"
  * 
 **/
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Associativity {
+    Left,
+    Right,
+}
+
+const LOWEST: u8 = 1;
+const CONDITIONAL: u8 = 3;
+const ADDITION: u8 = 6;
+const POWER: u8 = 8;
+
+// How tightly each binary operator binds, higher binds tighter, and which way a chain
+// of the same operator groups
+fn infix_operator(token_type: &TokenType) -> Option<(u8, Associativity)> {
+    match token_type {
+        TokenType::Or => Some((LOWEST, Associativity::Left)),
+        TokenType::And => Some((2, Associativity::Left)),
+        TokenType::Question => Some((CONDITIONAL, Associativity::Right)),
+        TokenType::EqualEqual | TokenType::BangEqual => Some((4, Associativity::Left)),
+        TokenType::Greater | TokenType::GreaterEqual | TokenType::Less | TokenType::LessEqual => Some((5, Associativity::Left)),
+        TokenType::Plus | TokenType::Minus | TokenType::PlusPlus => Some((ADDITION, Associativity::Left)),
+        TokenType::Star | TokenType::Slash | TokenType::Percent => Some((7, Associativity::Left)),
+        TokenType::StarStar => Some((POWER, Associativity::Right)),
+        _ => None,
+    }
+}
 pub struct Parser {
     tokens: Vec<Token>,
     index: usize,
     loops: u32,
+    // How many blocks the parser is inside of. 'defer' is only allowed in one
+    blocks: u32,
+    // The most errors parse_all will keep before it only counts the rest. No cap when None
+    pub max_errors: Option<usize>,
+    // When on, 'if' and 'while' conditions must be wrapped in parentheses
+    pub require_parens: bool,
 }
 
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Parser {
-        Parser { tokens, index: 0, loops: 0}
+        Parser { tokens, index: 0, loops: 0, blocks: 0, max_errors: None, require_parens: false }
     }
 
     pub fn parse(&mut self) -> Result<Vec<Stmt>, ParseError> {
         let mut statements = Vec::new();
         while !self.is_at_end() {
-            statements.push(self.declaration()?)
+            match self.declaration() {
+                Ok(stmt) => statements.push(stmt),
+                Err(e) if self.is_at_end() => return Err(e.at_end_of_input()),
+                Err(e) => return Err(e),
+            }
         }
         Ok(statements)
     }
 
+    // Like parse, but keeps going after an error so every mistake in a file can be reported at once
+    pub fn parse_all(&mut self) -> Result<Vec<Stmt>, ParseErrors> {
+        let mut statements = Vec::new();
+        let mut errors = ParseErrors { errors: Vec::new(), omitted: 0 };
+        while !self.is_at_end() {
+            match self.declaration() {
+                Ok(stmt) => statements.push(stmt),
+                Err(e) => {
+                    match self.max_errors {
+                        Some(max) if errors.errors.len() >= max => errors.omitted += 1,
+                        _ => errors.errors.push(e),
+                    }
+                    self.synchronize();
+                },
+            }
+        }
+        if errors.errors.is_empty() && errors.omitted == 0 {
+            return Ok(statements)
+        }
+        Err(errors)
+    }
+
     pub fn parse_expression(&mut self) -> Result<Expr, ParseError> {
         self.expression()
     }
 
-    //TODO: synchronize the parser here
+    // Skips the rest of a broken statement. Statements end at a ';', the end of a line
+    // or right before a keyword that starts a new statement
+    fn synchronize(&mut self) {
+        self.loops = 0;
+        self.consume();
+        while !self.is_at_end() {
+            if self.tokens[self.index - 1].token_type == TokenType::Semicolon
+                || self.tokens[self.index - 1].line < self.current_token().line {
+                return
+            }
+            match self.current_token().token_type {
+                TokenType::Let | TokenType::Const | TokenType::Func | TokenType::If | TokenType::Guard | TokenType::While
+                | TokenType::Return | TokenType::Import | TokenType::Try | TokenType::Throw | TokenType::Defer => return,
+                _ => self.consume(),
+            }
+        }
+    }
+
     fn declaration(&mut self) -> Result<Stmt, ParseError> {
         match self.current_token().token_type {
             TokenType::Let => {
                 self.consume();
                 self.var_declaration()
             },
+            TokenType::Const => {
+                self.consume();
+                self.const_declaration()
+            },
             _ => self.statement(),
         }
     }
 
     fn var_declaration(&mut self) -> Result<Stmt, ParseError> {
         let identifier = self.check_and_consume(TokenType::Identifier, "Expected variable name")?;
+        if self.current_token().token_type == TokenType::Comma {
+            return self.destructuring_declaration(identifier);
+        }
         let mut initializer = None;
         if self.current_token().token_type == TokenType::Equal {
             self.consume();
             initializer = Some(self.expression()?);
         }
-        self.check_and_consume(TokenType::Semicolon, "Expected ';' after variable declaration")?;
+        self.consume_terminator("Expected ';' after variable declaration")?;
         Ok(Stmt::VarDecl(identifier, initializer))
     }
 
+    // 'let x, y = f();'. Unlike a single variable, the names must be initialized
+    fn destructuring_declaration(&mut self, first: Token) -> Result<Stmt, ParseError> {
+        let mut names = vec![first];
+        while self.current_token().token_type == TokenType::Comma {
+            self.consume();
+            names.push(self.check_and_consume(TokenType::Identifier, "Expected variable name after ','")?);
+        }
+        let equal = self.check_and_consume(TokenType::Equal, "Expected '=' after variable names")?;
+        let initializer = self.expression()?;
+        self.consume_terminator("Expected ';' after variable declaration")?;
+        Ok(Stmt::Destructure(names, equal, initializer))
+    }
+
+    fn const_declaration(&mut self) -> Result<Stmt, ParseError> {
+        let identifier = self.check_and_consume(TokenType::Identifier, "Expected constant name")?;
+        self.check_and_consume(TokenType::Equal, "Expected '=' after constant name. Constants must be initialized")?;
+        let initializer = self.expression()?;
+        self.consume_terminator("Expected ';' after constant declaration")?;
+        Ok(Stmt::ConstDecl(identifier, initializer))
+    }
+
 
     fn statement(&mut self) -> Result<Stmt, ParseError> {
         match self.current_token().token_type {
-            TokenType::Func => {self.consume(); self.function("function")},
+            // 'func (' starts a lambda, which is an expression
+            TokenType::Func if self.next_token_type() != Some(TokenType::LeftParen) => {self.consume(); self.function("function")},
             TokenType::If => self.if_statement(),
             TokenType::LeftBrace => {self.consume(); self.block()},
             TokenType::While => self.while_stmt(),
             TokenType::Return => self.return_stmt(),
             TokenType::Break => self.break_statement(),
+            TokenType::Import => self.import_statement(),
+            TokenType::Guard => self.guard_statement(),
+            TokenType::Try => self.try_statement(),
+            TokenType::Throw => self.throw_statement(),
+            TokenType::Defer => self.defer_statement(),
             _ => self.expression_statement(),
         }
     }
@@ -84,17 +198,79 @@ impl Parser {
         self.consume(); // eat the return token
         let mut expr = None;
 
-        if self.current_token().token_type != TokenType::Semicolon {
-            expr = Some(self.expression()?); 
+        if !self.at_terminator() {
+            let value = self.expression()?;
+            // 'return a, b' returns the array [a, b]
+            if self.current_token().token_type == TokenType::Comma {
+                let mut values = vec![value];
+                while self.current_token().token_type == TokenType::Comma {
+                    self.consume();
+                    values.push(self.expression()?);
+                }
+                expr = Some(Expr::new_array(token.clone(), values));
+            } else {
+                expr = Some(value);
+            }
         }
 
-        self.check_and_consume(TokenType::Semicolon, "Expected ';' after return value")?;
+        self.consume_terminator("Expected ';' after return value")?;
         Ok(Stmt::new_return(token, expr))
     }
 
+    fn import_statement(&mut self) -> Result<Stmt, ParseError> {
+        self.consume(); // eat the import token
+        let path = self.check_and_consume(TokenType::STRING, "Expected a file path after 'import'")?;
+        self.consume_terminator("Expected ';' after import")?;
+        Ok(Stmt::Import(path))
+    }
+
+    fn try_statement(&mut self) -> Result<Stmt, ParseError> {
+        self.consume(); // eat the try token
+        self.check_and_consume(TokenType::LeftBrace, "Expected '{' after 'try'")?;
+        let try_block = self.block()?;
+        self.check_and_consume(TokenType::Catch, "Expected 'catch' after try block")?;
+        self.check_and_consume(TokenType::LeftParen, "Expected '(' after 'catch'")?;
+        let name = self.check_and_consume(TokenType::Identifier, "Expected error name")?;
+        self.check_and_consume(TokenType::RightParen, "Expected ')' after error name")?;
+        self.check_and_consume(TokenType::LeftBrace, "Expected '{' after catch")?;
+        let catch_block = self.block()?;
+        Ok(Stmt::new_try(try_block, name, catch_block))
+    }
+
+    fn throw_statement(&mut self) -> Result<Stmt, ParseError> {
+        let token = self.current_token().clone();
+        self.consume(); // eat the throw token
+        let expr = self.expression()?;
+        self.consume_terminator("Expected ';' after thrown value")?;
+        Ok(Stmt::Throw(token, expr))
+    }
+
+    // 'defer stmt;' inside a block. Outside of one there is no scope exit to run it at
+    fn defer_statement(&mut self) -> Result<Stmt, ParseError> {
+        let token = self.current_token().clone();
+        if self.blocks == 0 {
+            return Err(ParseError::new("'defer' can only be used inside a block".to_string(), token.line));
+        }
+        self.consume(); // eat the defer token
+        let stmt = self.statement()?;
+        Ok(Stmt::Defer(token, Box::new(stmt)))
+    }
+
     fn function(&mut self, kind: &str) -> Result<Stmt, ParseError> {
         let name = self.check_and_consume(TokenType::Identifier, &format!("Expect {} name.", kind))?;
         self.check_and_consume(TokenType::LeftParen, &format!("Expect '(' after {} name.", kind))?;
+        let (parameters, body) = self.function_rest()?;
+        Ok(Stmt::new_function(name, parameters, body))
+    }
+
+    fn lambda(&mut self, func_tok: Token) -> Result<Expr, ParseError> {
+        self.check_and_consume(TokenType::LeftParen, "Expect '(' after 'func'.")?;
+        let (parameters, body) = self.function_rest()?;
+        Ok(Expr::new_lambda(func_tok, parameters, body))
+    }
+
+    // The parameters and body shared by named functions and lambdas, starting after the '('
+    fn function_rest(&mut self) -> Result<(Vec<Token>, Stmt), ParseError> {
         let mut parameters = Vec::new();
         if self.current_token().token_type != TokenType::RightParen {
             loop {
@@ -111,38 +287,59 @@ impl Parser {
         }
         self.check_and_consume(TokenType::RightParen, "Expected ')' after parameters")?;
         self.check_and_consume(TokenType::LeftBrace, "Expected '{' after function declaration")?;
-        let body = self.block()?;
-
-        Ok(Stmt::new_function(name, parameters, body))
+        // A lambda's body is its own function, so 'break' can't reach loops outside it
+        let loops = std::mem::replace(&mut self.loops, 0);
+        let body = self.block();
+        self.loops = loops;
+        Ok((parameters, body?))
     }
 
     fn break_statement(&mut self) -> Result<Stmt, ParseError> {
         if self.loops > 0 {
             self.consume();
-            self.check_and_consume(TokenType::Semicolon, "Expected ';' after statement")?;
+            self.consume_terminator("Expected ';' after statement")?;
             return Ok(Stmt::Break)
         }
         Err(ParseError::new("'break' can only be used inside a while loop".to_string(), self.current_token().line))
     }
 
     fn while_stmt(&mut self) -> Result<Stmt, ParseError> {
+        let token = self.current_token().clone();
         self.consume(); // consume the while token
-        let condition = self.expression()?;
+        let condition = self.condition("while")?;
         self.check_and_consume(TokenType::LeftBrace, "Expected '{' after while condition")?;
         self.loops +=1; // Increment the loop counter
         let block = self.block()?;
         self.loops -=1; // Decrease the loop counter after all statements have been parsed
-        Ok(Stmt::new_while(condition, block))
+        Ok(Stmt::new_while(token, condition, block))
+    }
+
+    // The condition of an if or while. The parentheses are kept as a grouping so the
+    // statement prints back the way it was written
+    fn condition(&mut self, keyword: &str) -> Result<Expr, ParseError> {
+        if !self.require_parens {
+            return self.expression()
+        }
+        let line = self.current_token().line;
+        self.check_and_consume(TokenType::LeftParen, &format!("Expected '(' after '{}'", keyword))?;
+        let expr = self.expression()?;
+        self.check_and_consume(TokenType::RightParen, &format!("Expected ')' after {} condition", keyword))?;
+        Ok(Expr::new_grouping(expr, line))
     }
 
     fn if_statement(&mut self) -> Result<Stmt, ParseError> {
         self.consume(); // consume the if Stmt
-        let expr: Expr = self.expression()?;
+        let expr: Expr = self.condition("if")?;
         self.check_and_consume(TokenType::LeftBrace, "Expected block expression after a if expression")?;
         let then_block = self.block()?;
 
         if self.current_token().token_type == TokenType::Else {
             self.consume(); // consume the else
+            if self.current_token().token_type == TokenType::If {
+                // 'else if' chains become a nested if statement in the else branch
+                let else_if = self.if_statement()?;
+                return Ok(Stmt::new_if(expr, then_block, Some(else_if)));
+            }
             self.check_and_consume(TokenType::LeftBrace, "Expected block expression after a else expression")?;
             let else_block = self.block()?;
             return Ok(Stmt::new_if(expr, then_block, Some(else_block)));
@@ -152,27 +349,58 @@ impl Parser {
     }
 
 
+    // 'guard x else { return; }' is 'if !x { return; }'. The else block has to leave the
+    // enclosing code, so it must end with 'return', 'throw' or 'break'
+    fn guard_statement(&mut self) -> Result<Stmt, ParseError> {
+        let guard = self.current_token().clone();
+        self.consume(); // consume the guard
+        let expr: Expr = self.condition("guard")?;
+        self.check_and_consume(TokenType::Else, "Expected 'else' after guard condition")?;
+        self.check_and_consume(TokenType::LeftBrace, "Expected block expression after a else expression")?;
+        let else_block = self.block()?;
+        let diverges = match &else_block {
+            Stmt::Block(statements) => matches!(statements.last(), Some(Stmt::ReturnStmt(_)) | Some(Stmt::Throw(_, _)) | Some(Stmt::Break)),
+            _ => false,
+        };
+        if !diverges {
+            return Err(ParseError::new("A guard's else block must end with 'return', 'throw' or 'break'".to_string(), guard.line));
+        }
+        let not = Token::new(TokenType::Bang, String::from("!"), guard.line);
+        Ok(Stmt::new_if(Expr::new_unary(not, expr), else_block, None))
+    }
+
     fn block(&mut self) -> Result<Stmt, ParseError> {
-        let mut statements = Vec::new();
+        Ok(Stmt::new_block(self.block_statements()?))
+    }
+
+    // The statements up to and including the '}' that closes a block
+    fn block_statements(&mut self) -> Result<Vec<Stmt>, ParseError> {
+        self.block_statements_after(Vec::new())
+    }
+
+    // The rest of a block whose first statements have been parsed
+    fn block_statements_after(&mut self, statements: Vec<Stmt>) -> Result<Vec<Stmt>, ParseError> {
+        self.blocks += 1;
+        let statements = self.statements_until_brace(statements);
+        self.blocks -= 1;
+        statements
+    }
+
+    fn statements_until_brace(&mut self, mut statements: Vec<Stmt>) -> Result<Vec<Stmt>, ParseError> {
         while self.current_token().token_type != TokenType::RightBrace && !self.is_at_end() {
             let statement = self.declaration()?;
             statements.push(statement)
         }
 
         self.check_and_consume(TokenType::RightBrace, "Expected '}'")?;
-        Ok(Stmt::new_block(statements))
+        Ok(statements)
     }
 
 
     fn expression_statement(&mut self) -> Result<Stmt, ParseError> {
         let expr: Expr = self.expression()?;
-        match self.current_token().token_type {
-            TokenType::Semicolon => {
-                self.consume();
-                Ok(Stmt::ExprStmt(expr))
-            },
-            _ => Err(ParseError::new("Expected ';'".to_string(), self.current_token().line)),
-        }
+        self.consume_terminator("Expected ';'")?;
+        Ok(Stmt::ExprStmt(expr))
     }
 
     fn expression(&mut self) -> Result<Expr, ParseError> {
@@ -180,13 +408,13 @@ impl Parser {
     }
 
     fn assignment(&mut self) -> Result<Expr, ParseError> {
-        let expr = self.logical_or()?;
+        let expr = self.binary(LOWEST)?;
         match self.current_token().token_type {
             TokenType::Equal => {
                 self.consume();
                 let value = self.assignment()?;
 
-                if let Expr::V(tok) = expr {
+                if let Expr::V(tok, _) = expr {
                     return Ok(Expr::new_assignment(tok, value));
                 }
                 return Err(ParseError::new("Invalid assignment target".to_string(), self.current_token().line));
@@ -202,127 +430,72 @@ impl Parser {
             _ => Token::new(TokenType::Minus, "-".to_string(), self.current_token().line),
         };
         self.consume(); // eat += or -=
-        let right = self.addition()?;
-        if let Expr::V(ref tok) = expr {
+        let right = self.binary(ADDITION)?;
+        if let Expr::V(ref tok, _) = expr {
             return Ok(Expr::new_assignment(tok.clone(), Expr::new_binary(expr, token, right)));
         }
         return Err(ParseError::new("Invalid assignment target. Expected Value".to_string(), self.current_token().line));
     }
 
-    fn logical_or(&mut self) -> Result<Expr, ParseError> {
-        let left: Expr = self.logical_and()?;
-        if self.current_token().token_type == TokenType::Or {
-            let tok = self.current_token().clone();
-            self.consume(); // consume the or
-            let right = self.logical_and()?;
-            return Ok(Expr::new_logical(tok, left, right))
-        }
-        Ok(left)
-    }
-
-    fn logical_and(&mut self) -> Result<Expr, ParseError> {
-        let left: Expr = self.conditional()?;
-        if self.current_token().token_type == TokenType::And {
-            let tok = self.current_token().clone();
-            self.consume(); // consume the and
-            let right = self.conditional()?;
-            return Ok(Expr::new_logical(tok, left, right))
-        }
-        Ok(left)
-    }
-
-    fn conditional(&mut self) -> Result<Expr, ParseError> {
-        let expr: Expr = self.equality()?;
-
-        if self.current_token().token_type == TokenType::Question {
-            self.consume(); // consume the '?'
-            let then_expr = self.expression()?;
-            if self.current_token().token_type == TokenType::Colon {
-                self.consume(); // consume the ':'
-                let else_expr = self.conditional()?;
-                return Ok(Expr::new_conditional(expr, then_expr, else_expr, self.current_token().line))
-            }
-            return Err(ParseError::new("Expected : after then expression".to_string(), self.current_token().line));
-        }
-        Ok(expr)
-    }
-
-
-    fn equality(&mut self) -> Result<Expr, ParseError> {
-        let mut left: Expr = self.comparison()?;
-        loop {
-            match self.current_token().token_type {
-                TokenType::EqualEqual | TokenType::BangEqual => {
-                    let operator = self.current_token().clone();
-                    self.consume();
-                    let right = self.comparison()?;
-                    left = Expr::new_binary(left, operator, right);
-                },
-                _ => break,
-            }
-        }
-        Ok(left)
+    // Parses binary operators by precedence climbing. Each operator's precedence and
+    // associativity come from infix_operator, so a new operator only needs an entry there.
+    // Only operators binding at least as tightly as min_precedence are taken
+    fn binary(&mut self, min_precedence: u8) -> Result<Expr, ParseError> {
+        let left = self.unary()?;
+        self.climb(left, min_precedence)
     }
 
-    fn comparison(&mut self) -> Result<Expr, ParseError> {
-        let mut left: Expr = self.addition()?;
+    fn climb(&mut self, mut left: Expr, min_precedence: u8) -> Result<Expr, ParseError> {
         loop {
-            match self.current_token().token_type {
-                TokenType::Greater | TokenType::GreaterEqual | TokenType::Less | TokenType::LessEqual => {
-                    let operator = self.current_token().clone();
-                    self.consume();
-                    let right = self.addition()?;
-                    left = Expr::new_binary(left, operator, right);
-                },
-                _ => break,
+            let (precedence, associativity) = match infix_operator(&self.current_token().token_type) {
+                Some((precedence, associativity)) if precedence >= min_precedence => (precedence, associativity),
+                _ => return Ok(left),
+            };
+            let operator = self.current_token().clone();
+            self.consume();
+            if operator.token_type == TokenType::Question {
+                left = self.conditional(left)?;
+                continue;
             }
+            let next = match associativity {
+                Associativity::Left => precedence + 1,
+                Associativity::Right => precedence,
+            };
+            let right = self.binary(next)?;
+            left = match operator.token_type {
+                TokenType::And | TokenType::Or => Expr::new_logical(operator, left, right),
+                _ => Expr::new_binary(left, operator, right),
+            };
         }
-        Ok(left)
     }
 
-    fn addition(&mut self) -> Result<Expr, ParseError> {
-        let mut expr: Expr = self.multiplication()?;
-        loop {
-            match self.current_token().token_type {
-                TokenType::Plus | TokenType::Minus | TokenType::PlusPlus => {
-                    let operator = self.current_token().clone();
-                    self.consume();
-                    let right = self.multiplication()?;
-                    expr = Expr::new_binary(expr, operator, right);    
-                },
-                _ => break,
-            }
+    // The rest of 'cond ? then : else' after the '?'. The else branch can be another
+    // conditional, but not an 'and' or 'or'
+    fn conditional(&mut self, cond: Expr) -> Result<Expr, ParseError> {
+        let then_expr = self.expression()?;
+        if self.current_token().token_type == TokenType::Colon {
+            self.consume(); // consume the ':'
+            let else_expr = self.binary(CONDITIONAL)?;
+            return Ok(Expr::new_conditional(cond, then_expr, else_expr, self.current_token().line))
         }
-        Ok(expr)
+        Err(ParseError::new("Expected : after then expression".to_string(), self.current_token().line))
     }
 
-    fn multiplication(&mut self) -> Result<Expr, ParseError> {
-        let mut expr: Expr = self.unary()?;
-        loop {
-            match self.current_token().token_type {
-                TokenType::Star | TokenType::Slash => {
-                    let operator = self.current_token().clone();
-                    self.consume();
-                    let right = self.unary()?;
-                    expr = Expr::new_binary(expr, operator, right);
-                },
-                _ => break,
-            }
-        }
-        Ok(expr)
-    }
-
-
+    // '**' binds tighter than the unary operators on its left, so -2 ** 2 is -4. The
+    // exponent is parsed as a unary too, so 2 ** -1 works
     fn unary(&mut self) -> Result<Expr, ParseError> {
         match self.current_token().token_type {
-            TokenType::Minus | TokenType::Bang => {
+            TokenType::Minus | TokenType::Tilde | TokenType::Bang | TokenType::Not => {
                 let operator = self.current_token().clone();
                 self.consume();
                 let expr = self.unary()?;
                 Ok(Expr::new_unary(operator, expr))
             },
-            _ => self.call(),
-        }   
+            _ => {
+                let expr = self.call()?;
+                self.climb(expr, POWER)
+            },
+        }
     }
 
     fn call(&mut self) -> Result<Expr, ParseError> {
@@ -332,6 +505,12 @@ impl Parser {
             if self.current_token().token_type == TokenType::LeftParen {
                 self.consume(); // eat the '('
                 expr = self.finish_call(expr)?;
+            } else if self.current_token().token_type == TokenType::Dot {
+                self.consume(); // eat the '.'
+                let name = self.check_and_consume(TokenType::Identifier, "Expected method name after '.'")?;
+                self.check_and_consume(TokenType::LeftParen, "Expected '(' after method name")?;
+                let (arguments, paren) = self.arguments()?;
+                expr = Expr::new_method(expr, name, arguments, paren);
             } else {
                 break;
             }
@@ -343,22 +522,22 @@ impl Parser {
         let token = self.current_token();
         match token.token_type {
             TokenType::NUMBER => {
-                let e = Expr::new_literal(token.lexeme.clone());
+                let e = Expr::new_literal(token.lexeme.clone(), token.token_type.clone(), token.line);
                 self.consume();
                 Ok(e)
             },
             TokenType::STRING => {
-                let e = Expr::new_literal(token.lexeme.clone());
+                let e = Expr::new_literal(token.lexeme.clone(), token.token_type.clone(), token.line);
                 self.consume();
                 Ok(e)
             },
             TokenType::TRUE | TokenType::FALSE => {
-                let e = Expr::new_literal(token.lexeme.clone());
+                let e = Expr::new_literal(token.lexeme.clone(), token.token_type.clone(), token.line);
                 self.consume();
                 Ok(e)
             },
             TokenType::Nil => {
-                let e = Expr::new_literal(token.lexeme.clone());
+                let e = Expr::new_literal(token.lexeme.clone(), token.token_type.clone(), token.line);
                 self.consume();
                 Ok(e)
             },
@@ -367,16 +546,38 @@ impl Parser {
                 self.consume();
                 Ok(e)
             },
+            TokenType::LeftBracket => {
+                let tok = token.clone();
+                self.consume(); // eat the '['
+                self.array_literal(tok)
+            },
+            TokenType::Func => {
+                let tok = token.clone();
+                self.consume(); // eat the 'func'
+                self.lambda(tok)
+            },
+            TokenType::Match => {
+                let tok = token.clone();
+                self.consume(); // eat the 'match'
+                self.match_expression(tok)
+            },
+            // In expression position '{' starts either a map literal or a block
+            TokenType::LeftBrace => {
+                let tok = token.clone();
+                self.consume(); // eat the '{'
+                self.block_or_map(tok)
+            },
             // Error handling cases below
             TokenType::LeftParen => {
+                let line = token.line;
                 self.consume();
                 let expr: Expr = self.expression()?;
                 match self.consume_right_paren() {
-                    Ok(_) => Ok(Expr::new_grouping(expr)),
+                    Ok(_) => Ok(Expr::new_grouping(expr, line)),
                     Err(lexeme) => Err(ParseError::new(format!("Expected ')' given {}", lexeme), self.current_token().line)),
                 }
             },
-            TokenType::Plus | TokenType::Minus | TokenType::Star | TokenType::Slash | TokenType::Greater
+            TokenType::Plus | TokenType::Minus | TokenType::Star | TokenType::StarStar | TokenType::Slash | TokenType::Percent | TokenType::Greater
             | TokenType::Less | TokenType::LessEqual | TokenType::GreaterEqual => {
                 Err(ParseError::new(format!("Expected number before {}", token.lexeme), token.line))
             },
@@ -389,11 +590,111 @@ impl Parser {
     }
 
 
+    fn array_literal(&mut self, tok: Token) -> Result<Expr, ParseError> {
+        let mut elements = Vec::new();
+        while self.current_token().token_type != TokenType::RightBracket {
+            elements.push(self.element()?);
+            if self.current_token().token_type != TokenType::Comma {
+                break;
+            }
+            self.consume(); // eat the ','
+        }
+        self.check_and_consume(TokenType::RightBracket, "Expected ']' after array elements")?;
+        Ok(Expr::new_array(tok, elements))
+    }
+
+    // A block or a map, after the '{'. '{}' is an empty map, and a map's first expression
+    // is always followed by ':'. Anything else is a block. The first expression is only
+    // parsed once, it becomes the map's first key or the block's first statement
+    fn block_or_map(&mut self, tok: Token) -> Result<Expr, ParseError> {
+        match self.current_token().token_type {
+            TokenType::RightBrace => {
+                self.consume(); // eat the '}'
+                Ok(Expr::new_map(tok, Vec::new()))
+            },
+            TokenType::Let | TokenType::Const | TokenType::If | TokenType::Guard | TokenType::While | TokenType::Return
+            | TokenType::Break | TokenType::Import | TokenType::Try | TokenType::Throw | TokenType::Defer | TokenType::LeftBrace => {
+                Ok(Expr::new_block(self.block_statements()?))
+            },
+            // A named function declaration, where 'func (' would be a lambda
+            TokenType::Func if self.next_token_type() == Some(TokenType::Identifier) => {
+                Ok(Expr::new_block(self.block_statements()?))
+            },
+            _ => {
+                let first = self.expression()?;
+                if self.current_token().token_type == TokenType::Colon {
+                    return self.map_literal(tok, first);
+                }
+                self.consume_terminator("Expected ';'")?;
+                Ok(Expr::new_block(self.block_statements_after(vec![Stmt::ExprStmt(first)])?))
+            },
+        }
+    }
+
+    // The value and arms of a match, after the 'match'. Arms are separated by commas
+    fn match_expression(&mut self, tok: Token) -> Result<Expr, ParseError> {
+        let value = self.expression()?;
+        self.check_and_consume(TokenType::LeftBrace, "Expected '{' after match value")?;
+        let mut arms = Vec::new();
+        while self.current_token().token_type != TokenType::RightBrace {
+            let pattern = self.current_token().clone();
+            let is_type = matches!(pattern.token_type, TokenType::Identifier | TokenType::Nil) && MATCH_PATTERNS.contains(&&pattern.lexeme[..]);
+            if !is_type {
+                return Err(ParseError::new(format!("Expected a type to match, like 'number' or '_', given: {}", pattern.lexeme), pattern.line));
+            }
+            self.consume(); // eat the type
+            self.check_and_consume(TokenType::Arrow, "Expected '=>' after the type to match")?;
+            arms.push((pattern, self.expression()?));
+            if self.current_token().token_type != TokenType::Comma {
+                break;
+            }
+            self.consume(); // eat the ','
+        }
+        self.check_and_consume(TokenType::RightBrace, "Expected '}' after match arms")?;
+        Ok(Expr::new_match(tok, value, arms))
+    }
+
+    // The entries of a map whose first key has been parsed
+    fn map_literal(&mut self, tok: Token, first_key: Expr) -> Result<Expr, ParseError> {
+        let mut entries = Vec::new();
+        let mut key = first_key;
+        loop {
+            self.check_and_consume(TokenType::Colon, "Expected ':' after map key")?;
+            entries.push((key, self.expression()?));
+            if self.current_token().token_type != TokenType::Comma {
+                break;
+            }
+            self.consume(); // eat the ','
+            if self.current_token().token_type == TokenType::RightBrace {
+                break;
+            }
+            key = self.expression()?;
+        }
+        self.check_and_consume(TokenType::RightBrace, "Expected '}' after map entries")?;
+        Ok(Expr::new_map(tok, entries))
+    }
+
     fn finish_call(&mut self, callee: Expr) -> Result<Expr, ParseError> {
+        let (arguments, paren) = self.arguments()?;
+        Ok(Expr::new_call(callee, paren, arguments))
+    }
+
+    // An array element or call argument, which may spread an array: '...xs'
+    fn element(&mut self) -> Result<Expr, ParseError> {
+        if self.current_token().token_type == TokenType::DotDotDot {
+            let tok = self.current_token().clone();
+            self.consume(); // eat the '...'
+            return Ok(Expr::Spread(tok, Box::new(self.expression()?)));
+        }
+        self.expression()
+    }
+
+    // The arguments after a '(', and the ')' that ends them
+    fn arguments(&mut self) -> Result<(Vec<Expr>, Token), ParseError> {
         let mut arguments: Vec<Expr> = Vec::new();
         if self.current_token().token_type != TokenType::RightParen {
             loop{
-                arguments.push(self.expression()?);
+                arguments.push(self.element()?);
                 
                 if arguments.len() > 127 {
                     return Err(ParseError::new("Cannot have more then 127 arguments.".to_string(),self.current_token().line))
@@ -405,8 +706,7 @@ impl Parser {
             }
         }
         let paren = self.check_and_consume(TokenType::RightParen, "Expected ')' after arguments.")?;
-    
-        Ok(Expr::new_call(callee, paren, arguments))
+        Ok((arguments, paren))
     }
 
 
@@ -438,7 +738,204 @@ impl Parser {
         return Err(ParseError::new(message.to_string(), self.current_token().line));
     }
 
+    // A statement ends with a ';' or at the end of its line. The closing '}' of a block and
+    // EOF also end a statement. Newlines inside an unfinished expression (like an open
+    // '(') are never checked here, so those expressions can span lines
+    fn at_terminator(&self) -> bool {
+        match self.current_token().token_type {
+            TokenType::Semicolon | TokenType::RightBrace | TokenType::EOF => true,
+            _ => self.index > 0 && self.tokens[self.index - 1].line < self.current_token().line,
+        }
+    }
+
+    fn consume_terminator(&mut self, message: &str) -> Result<(), ParseError> {
+        if !self.at_terminator() {
+            return Err(ParseError::new(message.to_string(), self.current_token().line));
+        }
+        if self.current_token().token_type == TokenType::Semicolon {
+            self.consume();
+        }
+        Ok(())
+    }
+
+    fn next_token_type(&self) -> Option<TokenType> {
+        self.tokens.get(self.index + 1).map(|token| token.token_type.clone())
+    }
+
     fn is_at_end(&mut self) -> bool { 
         self.current_token().token_type == TokenType::EOF
     }
-}
\ No newline at end of file
+}
+
+
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::lexer::lex_source;
+
+    fn parse(source: &str) -> Result<Vec<Stmt>, ParseError> {
+        Parser::new(lex_source(source).unwrap()).parse()
+    }
+
+    #[test]
+    fn newline_terminated_statements() {
+        let statements = parse("
+            let x = 1
+            x = x + 2
+            println(x)
+        ").unwrap();
+        assert_eq!(3, statements.len());
+    }
+
+    #[test]
+    fn semicolon_terminated_statements() {
+        let statements = parse("let x = 1; x = x + 2; println(x);").unwrap();
+        assert_eq!(3, statements.len());
+    }
+
+    #[test]
+    fn statement_spanning_lines_in_parens() {
+        let statements = parse("
+            let x = (1 +
+                2 *
+                3)
+            println(x)
+        ").unwrap();
+        assert_eq!(2, statements.len());
+        assert_eq!(parse("let x = (1 + 2 * 3);").unwrap()[0].to_string(), statements[0].to_string());
+    }
+
+    #[test]
+    fn statements_on_one_line_need_semicolons() {
+        assert!(parse("let x = 1 let y = 2").is_err());
+    }
+
+    #[test]
+    fn parse_all_reports_every_error() {
+        let mut parser = Parser::new(lex_source("let = 1\nlet ok = 2\nconst c;\nprintln(ok)\nlet x = (1 +; let y = 3").unwrap());
+        let errors = parser.parse_all().unwrap_err();
+        let messages: Vec<String> = errors.errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(vec![
+            "Expected variable name at line: 1",
+            "Expected '=' after constant name. Constants must be initialized at line: 3",
+            "Expected expression at line: 5",
+        ], messages);
+        assert_eq!(0, errors.omitted);
+    }
+
+    #[test]
+    fn parse_all_caps_the_errors() {
+        let source: String = (1..=15).map(|i| format!("let = {}\n", i)).collect();
+        let mut parser = Parser::new(lex_source(&source).unwrap());
+        parser.max_errors = Some(3);
+        let report = parser.parse_all().unwrap_err().to_string();
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(4, lines.len());
+        assert_eq!("Expected variable name at line: 1", lines[0]);
+        assert_eq!("Expected variable name at line: 3", lines[2]);
+        assert_eq!("... and 12 more errors", lines[3]);
+    }
+
+    #[test]
+    fn required_parens_around_conditions() {
+        let parse_strict = |source: &str| {
+            let mut parser = Parser::new(lex_source(source).unwrap());
+            parser.require_parens = true;
+            parser.parse()
+        };
+        let err = parse_strict("let x = 2\nif x > 1 {}").unwrap_err();
+        assert_eq!("Expected '(' after 'if' at line: 2", err.to_string());
+        let err = parse_strict("while true {}").unwrap_err();
+        assert_eq!("Expected '(' after 'while' at line: 1", err.to_string());
+        let err = parse_strict("if (x > 1) and (y) {}").unwrap_err();
+        assert_eq!("Expected block expression after a if expression at line: 1", err.to_string());
+
+        let statements = parse_strict("let x = 2\nif (x > 1) {} else if (x) {}\nwhile (x == 3) {}").unwrap();
+        assert_eq!(parse("let x = 2\nif (x > 1) {} else if (x) {}\nwhile (x == 3) {}").unwrap(), statements);
+    }
+
+    #[test]
+    fn guard_is_an_inverted_if() {
+        assert_eq!(parse("if !x {\n    return 1\n}").unwrap(), parse("guard x else {\n    return 1\n}").unwrap());
+        let err = parse("guard x else {\n    println(x)\n}").unwrap_err();
+        assert_eq!("A guard's else block must end with 'return', 'throw' or 'break' at line: 1", err.to_string());
+        assert!(parse("guard x {\n    return\n}").is_err());
+    }
+
+    #[test]
+    fn match_arms_name_types() {
+        let expr = match parse("match x {\n    number => 1,\n    _ => 2,\n}").unwrap().remove(0) {
+            Stmt::ExprStmt(Expr::Match(m)) => m,
+            other => panic!("expected a match, got {:?}", other),
+        };
+        let patterns: Vec<&str> = expr.arms.iter().map(|(pattern, _)| &pattern.lexeme[..]).collect();
+        assert_eq!(vec!["number", "_"], patterns);
+
+        let err = parse("match x { float => 1 }").unwrap_err();
+        assert_eq!("Expected a type to match, like 'number' or '_', given: float at line: 1", err.to_string());
+        assert!(parse("match x { number 1 }").is_err());
+    }
+
+    // The tree an expression parses to, without its Grouping nodes so explicit
+    // parentheses compare equal to the precedence that makes them unneeded
+    fn tree(source: &str) -> String {
+        crate::optimizer::optimize_expr(Parser::new(lex_source(source).unwrap()).parse_expression().unwrap()).to_string()
+    }
+
+    #[test]
+    fn operator_precedence_and_associativity() {
+        assert_eq!(tree("1 + (2 * 3)"), tree("1 + 2 * 3"));
+        assert_eq!(tree("(1 - 2) + 3"), tree("1 - 2 + 3"));
+        assert_eq!(tree("(8 / 4) * 2"), tree("8 / 4 * 2"));
+        assert_eq!(tree("2 ** (3 ** 2)"), tree("2 ** 3 ** 2"));
+        assert_eq!(tree("-(2 ** 2)"), tree("-2 ** 2"));
+        assert_eq!(tree("2 * (3 ** 2)"), tree("2 * 3 ** 2"));
+        assert_eq!(tree("2 ** (-1)"), tree("2 ** -1"));
+        assert_eq!(tree("(1 + 2) == (3 * 1)"), tree("1 + 2 == 3 * 1"));
+        assert_eq!(tree("(a == b) == c"), tree("a == b == c"));
+        assert_eq!(tree("(1 < 2) == (3 >= 4)"), tree("1 < 2 == 3 >= 4"));
+        assert_eq!(tree("(a == b) and (c != d)"), tree("a == b and c != d"));
+        assert_eq!(tree("(a and b) and c"), tree("a and b and c"));
+        assert_eq!(tree("a or (b and c)"), tree("a or b and c"));
+        assert_eq!(tree("x = (a or b)"), tree("x = a or b"));
+
+        // The conditional sits between 'and' and '=='
+        assert_eq!(tree("a and ((b == c) ? 1 : 2)"), tree("a and b == c ? 1 : 2"));
+        assert_eq!(tree("a ? 1 : (b ? 2 : 3)"), tree("a ? 1 : b ? 2 : 3"));
+        assert_eq!(tree("(a ? 1 : (2 + 3)) or b"), tree("a ? 1 : 2 + 3 or b"));
+
+        assert_eq!("('+' ('*' 1 2) ('**' 3 ('**' 4 5)))", tree("1 * 2 + 3 ** 4 ** 5"));
+        assert_eq!(tree("x = x + (2 * 3)"), tree("x += 2 * 3"));
+    }
+
+    #[test]
+    fn defer_needs_a_block() {
+        let statements = parse("func f() {\n    defer println(1);\n}").unwrap();
+        assert_eq!(1, statements.len());
+        let err = parse("defer println(1);").unwrap_err();
+        assert_eq!("'defer' can only be used inside a block at line: 1", err.to_string());
+    }
+
+    #[test]
+    fn nested_block_expressions_parse_once() {
+        // Deciding block or map used to parse each level twice, doubling the time per level
+        let depth = 40;
+        let source = format!("let x = {}1{};", "{1 + ".repeat(depth), "}".repeat(depth));
+        assert_eq!(1, parse(&source).unwrap().len());
+
+        assert_eq!(parse("let m = {\"a\": 1, \"b\": 2,};").unwrap(), parse("let m = {\"a\": 1, \"b\": 2};").unwrap());
+        assert!(matches!(&parse("let b = {\n    x = 1;\n    x;\n};").unwrap()[0], Stmt::VarDecl(_, Some(Expr::Block(statements))) if statements.len() == 2));
+        assert!(parse("let m = {\"a\" 1};").is_err());
+    }
+
+    #[test]
+    fn block_expressions_can_start_with_a_function() {
+        let statements = parse("let x = {\n    func g() { return 1 }\n    g()\n}").unwrap();
+        assert!(matches!(&statements[0], Stmt::VarDecl(_, Some(Expr::Block(block))) if matches!(block[0], Stmt::FuncStmt(_)) && block.len() == 2));
+        // 'func (' is still a lambda, so the block's value is the function
+        let statements = parse("let f = {\n    func (a) { return a }\n}").unwrap();
+        assert!(matches!(&statements[0], Stmt::VarDecl(_, Some(Expr::Block(block))) if matches!(block[0], Stmt::ExprStmt(_))));
+    }
+}
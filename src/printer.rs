@@ -0,0 +1,322 @@
+use crate::ast::{Expr, Stmt, Function};
+use crate::lexer::TokenType;
+
+// Reconstructs Flax source code from the Abstract Syntax Tree.
+// Unlike the Display implementations (which print a Lisp like tree for debugging),
+// the output here is valid Flax that parses back into the same tree.
+//
+// Parentheses are only added where precedence requires them. Groupings the user
+// wrote are part of the tree, so they are always kept.
+
+
+// Binding power of each kind of expression, loosest first. Mirrors the parser
+const ASSIGNMENT: u8 = 1;
+const OR: u8 = 2;
+const AND: u8 = 3;
+const CONDITIONAL: u8 = 4;
+const EQUALITY: u8 = 5;
+const COMPARISON: u8 = 6;
+const ADDITION: u8 = 7;
+const MULTIPLICATION: u8 = 8;
+const UNARY: u8 = 9;
+const POWER: u8 = 10;
+const CALL: u8 = 11;
+const PRIMARY: u8 = 12;
+
+const INDENT: &str = "    ";
+
+
+pub fn to_source(expr: &Expr) -> String {
+    expr_source(expr, ASSIGNMENT)
+}
+
+pub fn stmt_to_source(stmt: &Stmt) -> String {
+    stmt_source(stmt, 0)
+}
+
+pub fn program_to_source(statements: &[Stmt]) -> String {
+    statements.iter().map(stmt_to_source).collect::<Vec<String>>().join("\n")
+}
+
+
+fn precedence(expr: &Expr) -> u8 {
+    match expr {
+        Expr::A(_, _, _) => ASSIGNMENT,
+        Expr::Log(logical) => match logical.tok.token_type {
+            TokenType::Or => OR,
+            _ => AND,
+        },
+        Expr::C(_) => CONDITIONAL,
+        Expr::B(binary) => binary_precedence(&binary.operator.token_type),
+        Expr::U(_) => UNARY,
+        Expr::Cal(_) | Expr::Method(_) => CALL,
+        Expr::L(_) | Expr::V(_, _) | Expr::G(_) | Expr::Arr(_) | Expr::Map(_) | Expr::Lambda(_)
+        | Expr::Block(_) | Expr::Spread(_, _) | Expr::Match(_) => PRIMARY,
+    }
+}
+
+fn binary_precedence(token_type: &TokenType) -> u8 {
+    match token_type {
+        TokenType::EqualEqual | TokenType::BangEqual => EQUALITY,
+        TokenType::Greater | TokenType::GreaterEqual | TokenType::Less | TokenType::LessEqual => COMPARISON,
+        TokenType::Plus | TokenType::Minus | TokenType::PlusPlus => ADDITION,
+        TokenType::StarStar => POWER,
+        _ => MULTIPLICATION,
+    }
+}
+
+// Prints the expression, wrapping it in parentheses if it binds looser than `min`
+fn expr_source(expr: &Expr, min: u8) -> String {
+    let prec = precedence(expr);
+    let source = match expr {
+        Expr::L(lit) => match lit.token_type {
+            TokenType::STRING => format!("\"{}\"", lit.val),
+            _ => lit.val.clone(),
+        },
+        Expr::V(tok, _) => tok.lexeme.clone(),
+        Expr::G(grp) => format!("({})", expr_source(&grp.expr, ASSIGNMENT)),
+        Expr::A(tok, value, _) => format!("{} = {}", tok.lexeme, expr_source(value, ASSIGNMENT)),
+        // '**' is right associative and takes a unary exponent
+        Expr::B(bi) if bi.operator.token_type == TokenType::StarStar => {
+            format!("{} ** {}", expr_source(&bi.left, CALL), expr_source(&bi.right, UNARY))
+        },
+        // Binary and logical operators are left associative, so the right operand must bind tighter
+        Expr::B(bi) => format!("{} {} {}", expr_source(&bi.left, prec), bi.operator.lexeme, expr_source(&bi.right, prec + 1)),
+        Expr::Log(log) => format!("{} {} {}", expr_source(&log.left, prec), log.tok.lexeme, expr_source(&log.right, prec + 1)),
+        Expr::C(cond) => {
+            format!("{} ? {} : {}", expr_source(&cond.cond, EQUALITY), expr_source(&cond.then_expr, ASSIGNMENT),
+                expr_source(&cond.else_expr, CONDITIONAL))
+        },
+        Expr::U(ur) => {
+            let operand = expr_source(&ur.expr, UNARY);
+            // The lexer reads runs of operator characters as one token, so keep '- -x' from
+            // becoming '--x'. Word operators like 'not' always need a space
+            if ur.operator.token_type == TokenType::Not || operand.starts_with(['-', '!', '+', '=', '<', '>']) {
+                format!("{} {}", ur.operator.lexeme, operand)
+            } else {
+                format!("{}{}", ur.operator.lexeme, operand)
+            }
+        },
+        Expr::Cal(call) => {
+            let args: Vec<String> = call.args.iter().map(|arg| expr_source(arg, ASSIGNMENT)).collect();
+            format!("{}({})", expr_source(&call.callee, CALL), args.join(", "))
+        },
+        Expr::Method(method) => {
+            let args: Vec<String> = method.args.iter().map(|arg| expr_source(arg, ASSIGNMENT)).collect();
+            format!("{}.{}({})", expr_source(&method.receiver, CALL), method.name.lexeme, args.join(", "))
+        },
+        Expr::Arr(arr) => {
+            let elements: Vec<String> = arr.elements.iter().map(|e| expr_source(e, ASSIGNMENT)).collect();
+            format!("[{}]", elements.join(", "))
+        },
+        Expr::Map(map) => {
+            let entries: Vec<String> = map.entries.iter()
+                .map(|(k, v)| format!("{}: {}", expr_source(k, ASSIGNMENT), expr_source(v, ASSIGNMENT)))
+                .collect();
+            format!("{{{}}}", entries.join(", "))
+        },
+        // Expressions don't track their indentation, so a lambda's body is indented from the left margin
+        Expr::Lambda(func) => {
+            let params: Vec<&str> = func.params.iter().map(|param| &param.lexeme[..]).collect();
+            format!("func ({}) {}", params.join(", "), block_source(&func.body, 0))
+        },
+        Expr::Block(statements) => statements_source(statements, 0),
+        Expr::Spread(_, expr) => format!("...{}", expr_source(expr, ASSIGNMENT)),
+        Expr::Match(m) => {
+            let arms: Vec<String> = m.arms.iter()
+                .map(|(pattern, value)| format!("{} => {}", pattern.lexeme, expr_source(value, ASSIGNMENT)))
+                .collect();
+            format!("match {} {{ {} }}", expr_source(&m.value, ASSIGNMENT), arms.join(", "))
+        },
+    };
+
+    if prec < min {
+        format!("({})", source)
+    } else {
+        source
+    }
+}
+
+
+fn stmt_source(stmt: &Stmt, depth: usize) -> String {
+    let indent = INDENT.repeat(depth);
+    match stmt {
+        Stmt::ExprStmt(expr) => format!("{}{};", indent, to_source(expr)),
+        Stmt::VarDecl(name, Some(expr)) => format!("{}let {} = {};", indent, name.lexeme, to_source(expr)),
+        Stmt::VarDecl(name, None) => format!("{}let {};", indent, name.lexeme),
+        Stmt::Destructure(names, _, expr) => {
+            let names: Vec<&str> = names.iter().map(|name| &name.lexeme[..]).collect();
+            format!("{}let {} = {};", indent, names.join(", "), to_source(expr))
+        },
+        Stmt::ConstDecl(name, expr) => format!("{}const {} = {};", indent, name.lexeme, to_source(expr)),
+        Stmt::Block(_) => format!("{}{}", indent, block_source(stmt, depth)),
+        Stmt::IfStmt(if_stmt) => format!("{}{}", indent, if_source(&if_stmt.conditional, &if_stmt.then_block, &if_stmt.else_block, depth)),
+        Stmt::WhileStmt(_, cond, body) => format!("{}while {} {}", indent, to_source(cond), block_source(body, depth)),
+        Stmt::FuncStmt(func) => format!("{}{}", indent, function_source(func, depth)),
+        Stmt::ReturnStmt(ret) => match &ret.expr {
+            Some(expr) => format!("{}return {};", indent, to_source(expr)),
+            None => format!("{}return;", indent),
+        },
+        Stmt::Import(path) => format!("{}import \"{}\";", indent, path.lexeme),
+        Stmt::TryStmt(stmt) => {
+            format!("{}try {} catch ({}) {}", indent, block_source(&stmt.try_block, depth), stmt.name.lexeme,
+                block_source(&stmt.catch_block, depth))
+        },
+        Stmt::Throw(_, expr) => format!("{}throw {};", indent, to_source(expr)),
+        Stmt::Defer(_, stmt) => format!("{}defer {}", indent, stmt_source(stmt, depth).trim_start()),
+        Stmt::Break => format!("{}break;", indent),
+    }
+}
+
+// Prints a block starting at its '{'. The caller is responsible for the indentation before it
+fn block_source(block: &Stmt, depth: usize) -> String {
+    match block {
+        Stmt::Block(statements) => statements_source(statements, depth),
+        stmt => statements_source(std::slice::from_ref(stmt), depth),
+    }
+}
+
+fn statements_source(statements: &[Stmt], depth: usize) -> String {
+    let statements: Vec<String> = statements.iter().map(|stmt| stmt_source(stmt, depth + 1)).collect();
+    if statements.is_empty() {
+        return String::from("{}");
+    }
+    format!("{{\n{}\n{}}}", statements.join("\n"), INDENT.repeat(depth))
+}
+
+fn if_source(cond: &Expr, then_block: &Stmt, else_block: &Option<Stmt>, depth: usize) -> String {
+    let source = format!("if {} {}", to_source(cond), block_source(then_block, depth));
+    match else_block {
+        Some(Stmt::IfStmt(else_if)) => {
+            format!("{} else {}", source, if_source(&else_if.conditional, &else_if.then_block, &else_if.else_block, depth))
+        },
+        Some(block) => format!("{} else {}", source, block_source(block, depth)),
+        None => source,
+    }
+}
+
+fn function_source(func: &Function, depth: usize) -> String {
+    let params: Vec<&str> = func.params.iter().map(|param| &param.lexeme[..]).collect();
+    format!("func {}({}) {}", func.name.lexeme, params.join(", "), block_source(&func.body, depth))
+}
+
+
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::lexer::{lex_line, lex_source, Token};
+    use crate::parser::Parser;
+
+    fn parse_expr(source: &str) -> Expr {
+        Parser::new(lex_line(source.to_string()).unwrap()).parse_expression().unwrap()
+    }
+
+    fn parse(source: &str) -> Vec<Stmt> {
+        Parser::new(lex_source(source).unwrap()).parse().unwrap()
+    }
+
+    fn number(val: &str) -> Expr {
+        Expr::new_literal(val.to_string(), TokenType::NUMBER, 1)
+    }
+
+    fn op(token_type: TokenType, lexeme: &str) -> Token {
+        Token::new(token_type, lexeme.to_string(), 1)
+    }
+
+    #[test]
+    fn only_needed_parens_are_added() {
+        let expr = Expr::new_binary(number("1"), op(TokenType::Plus, "+"),
+            Expr::new_binary(number("2"), op(TokenType::Star, "*"), number("3")));
+        assert_eq!("1 + 2 * 3", to_source(&expr));
+
+        let expr = Expr::new_binary(Expr::new_binary(number("1"), op(TokenType::Plus, "+"), number("2")),
+            op(TokenType::Star, "*"), number("3"));
+        assert_eq!("(1 + 2) * 3", to_source(&expr));
+
+        let expr = Expr::new_binary(number("1"), op(TokenType::Minus, "-"),
+            Expr::new_binary(number("2"), op(TokenType::Minus, "-"), number("3")));
+        assert_eq!("1 - (2 - 3)", to_source(&expr));
+    }
+
+    #[test]
+    fn expressions_round_trip() {
+        let sources = [
+            "1 + 2 * 3",
+            "(1 + 2) * 3",
+            "1 - 2 - 3",
+            "x = y = 4",
+            "-(-5)",
+            "- -5",
+            "! !true",
+            "not a and b or c",
+            "a == b ? \"yes\" : c < d ? 1 : 2",
+            "\"a\" ++ 1 ++ f(1, g(2), x + 3)",
+            "a >= 1 != false",
+            "[1, [2, \"three\"], {\"k\": [x]}]",
+            "{}",
+            "map(func (x) {}, [1])",
+            "-2 ** -x ** 2 * 3",
+            "-~x + ~y",
+            "(2 ** 3) ** 2",
+            "(-2) ** 2",
+            "\"  x \".trim().upper()",
+            "(a ++ b).charAt(0) ++ s.lower()",
+            "f(...args, [0, ...xs], ...[1])",
+            "match x { number => x + 1, nil => 0, _ => f(x) }",
+        ];
+        for source in sources.iter() {
+            let expr = parse_expr(source);
+            let printed = to_source(&expr);
+            assert_eq!(expr, parse_expr(&printed), "{} printed as {}", source, printed);
+            assert_eq!(*source, printed);
+        }
+    }
+
+    #[test]
+    fn statements_round_trip() {
+        let source = "\
+import \"lib.flax\";
+const limit = 10;
+let total;
+let low, high = bounds();
+func add(a, b) {
+    return a + b;
+}
+while total < limit {
+    if total == 3 {
+        break;
+    } else if total == 2 {
+        total = add(total, 2);
+    } else {
+        total = total + 1;
+    }
+}
+{
+    let shadow = total;
+    defer println(shadow);
+    defer {
+        shadow = 0;
+    }
+}
+try {
+    total = total / 0;
+} catch (e) {
+    throw \"failed: \" ++ e;
+}";
+        let statements = parse(source);
+        let printed = program_to_source(&statements);
+        assert_eq!(source, printed);
+        assert_eq!(statements, parse(&printed));
+    }
+
+    #[test]
+    fn block_expressions_round_trip() {
+        let source = "let x = {\n    let t = 2;\n    t * 3;\n};";
+        let statements = parse(source);
+        let printed = program_to_source(&statements);
+        assert_eq!(source, printed);
+        assert_eq!(statements, parse(&printed));
+    }
+}
@@ -0,0 +1,58 @@
+use std::fmt;
+use std::collections::BTreeMap;
+
+use crate::ast::Expr;
+
+// Counts how many times each kind of expression is evaluated. Attach one to an
+// Interpreter to find out where a program spends its time
+#[derive(Debug, Default)]
+pub struct Profiler {
+    counts: BTreeMap<&'static str, u64>,
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler::default()
+    }
+
+    pub fn record(&mut self, expr: &Expr) {
+        *self.counts.entry(kind(expr)).or_insert(0) += 1;
+    }
+
+    // How many times the kind of expression (such as "binary" or "call") was evaluated
+    #[allow(dead_code)]
+    pub fn count(&self, kind: &str) -> u64 {
+        self.counts.get(kind).copied().unwrap_or(0)
+    }
+}
+
+fn kind(expr: &Expr) -> &'static str {
+    match expr {
+        Expr::L(_) => "literal",
+        Expr::U(_) => "unary",
+        Expr::B(_) => "binary",
+        Expr::G(_) => "grouping",
+        Expr::C(_) => "conditional",
+        Expr::V(_, _) => "variable",
+        Expr::A(_, _, _) => "assignment",
+        Expr::Log(_) => "logical",
+        Expr::Cal(_) => "call",
+        Expr::Method(_) => "method",
+        Expr::Arr(_) => "array",
+        Expr::Map(_) => "map",
+        Expr::Lambda(_) => "lambda",
+        Expr::Block(_) => "block",
+        Expr::Spread(_, _) => "spread",
+        Expr::Match(_) => "match",
+    }
+}
+
+// The report lists every kind that was evaluated, most frequent first
+impl fmt::Display for Profiler {
+    fn fmt(&self, f: &mut fmt::Formatter<>) -> fmt::Result {
+        let mut counts: Vec<(&&str, &u64)> = self.counts.iter().collect();
+        counts.sort_by(|a, b| b.1.cmp(a.1));
+        let lines: Vec<String> = counts.iter().map(|(kind, count)| format!("{:<12} {}", kind, count)).collect();
+        write!(f, "{}", lines.join("\n"))
+    }
+}
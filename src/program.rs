@@ -0,0 +1,83 @@
+use std::cell::RefCell;
+
+use crate::ast::Stmt;
+use crate::environment::Environment;
+use crate::errors::RuntimeError;
+use crate::interpreter::Interpreter;
+use crate::lexer;
+use crate::parser::Parser;
+
+// A program parsed once and run many times, for embedders that run the same script
+// over and over (like a game running a script every frame). Each run continues from
+// the environment it's given, so variables set by one run are there for the next.
+//
+// The program isn't resolved ahead of time since the environments it will run in
+// aren't known yet, so its variables are looked up by name.
+
+
+pub struct CompiledProgram {
+    statements: Vec<Stmt>,
+    interpreter: RefCell<Interpreter>,
+}
+
+impl CompiledProgram {
+    // Lexes and parses the source. The error lists what was wrong with it
+    pub fn compile(source: &str) -> Result<CompiledProgram, String> {
+        let tokens = lexer::lex_source(source)
+            .map_err(|errors| errors.iter().map(|e| e.to_string()).collect::<Vec<String>>().join("\n"))?;
+        let statements = Parser::new(tokens).parse().map_err(|e| e.to_string())?;
+        Ok(CompiledProgram { statements, interpreter: RefCell::new(Interpreter::new()) })
+    }
+
+    // A new environment with the builtins in it, for runs of the program to share
+    pub fn environment(&self) -> Environment {
+        self.interpreter.borrow().globals.clone().new_lexical()
+    }
+
+    pub fn run_on(&self, env: &mut Environment) -> Result<(), RuntimeError> {
+        self.interpreter.borrow_mut().run_statements(&self.statements, env)
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::interpreter::Value;
+    use crate::lexer::{Token, TokenType};
+
+    fn lookup(env: &mut Environment, name: &str) -> Value {
+        env.get(&Token::new(TokenType::Identifier, name.to_string(), 1)).unwrap()
+    }
+
+    #[test]
+    fn runs_keep_their_state() {
+        let program = CompiledProgram::compile("runs = runs + 1;\nlog = log ++ str(runs);").unwrap();
+        let mut env = program.environment();
+        env.define(String::from("runs"), Some(Value::INT(0)));
+        env.define(String::from("log"), Some(Value::STRING(String::new())));
+
+        program.run_on(&mut env).unwrap();
+        program.run_on(&mut env).unwrap();
+        assert_eq!(Value::INT(2), lookup(&mut env, "runs"));
+        assert_eq!(Value::STRING("12".to_string()), lookup(&mut env, "log"));
+
+        // Another environment starts over
+        let mut other = program.environment();
+        other.define(String::from("runs"), Some(Value::INT(10)));
+        other.define(String::from("log"), Some(Value::STRING(String::new())));
+        program.run_on(&mut other).unwrap();
+        assert_eq!(Value::INT(11), lookup(&mut other, "runs"));
+        assert_eq!(Value::INT(2), lookup(&mut env, "runs"));
+    }
+
+    #[test]
+    fn compile_errors_are_reported() {
+        assert!(CompiledProgram::compile("let x = ;").is_err());
+        assert!(CompiledProgram::compile("let s = \"open").is_err());
+        let program = CompiledProgram::compile("missing + 1;").unwrap();
+        assert!(program.run_on(&mut program.environment()).is_err());
+    }
+}
@@ -1,32 +1,76 @@
 use std::io;
 use std::io::Write;
 use std::env;
+use std::fs;
 
 use crate::lexer;
 use crate::interpreter;
 use crate::parser;
+use crate::printer;
 use parser::{Parser};
+use crate::profiler::Profiler;
+use crate::arena::StringArena;
+use crate::errors::LexError;
+use crate::report;
+use crate::optimizer;
+use crate::lint;
+use crate::ast_json;
 use colored::*;
 
 
 pub fn run_repl() {
     //Check if REPL was run with args
     let args: Vec<String> = env::args().collect();
+    // 'flax ast <file>' prints the file's syntax tree as JSON. The errors are JSON too,
+    // but the exit code is 1 so scripts can tell them apart
+    if args.len() > 1 && args[1] == "ast" {
+        let code = match args.get(2) {
+            Some(path) => match ast_json::dump_ast_json(path) {
+                Ok(json) => {
+                    println!("{}", json);
+                    0
+                },
+                Err(json) => {
+                    println!("{}", json);
+                    1
+                },
+            },
+            None => {
+                println!("{}", "Expected a file to print the syntax tree of".red());
+                1
+            },
+        };
+        std::process::exit(code);
+    }
+    // Running a file exits with 1 when it has errors, so batch runs can tell it failed
     if args.len() > 1 {
-        let filename = &args[1];
-        parse_file(filename);
-        std::process::exit(0);
+        let ok = match parse_args(&args[1..]) {
+            Ok(options) => parse_file(&options),
+            Err(e) => {
+                println!("{}", e.red());
+                false
+            },
+        };
+        std::process::exit(if ok { 0 } else { 1 });
     }
 
     println!("{}", "Welcome to Flax! v0.1".purple());
     let mut mode = ReplMode::Normal;
+    let mut input = ReplInput::new();
     loop {
         let mut buffer = String::new();
-        print!(">>>");
+        print!("{}", if input.is_continuing() { "..." } else { ">>>" });
         io::stdout().flush().expect("Unable to flush buffer");
         let _stdin = io::stdin().read_line(&mut buffer).unwrap();
         let buffer = buffer.trim();
 
+        if input.is_continuing() {
+            if let Some(source) = input.feed(buffer) {
+                evaluate(&source, &mode);
+            }
+            continue;
+        }
+
         // Evaluate the command
         match buffer {
             ":quit" => {
@@ -37,25 +81,73 @@ pub fn run_repl() {
                 mode = ReplMode::Debug;
                 println!("{}", "Now in debug mode".yellow());
             },
+            ":tokens" => {
+                mode = ReplMode::Tokens;
+                println!("{}", "Now in tokens mode".yellow());
+            },
+            ":format" => {
+                mode = ReplMode::Format;
+                println!("{}", "Now in format mode".yellow());
+            },
             ":normal" => {
                 mode = ReplMode::Normal;
                 println!("{}", "Now in normal mode".yellow());
             },
-            _ => evaluate(buffer, &mode),
+            _ => {
+                if let Some(source) = input.feed(buffer) {
+                    evaluate(&source, &mode);
+                }
+            },
         }       
     }
 }
 
+// Collects the lines of a statement that spans several REPL inputs, like a function
+// typed one line at a time. Input is complete once the Parser stops running out of tokens
+struct ReplInput {
+    pending: Vec<String>,
+}
+
+impl ReplInput {
+    fn new() -> ReplInput {
+        ReplInput { pending: Vec::new() }
+    }
+
+    // Whether earlier lines are waiting for the rest of their statement
+    fn is_continuing(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    // Adds a line. Returns the source to run once it is complete, otherwise None and
+    // the REPL asks for another line. Errors other than running out of input are
+    // returned right away so they can be reported
+    fn feed(&mut self, line: &str) -> Option<String> {
+        self.pending.push(line.to_string());
+        let source = self.pending.join("\n");
+        if let Ok(tokens) = lexer::lex_source(&source) {
+            if let Err(e) = Parser::new(tokens).parse() {
+                if e.is_unexpected_eof() {
+                    return None;
+                }
+            }
+        }
+        self.pending.clear();
+        Some(source)
+    }
+}
+
 fn evaluate(stmt: &str, repl_mode: &ReplMode) {
     match repl_mode {
         ReplMode::Normal => parse_statement(stmt),
         ReplMode::Debug => debug_parse_statement(stmt),
+        ReplMode::Format => format_statement(stmt),
+        ReplMode::Tokens => print_tokens(stmt),
     }
 }
 
 
 fn parse_statement(stmt: &str) {
-    match lexer::lex_line(stmt.to_string()) {
+    match lexer::lex_source(stmt) {
         Ok(tokens) => {
             let mut parser = Parser::new(tokens);
             match parser.parse() {
@@ -70,34 +162,124 @@ fn parse_statement(stmt: &str) {
                 Err(e) => println!("{}", e.to_string().red())
             }
         },
-        Err(e) => println!("{}", e.to_string().red()),
+        Err(errors) => print_lex_errors(&errors, stmt),
     }
 }
 
-fn parse_file(filename: &str) {
-    let tokens = lexer::lex_file(filename);
-    match tokens {
+// Options given on the command line when running a file
+#[derive(Debug, PartialEq)]
+struct RunOptions<'a> {
+    filename: &'a str,
+    max_errors: Option<usize>,
+    profile: bool,
+    require_parens: bool,
+    optimize: bool,
+    string_arena: bool,
+    // Everything after '--', handed to the script as args()
+    script_args: &'a [String],
+}
+
+// Reads the command line: the file to run, an optional '--max-errors N', '--profile', '--require-parens',
+// '--optimize' and '--string-arena'. Arguments after '--' are for the script
+fn parse_args(args: &[String]) -> Result<RunOptions<'_>, String> {
+    let mut filename = None;
+    let mut max_errors = None;
+    let mut profile = false;
+    let mut require_parens = false;
+    let mut optimize = false;
+    let mut string_arena = false;
+    let mut script_args: &[String] = &[];
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        match arg.as_str() {
+            "--max-errors" => {
+                let count = it.next().ok_or("Expected a number after '--max-errors'")?;
+                let count = count.parse::<usize>().map_err(|_| format!("Invalid error count '{}'", count))?;
+                max_errors = Some(count);
+            },
+            "--profile" => profile = true,
+            "--require-parens" => require_parens = true,
+            "--optimize" => optimize = true,
+            "--string-arena" => string_arena = true,
+            "--" => {
+                script_args = it.as_slice();
+                break;
+            },
+            flag if flag.starts_with("--") => return Err(format!("Unknown option '{}'", flag)),
+            // Arguments for the script go after '--'
+            _ if filename.is_some() => return Err(format!("Unexpected argument '{}', pass arguments to the script after '--'", arg)),
+            _ => filename = Some(arg.as_str()),
+        }
+    }
+    match filename {
+        Some(filename) => Ok(RunOptions { filename, max_errors, profile, require_parens, optimize, string_arena, script_args }),
+        None => Err(String::from("Expected a file to run")),
+    }
+}
+
+// Whether the file ran without errors
+fn parse_file(options: &RunOptions) -> bool {
+    let source = match fs::read_to_string(options.filename) {
+        Ok(source) => source,
+        Err(e) => {
+            println!("{}", format!("Unable to read '{}': {}", options.filename, e).red());
+            return false;
+        },
+    };
+    match lexer::lex_source(&source) {
         Ok(tokens) => {
             let mut parser = Parser::new(tokens);
-            match parser.parse() {
+            parser.max_errors = options.max_errors;
+            parser.require_parens = options.require_parens;
+            match parser.parse_all() {
                 Ok(statements) => {
+                    for warning in lint::lint(&statements) {
+                        println!("{}", warning.to_string().yellow());
+                    }
+                    let statements = if options.optimize { optimizer::optimize(statements) } else { statements };
                     let mut interpreter = interpreter::Interpreter::new();
+                    interpreter.set_script(options.filename);
+                    interpreter.script_args = options.script_args.to_vec();
+                    if options.profile {
+                        interpreter.profiler = Some(Profiler::new());
+                    }
+                    if options.string_arena {
+                        interpreter.arena = Some(StringArena::new());
+                    }
                     let result = interpreter.interpret(statements);
-                    match result {
-                        Err(e) => println!("{}", e.to_string().red()),
-                        _ => (),
+                    if let Err(e) = &result {
+                        println!("{}", e.to_string().red());
+                    }
+                    if let Some(profiler) = interpreter.profiler {
+                        println!("{}", profiler.to_string().yellow());
                     }
+                    result.is_ok()
+                },
+                Err(e) => {
+                    println!("{}", e.to_string().red());
+                    false
                 },
-                Err(e) => println!("{}", e.to_string().red()),
             }
         },
-        Err(e) => println!("{}", e.to_string().red()),
+        Err(errors) => {
+            print_lex_errors(&errors, &source);
+            false
+        },
+    }
+}
+
+// Shows each lex error under the line of source it was found on
+fn print_lex_errors(errors: &[LexError], source: &str) {
+    let lines: Vec<&str> = source.lines().collect();
+    for error in errors {
+        let line = lines.get(error.line() as usize - 1).copied().unwrap_or("");
+        println!("{}", report::caret_report(line, error.line(), error.column(), error.message()).red());
     }
 }
 
 // Debug mode prints the ast
 fn debug_parse_statement(stmt: &str) {
-    match lexer::lex_line(stmt.to_string()) {
+    match lexer::lex_source(stmt) {
         Ok(tokens) => {
             let mut parser = Parser::new(tokens);
             match parser.parse_expression() {
@@ -107,11 +289,81 @@ fn debug_parse_statement(stmt: &str) {
                 Err(e) => println!("{}", e.to_string().red()),
             }
         },
-        Err(e) => println!("{}", e.to_string().red()),
+        Err(errors) => print_lex_errors(&errors, stmt),
+    }
+}
+
+// Format mode prints the statements back as formatted source
+fn format_statement(stmt: &str) {
+    match lexer::lex_source(stmt) {
+        Ok(tokens) => {
+            let mut parser = Parser::new(tokens);
+            match parser.parse() {
+                Ok(statements) => println!("{}", printer::program_to_source(&statements)),
+                Err(e) => println!("{}", e.to_string().red()),
+            }
+        },
+        Err(errors) => print_lex_errors(&errors, stmt),
+    }
+}
+
+// Tokens mode prints what the Lexer made of each line
+fn print_tokens(stmt: &str) {
+    match lexer::lex_source(stmt) {
+        Ok(tokens) => println!("{}", lexer::dump_tokens(&tokens)),
+        Err(errors) => print_lex_errors(&errors, stmt),
     }
 }
 
 #[derive(PartialEq)]
 enum ReplMode {
-    Normal, Debug
-}
\ No newline at end of file
+    Normal, Debug, Format, Tokens
+}
+
+
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn command_line_flags() {
+        let given = args(&["main.flax"]);
+        assert_eq!(Ok(RunOptions { filename: "main.flax", max_errors: None, profile: false, require_parens: false, optimize: false, string_arena: false, script_args: &[] }), parse_args(&given));
+        let given = args(&["--max-errors", "5", "main.flax", "--profile", "--require-parens", "--optimize", "--string-arena"]);
+        assert_eq!(Ok(RunOptions { filename: "main.flax", max_errors: Some(5), profile: true, require_parens: true, optimize: true, string_arena: true, script_args: &[] }), parse_args(&given));
+        let given = args(&["--profile", "main.flax", "--", "input.txt", "--profile"]);
+        let options = parse_args(&given).unwrap();
+        assert_eq!(("main.flax", true), (options.filename, options.profile));
+        assert_eq!(args(&["input.txt", "--profile"]), options.script_args);
+        assert!(parse_args(&args(&["main.flax", "--max-errors"])).is_err());
+        assert!(parse_args(&args(&["--max-errors", "lots", "main.flax"])).is_err());
+        assert!(parse_args(&args(&["--max-errors", "5"])).is_err());
+        assert_eq!(Err(String::from("Unknown option '--optimise'")), parse_args(&args(&["--optimise", "main.flax"])));
+        assert!(parse_args(&args(&["main.flax", "extra.txt"])).is_err());
+    }
+
+    #[test]
+    fn incomplete_input_continues() {
+        let mut input = ReplInput::new();
+        assert_eq!(None, input.feed("func add(a, b) {"));
+        assert!(input.is_continuing());
+        assert_eq!(None, input.feed("return a +"));
+        assert_eq!(None, input.feed("b"));
+        assert_eq!(Some(String::from("func add(a, b) {\nreturn a +\nb\n}")), input.feed("}"));
+        assert!(!input.is_continuing());
+
+        assert_eq!(None, input.feed("println(max(1,"));
+        assert_eq!(Some(String::from("println(max(1,\n2))")), input.feed("2))"));
+
+        // Complete statements and errors that more input can't fix run right away
+        assert_eq!(Some(String::from("let x = 1")), input.feed("let x = 1"));
+        assert_eq!(Some(String::from("let = 2")), input.feed("let = 2"));
+        assert_eq!(Some(String::from("}")), input.feed("}"));
+    }
+}
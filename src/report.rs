@@ -0,0 +1,114 @@
+// Renders an error under the line of source it happened on, with a caret pointing at
+// the column:
+//
+//     Unexpected character '@'
+//     3 |     let x = @
+//       |             ^
+//
+// Columns count characters, starting at 1. Terminals disagree on how wide a tab is, so
+// tabs are expanded to spaces before printing. Wide characters (like CJK) take up two
+// cells, so the caret is moved over twice for them and is drawn two cells wide under one.
+
+const TAB_WIDTH: usize = 4;
+
+
+pub fn caret_report(source_line: &str, line: u64, column: usize, message: &str) -> String {
+    let gutter = line.to_string();
+    let mut expanded = String::new();
+    let mut caret_start = None;
+    let mut caret_width = 1;
+
+    for (i, c) in source_line.chars().enumerate() {
+        let start = display_width(&expanded);
+        if c == '\t' {
+            expanded.push_str(&" ".repeat(TAB_WIDTH - start % TAB_WIDTH));
+        } else {
+            expanded.push(c);
+        }
+        if i + 1 == column {
+            caret_start = Some(start);
+            caret_width = display_width(&expanded) - start;
+        }
+    }
+    // Errors at the end of the line (like a missing quote) point just past the last character
+    let caret_start = caret_start.unwrap_or_else(|| display_width(&expanded));
+
+    format!("{}\n{} | {}\n{} | {}{}", message, gutter, expanded.trim_end(), " ".repeat(gutter.len()),
+        " ".repeat(caret_start), "^".repeat(caret_width.max(1)))
+}
+
+fn display_width(text: &str) -> usize {
+    text.chars().map(char_width).sum()
+}
+
+// How many terminal cells a character takes up
+fn char_width(c: char) -> usize {
+    match c as u32 {
+        // Combining marks draw over the previous character
+        0x0300..=0x036F | 0x200B..=0x200F => 0,
+        0x1100..=0x115F
+        | 0x2E80..=0x303E
+        | 0x3041..=0x33FF
+        | 0x3400..=0x4DBF
+        | 0x4E00..=0x9FFF
+        | 0xA000..=0xA4CF
+        | 0xAC00..=0xD7A3
+        | 0xF900..=0xFAFF
+        | 0xFE30..=0xFE4F
+        | 0xFF00..=0xFF60
+        | 0xFFE0..=0xFFE6
+        | 0x1F300..=0x1F64F
+        | 0x1F900..=0x1F9FF
+        | 0x20000..=0x3FFFD => 2,
+        _ => 1,
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    // The column the caret is drawn at, counting terminal cells from the start of the source
+    fn caret_column(report: &str) -> usize {
+        let caret_line = report.lines().last().unwrap();
+        let source_start = caret_line.find('|').unwrap() + 2;
+        caret_line.find('^').unwrap() - source_start
+    }
+
+    #[test]
+    fn caret_under_plain_column() {
+        let report = caret_report("let x = @;", 3, 9, "Unexpected character '@'");
+        assert_eq!("Unexpected character '@'\n3 | let x = @;\n  |         ^", report);
+    }
+
+    #[test]
+    fn tabs_are_expanded() {
+        let report = caret_report("\tlet x = @;", 12, 10, "Unexpected character '@'");
+        assert_eq!("Unexpected character '@'\n12 |     let x = @;\n   |             ^", report);
+
+        // A tab after text only moves to the next tab stop
+        let report = caret_report("ab\t@", 1, 4, "");
+        assert_eq!(4, caret_column(&report));
+        assert!(report.contains("1 | ab  @"));
+    }
+
+    #[test]
+    fn wide_characters_take_two_cells() {
+        let report = caret_report("let 名前 = @;", 1, 10, "Unexpected character '@'");
+        assert_eq!(11, caret_column(&report));
+
+        // A caret under a wide character covers both of its cells
+        let report = caret_report("\"名\" @", 1, 2, "");
+        assert_eq!(1, caret_column(&report));
+        assert!(report.ends_with(" ^^"));
+    }
+
+    #[test]
+    fn caret_past_the_end() {
+        let report = caret_report("let s = \"abc", 1, 13, "Unterminated string");
+        assert_eq!(12, caret_column(&report));
+    }
+}
@@ -0,0 +1,329 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::{Expr, Stmt, Function, Depth, Resolution};
+use crate::environment;
+use crate::errors::RuntimeError;
+use crate::lexer::Token;
+
+// Works out where every variable lives before a program runs. Each use of a variable
+// is marked with how many scopes out its declaration is, so the interpreter can go
+// straight to that scope instead of searching for the name. Functions see the variables
+// of the scopes they were declared in, not of the code calling them.
+//
+// Names that are never declared, and names used before their declaration has run,
+// are reported here instead of failing halfway through running the program.
+//
+// The scopes pushed here match the ones the interpreter makes: one per block, one for
+// a function's parameters and one for a catch's error name.
+//
+// An import can declare any name in the scope it is in. In a scope with an import, names
+// that aren't found, and names that could be hidden by an imported one, are left
+// Unresolved and looked up by name when used.
+
+
+pub fn resolve(statements: &[Stmt], globals: Vec<String>) -> Result<(), RuntimeError> {
+    let mut resolver = Resolver {
+        scopes: Vec::new(),
+        imported: Vec::new(),
+        functions: Vec::new(),
+        globals: globals.into_iter().collect(),
+        declared: statements.iter().flat_map(declared_names).map(|name| name.lexeme.clone()).collect(),
+        imports: has_import(statements),
+    };
+    resolver.statements(statements)
+}
+
+struct Resolver {
+    // The local scopes, innermost last. Names map to whether their declaration has run yet
+    scopes: Vec<HashMap<String, bool>>,
+    // Whether each scope has an import, one per scope
+    imported: Vec<bool>,
+    // How many scopes were open when each function being resolved was declared
+    functions: Vec<usize>,
+    // The globals declared so far, builtins included
+    globals: HashSet<String>,
+    // Every global the program declares. Functions can use them before they are declared
+    declared: HashSet<String>,
+    // Whether the top level has an import
+    imports: bool,
+}
+
+impl Resolver {
+    fn statements(&mut self, statements: &[Stmt]) -> Result<(), RuntimeError> {
+        statements.iter().try_for_each(|statement| self.statement(statement))
+    }
+
+    fn statement(&mut self, statement: &Stmt) -> Result<(), RuntimeError> {
+        match statement {
+            Stmt::ExprStmt(expr) => self.expression(expr),
+            Stmt::VarDecl(name, initializer) => {
+                // The initializer runs first, so 'let x = x + 1;' can use an outer x
+                if let Some(expr) = initializer {
+                    self.expression(expr)?;
+                }
+                self.declare(name);
+                Ok(())
+            },
+            Stmt::ConstDecl(name, initializer) => {
+                self.expression(initializer)?;
+                self.declare(name);
+                Ok(())
+            },
+            Stmt::Destructure(names, _, initializer) => {
+                self.expression(initializer)?;
+                names.iter().for_each(|name| self.declare(name));
+                Ok(())
+            },
+            Stmt::Block(statements) => self.scoped(statements),
+            Stmt::IfStmt(if_stmt) => {
+                self.expression(&if_stmt.conditional)?;
+                self.statement(&if_stmt.then_block)?;
+                if_stmt.else_block.as_ref().map_or(Ok(()), |block| self.statement(block))
+            },
+            Stmt::WhileStmt(_, cond, body) => {
+                self.expression(cond)?;
+                self.statement(body)
+            },
+            Stmt::FuncStmt(func) => {
+                // Declared before its body is resolved so it can call itself
+                self.declare(&func.name);
+                self.function(func)
+            },
+            Stmt::ReturnStmt(ret) => ret.expr.as_ref().map_or(Ok(()), |expr| self.expression(expr)),
+            Stmt::Import(_) => Ok(()),
+            Stmt::TryStmt(stmt) => {
+                self.statement(&stmt.try_block)?;
+                self.push_scope(HashMap::new(), false);
+                self.declare(&stmt.name);
+                let result = self.statement(&stmt.catch_block);
+                self.pop_scope();
+                result
+            },
+            Stmt::Throw(_, expr) => self.expression(expr),
+            Stmt::Defer(_, stmt) => self.statement(stmt),
+            Stmt::Break => Ok(()),
+        }
+    }
+
+    // Runs the statements in a new scope. Names declared anywhere in it are known up
+    // front so using one too early can be reported as such
+    fn scoped(&mut self, statements: &[Stmt]) -> Result<(), RuntimeError> {
+        let scope = statements.iter().flat_map(declared_names).map(|name| (name.lexeme.clone(), false)).collect();
+        self.push_scope(scope, has_import(statements));
+        let result = self.statements(statements);
+        self.pop_scope();
+        result
+    }
+
+    fn push_scope(&mut self, scope: HashMap<String, bool>, imports: bool) {
+        self.scopes.push(scope);
+        self.imported.push(imports);
+    }
+
+    fn pop_scope(&mut self) {
+        self.scopes.pop();
+        self.imported.pop();
+    }
+
+    fn function(&mut self, func: &Function) -> Result<(), RuntimeError> {
+        self.functions.push(self.scopes.len());
+        self.push_scope(func.params.iter().map(|param| (param.lexeme.clone(), true)).collect(), false);
+        let result = self.statement(&func.body);
+        self.pop_scope();
+        self.functions.pop();
+        result
+    }
+
+    fn declare(&mut self, name: &Token) {
+        match self.scopes.last_mut() {
+            Some(scope) => {scope.insert(name.lexeme.clone(), true);},
+            None => {self.globals.insert(name.lexeme.clone());},
+        }
+    }
+
+    fn expression(&mut self, expr: &Expr) -> Result<(), RuntimeError> {
+        match expr {
+            Expr::L(_) => Ok(()),
+            Expr::V(name, depth) => self.variable(name, depth),
+            Expr::A(name, value, depth) => {
+                self.expression(value)?;
+                self.variable(name, depth)
+            },
+            Expr::U(unary) => self.expression(&unary.expr),
+            Expr::B(binary) => {
+                self.expression(&binary.left)?;
+                self.expression(&binary.right)
+            },
+            Expr::Log(logical) => {
+                self.expression(&logical.left)?;
+                self.expression(&logical.right)
+            },
+            Expr::G(grouping) => self.expression(&grouping.expr),
+            Expr::C(cond) => {
+                self.expression(&cond.cond)?;
+                self.expression(&cond.then_expr)?;
+                self.expression(&cond.else_expr)
+            },
+            Expr::Cal(call) => {
+                self.expression(&call.callee)?;
+                call.args.iter().try_for_each(|arg| self.expression(arg))
+            },
+            Expr::Method(method) => {
+                self.expression(&method.receiver)?;
+                method.args.iter().try_for_each(|arg| self.expression(arg))
+            },
+            Expr::Arr(arr) => arr.elements.iter().try_for_each(|element| self.expression(element)),
+            Expr::Map(map) => map.entries.iter().try_for_each(|(key, value)| {
+                self.expression(key)?;
+                self.expression(value)
+            }),
+            Expr::Lambda(func) => self.function(func),
+            Expr::Block(statements) => self.scoped(statements),
+            Expr::Spread(_, expr) => self.expression(expr),
+            Expr::Match(m) => {
+                self.expression(&m.value)?;
+                m.arms.iter().try_for_each(|(_, value)| self.expression(value))
+            },
+        }
+    }
+
+    fn variable(&mut self, name: &Token, depth: &Depth) -> Result<(), RuntimeError> {
+        // Scopes opened before the innermost function was declared. Their names will
+        // have been declared by the time the function is called
+        let outside = self.functions.last().copied().unwrap_or(0);
+        // The innermost scope with an import. Names from outside it could be hidden by an imported one
+        let import = self.imported.iter().rposition(|imports| *imports);
+        let mut too_early = false;
+        for (i, scope) in self.scopes.iter().enumerate().rev() {
+            if import.is_some_and(|import| i < import) {
+                return Ok(());
+            }
+            match scope.get(&name.lexeme) {
+                Some(declared) if *declared || i < outside => {
+                    depth.0.set(Resolution::Local(self.scopes.len() - 1 - i));
+                    return Ok(());
+                },
+                Some(_) => too_early = true,
+                None => {},
+            }
+        }
+
+        let in_function = !self.functions.is_empty();
+        if import.is_some() {
+            Ok(())
+        } else if self.globals.contains(&name.lexeme) || (in_function && self.declared.contains(&name.lexeme)) {
+            depth.0.set(Resolution::Global);
+            Ok(())
+        } else if self.imports {
+            Ok(())
+        } else if too_early || self.declared.contains(&name.lexeme) {
+            Err(RuntimeError::string_error(name, format!("Cannot use '{}' before it is declared", name.lexeme)))
+        } else {
+            Err(RuntimeError::string_error(name, format!("Undefined Identifier: {}{}", name.lexeme, environment::suggestion(&name.lexeme, self.visible_names()))))
+        }
+    }
+
+    fn visible_names(&self) -> Vec<String> {
+        let mut globals: Vec<String> = self.globals.iter().cloned().collect();
+        globals.sort();
+        self.scopes.iter().rev().flat_map(|scope| scope.keys().cloned()).chain(globals).collect()
+    }
+}
+
+// An imported file can declare anything, so names that aren't found are looked up when used
+fn has_import(statements: &[Stmt]) -> bool {
+    statements.iter().any(|statement| matches!(statement, Stmt::Import(_)))
+}
+
+// The names a statement declares in the scope it's in
+fn declared_names(statement: &Stmt) -> Vec<&Token> {
+    match statement {
+        Stmt::VarDecl(name, _) | Stmt::ConstDecl(name, _) => vec![name],
+        Stmt::Destructure(names, _, _) => names.iter().collect(),
+        Stmt::FuncStmt(func) => vec![&func.name],
+        _ => Vec::new(),
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::interpreter::{Interpreter, Value};
+    use crate::environment::Environment;
+    use crate::lexer::{lex_source, TokenType};
+    use crate::parser::Parser;
+
+    fn parse(source: &str) -> Vec<Stmt> {
+        Parser::new(lex_source(source).unwrap()).parse().unwrap()
+    }
+
+    fn resolve_error(source: &str) -> String {
+        resolve(&parse(source), Interpreter::new().globals.names()).unwrap_err().message()
+    }
+
+    #[test]
+    fn variables_are_marked_with_their_depth() {
+        let statements = parse("let a = 1;\n{\n    let b = 2;\n    {\n        a + b;\n    }\n}");
+        resolve(&statements, Vec::new()).unwrap();
+        let sum = match &statements[1] {
+            Stmt::Block(outer) => match &outer[1] {
+                Stmt::Block(inner) => match &inner[0] {
+                    Stmt::ExprStmt(Expr::B(sum)) => sum.clone(),
+                    other => panic!("expected a sum, got {:?}", other),
+                },
+                other => panic!("expected a block, got {:?}", other),
+            },
+            other => panic!("expected a block, got {:?}", other),
+        };
+        match (&sum.left, &sum.right) {
+            (Expr::V(_, a), Expr::V(_, b)) => {
+                assert_eq!(Resolution::Global, a.0.get());
+                assert_eq!(Resolution::Local(1), b.0.get());
+            },
+            other => panic!("expected two variables, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn use_before_declare_is_an_error() {
+        assert_eq!("Cannot use 'x' before it is declared", resolve_error("println(x);\nlet x = 1;"));
+        assert_eq!("Cannot use 'z' before it is declared", resolve_error("func f() {\n    let y = z + 1;\n    let z = 2;\n    return y;\n}"));
+        assert_eq!("Undefined Identifier: lenght, did you mean 'length'?", resolve_error("let length = 3;\nlenght + 1;"));
+        assert_eq!("Undefined Identifier: missing", resolve_error("func f() { missing = 1; }"));
+        // Functions can use globals declared after them, they only run once called
+        assert!(resolve(&parse("func f() { return g(); }\nfunc g() { return 1; }\nf();"), Vec::new()).is_ok());
+    }
+
+    #[test]
+    fn closures_capture_their_declaring_scope() {
+        let source = "let x = \"global\";
+func show() { return x; }
+func caller() {
+    let x = \"local\";
+    return show();
+}
+func counter() {
+    let count = 0;
+    return func() {
+        count += 1;
+        return count;
+    };
+}
+let shown = caller();
+let next = counter();
+next();
+let counted = next();";
+        let mut interpreter = Interpreter::new();
+        interpreter.interpret(parse(source)).unwrap();
+        let mut globals = interpreter.globals.clone();
+        let get = |globals: &mut Environment, name: &str| {
+            globals.get(&Token::new(TokenType::Identifier, name.to_string(), 1)).unwrap()
+        };
+        // show sees the x it was declared next to, not the one in the function calling it
+        assert_eq!(Value::STRING("global".to_string()), get(&mut globals, "shown"));
+        assert_eq!(Value::INT(2), get(&mut globals, "counted"));
+    }
+}
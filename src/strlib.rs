@@ -1,4 +1,5 @@
 use std::fmt;
+use std::convert::TryFrom;
 use crate::errors::RuntimeError;
 use crate::callable::Callable;
 use crate::interpreter::{Value, Interpreter};
@@ -10,6 +11,15 @@ pub enum StrLib {
     Len,
     CharAt,
     SubStr,
+    EqIgnoreCase,
+    Upper,
+    Lower,
+    Trim,
+    Chr,
+    Ord,
+    IsNumber,
+    IsDigit,
+    IsAlpha,
 }
 
 
@@ -19,6 +29,15 @@ impl Callable for StrLib {
             StrLib::Len => len(&args[0]),
             StrLib::CharAt => char_at((&args[0], &args[1])),
             StrLib::SubStr => sub_str((&args[0], &args[1], &args[2])),
+            StrLib::EqIgnoreCase => eq_ignore_case((&args[0], &args[1])),
+            StrLib::Upper => map_string("upper", &args[0], str::to_uppercase),
+            StrLib::Lower => map_string("lower", &args[0], str::to_lowercase),
+            StrLib::Trim => map_string("trim", &args[0], |s| s.trim().to_string()),
+            StrLib::Chr => chr(&args[0]),
+            StrLib::Ord => ord(&args[0]),
+            StrLib::IsNumber => check_string("is_number", &args[0], is_number),
+            StrLib::IsDigit => check_string("is_digit", &args[0], |s| s.chars().all(char::is_numeric)),
+            StrLib::IsAlpha => check_string("is_alpha", &args[0], |s| s.chars().all(char::is_alphabetic)),
         }
     }
 
@@ -27,6 +46,10 @@ impl Callable for StrLib {
             StrLib::Len => 1,
             StrLib::CharAt => 2,
             StrLib::SubStr => 3,
+            StrLib::EqIgnoreCase => 2,
+            StrLib::Upper | StrLib::Lower | StrLib::Trim => 1,
+            StrLib::Chr | StrLib::Ord => 1,
+            StrLib::IsNumber | StrLib::IsDigit | StrLib::IsAlpha => 1,
         }
     }
 }
@@ -34,15 +57,15 @@ impl Callable for StrLib {
 
 fn len(val: &Value) -> Result<Value, RuntimeError> {
     if let Value::STRING(word) = val {
-        return Ok(Value::NUMBER(word.len() as f64))
+        return Ok(Value::INT(word.len() as i64))
     }
     Err(RuntimeError::no_token_error("len", "Expected String".to_string(), 1000))
 }
 
 fn char_at(args: (&Value, &Value)) -> Result<Value, RuntimeError> {
-    match args {
-        (Value::NUMBER(i), Value::STRING(s)) => {
-            let c = s.chars().nth(*i as usize).unwrap();
+    match (index(args.0), args.1) {
+        (Some(i), Value::STRING(s)) => {
+            let c = s.chars().nth(i).unwrap();
             Ok(Value::STRING(c.to_string()))
         },
         _ => Err(RuntimeError::no_token_error("len", "charAt expects Number, String".to_string(), 1000))
@@ -50,9 +73,9 @@ fn char_at(args: (&Value, &Value)) -> Result<Value, RuntimeError> {
 }
 
 fn sub_str(args: (&Value, &Value, &Value)) -> Result<Value, RuntimeError> {
-    match args {
-        (Value::NUMBER(start), Value::NUMBER(end),  Value::STRING(s)) => {
-            let slice = &s[*start as usize..*end as usize];
+    match (index(args.0), index(args.1), args.2) {
+        (Some(start), Some(end), Value::STRING(s)) => {
+            let slice = &s[start..end];
             Ok(Value::STRING(String::from(slice)))
         }
         _ => Err(RuntimeError::no_token_error("len", "charAt expects Number, String".to_string(), 1000))
@@ -60,13 +83,93 @@ fn sub_str(args: (&Value, &Value, &Value)) -> Result<Value, RuntimeError> {
 
 }
 
+// Compares after lowercasing, which also handles non ASCII letters
+fn eq_ignore_case(args: (&Value, &Value)) -> Result<Value, RuntimeError> {
+    match args {
+        (Value::STRING(s), Value::STRING(s2)) => Ok(Value::BOOL(s.to_lowercase() == s2.to_lowercase())),
+        _ => Err(RuntimeError::no_token_error("eq_ignore_case", format!("eq_ignore_case expects String, String, given: {}, {}", args.0, args.1), 1000))
+    }
+}
 
-impl fmt::Debug for StrLib {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+// Applies a string to string function, for builtins like upper and trim
+fn map_string(name: &str, val: &Value, f: impl Fn(&str) -> String) -> Result<Value, RuntimeError> {
+    match val {
+        Value::STRING(s) => Ok(Value::STRING(f(s))),
+        _ => Err(RuntimeError::no_token_error(name, format!("{} expects String, given: {}", name, val), 1000)),
+    }
+}
+
+// The character with the given Unicode code point
+fn chr(val: &Value) -> Result<Value, RuntimeError> {
+    let code = match val {
+        Value::INT(n) => Some(*n),
+        Value::NUMBER(n) if n.fract() == 0.0 => Some(*n as i64),
+        _ => None,
+    };
+    match code.and_then(|n| u32::try_from(n).ok()).and_then(char::from_u32) {
+        Some(c) => Ok(Value::STRING(c.to_string())),
+        None => Err(RuntimeError::no_token_error("chr", format!("chr expects a Unicode code point, given: {}", val), 1000)),
+    }
+}
+
+// The code point of a string's first character
+fn ord(val: &Value) -> Result<Value, RuntimeError> {
+    match val {
+        Value::STRING(s) => match s.chars().next() {
+            Some(c) => Ok(Value::INT(c as i64)),
+            None => Err(RuntimeError::no_token_error("ord", String::from("ord expects a non-empty String"), 1000)),
+        },
+        _ => Err(RuntimeError::no_token_error("ord", format!("ord expects String, given: {}", val), 1000)),
+    }
+}
+
+// Checks a property of a non-empty string, for builtins like is_alpha. The empty string has none
+fn check_string(name: &str, val: &Value, f: impl Fn(&str) -> bool) -> Result<Value, RuntimeError> {
+    match val {
+        Value::STRING(s) => Ok(Value::BOOL(!s.is_empty() && f(s))),
+        _ => Err(RuntimeError::no_token_error(name, format!("{} expects String, given: {}", name, val), 1000)),
+    }
+}
+
+// Whether the string is written like a number: an optional sign, digits with an optional
+// fraction, and an optional exponent. Words like "inf" and "NaN" aren't numbers
+fn is_number(s: &str) -> bool {
+    let written_as_number = s.chars().all(|c| c.is_ascii_digit() || matches!(c, '.' | '-' | '+' | 'e' | 'E'));
+    written_as_number && s.chars().any(|c| c.is_ascii_digit()) && s.parse::<f64>().is_ok()
+}
+
+// Indexes may be given as either kind of number
+fn index(val: &Value) -> Option<usize> {
+    match val {
+        Value::INT(i) if *i >= 0 => Some(*i as usize),
+        Value::NUMBER(n) if *n >= 0.0 => Some(*n as usize),
+        _ => None,
+    }
+}
+
+
+impl StrLib {
+    // The name the builtin is bound to in the globals
+    pub fn name(&self) -> &'static str {
         match self {
-            StrLib::Len => write!(f, "<fn len>", ),
-            StrLib::CharAt => write!(f, "<fn charAt>"),
-            StrLib::SubStr => write!(f, "<fn subStr>"),
+            StrLib::Len => "len",
+            StrLib::CharAt => "charAt",
+            StrLib::SubStr => "subString",
+            StrLib::EqIgnoreCase => "eq_ignore_case",
+            StrLib::Upper => "upper",
+            StrLib::Lower => "lower",
+            StrLib::Trim => "trim",
+            StrLib::Chr => "chr",
+            StrLib::Ord => "ord",
+            StrLib::IsNumber => "is_number",
+            StrLib::IsDigit => "is_digit",
+            StrLib::IsAlpha => "is_alpha",
         }
     }
-}
\ No newline at end of file
+}
+
+impl fmt::Debug for StrLib {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<fn {}>", self.name())
+    }
+}